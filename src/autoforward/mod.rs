@@ -0,0 +1,3 @@
+pub mod watcher;
+
+pub use watcher::*;
@@ -0,0 +1,216 @@
+use crate::config::schema::ResolvedConfig;
+use crate::integration::event_bus::EventBus;
+use crate::qemu::argv::{hostfwd_rule, USER_NETDEV_ID};
+use crate::qemu::monitor::{MonitorError, QmpClient};
+use crate::ssh::{guest_listening_ports, pin_host_keys};
+use crate::state::{load_state, save_state, ActiveForward, StateError, VmState};
+use crate::state::lock::Lock;
+use crate::util::net::{is_binding_available, reserve_port, NetError, PortBinding, Protocol};
+use crate::util::process::is_process_running;
+use serde_json::json;
+use std::collections::BTreeSet;
+use std::net::Ipv4Addr;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Emitted with `{ "port", "host_port", "proto" }` when a guest port is forwarded.
+pub const PORT_OPENED: &str = "port_opened";
+/// Emitted with the same payload when the guest stops listening.
+pub const PORT_CLOSED: &str = "port_closed";
+
+/// Guest SSH port, which always has its own forward.
+const GUEST_SSH_PORT: u16 = 22;
+
+#[derive(Error, Debug)]
+pub enum AutoForwardError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("State error: {0}")]
+    State(#[from] StateError),
+    #[error("Monitor error: {0}")]
+    Monitor(#[from] MonitorError),
+    #[error("Network error: {0}")]
+    Net(#[from] NetError),
+    #[error("VM was started without a monitor; restart it to auto-forward ports")]
+    NoMonitor,
+}
+
+/// Watches the guest for listening ports and keeps matching host forwards.
+pub struct AutoForwarder {
+    config: ResolvedConfig,
+    state_path: PathBuf,
+    lock_path: PathBuf,
+    event_bus: Arc<EventBus>,
+}
+
+impl AutoForwarder {
+    pub fn new(config: ResolvedConfig, root: &Path, event_bus: Arc<EventBus>) -> Self {
+        Self {
+            config,
+            state_path: root.join("config").join("state.json"),
+            lock_path: root.join("config").join("portaqemu.lock"),
+            event_bus,
+        }
+    }
+
+    /// Poll until the VM stops or Ctrl-C, then drop the auto forwards.
+    pub async fn run(&self) -> Result<(), AutoForwardError> {
+        let interval = Duration::from_secs(self.config.network.auto_forward.interval_secs.max(1));
+        loop {
+            match self.poll().await {
+                Ok(true) => {}
+                Ok(false) => return Ok(()),
+                Err(AutoForwardError::NoMonitor) => return Err(AutoForwardError::NoMonitor),
+                Err(e) => tracing::warn!("Auto-forward: {}", e),
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(interval) => {}
+                _ = tokio::signal::ctrl_c() => break,
+            }
+        }
+        self.cleanup().await
+    }
+
+    /// One detection pass. Returns false once the VM is no longer running.
+    pub async fn poll(&self) -> Result<bool, AutoForwardError> {
+        let state = load_state(&self.state_path)?;
+        if !vm_alive(&state) {
+            return Ok(false);
+        }
+        if state.monitor.is_none() {
+            return Err(AutoForwardError::NoMonitor);
+        }
+
+        // The guest may still be booting; try again next round
+        let listening = match pin_host_keys(&self.config)
+            .map_err(|e| e.to_string())
+            .and_then(|_| guest_listening_ports(&self.config).map_err(|e| e.to_string()))
+        {
+            Ok(ports) => ports,
+            Err(e) => {
+                tracing::debug!("Guest ports unavailable: {}", e);
+                return Ok(true);
+            }
+        };
+        let wanted = self.wanted_ports(&state, &listening);
+        let current: BTreeSet<u16> = auto_forwards(&state).map(|f| f.guest).collect();
+        if wanted == current {
+            return Ok(true);
+        }
+
+        // Another command holds the lock; retry next round
+        let Ok(_lock) = Lock::try_acquire(&self.lock_path) else {
+            return Ok(true);
+        };
+        let mut state = load_state(&self.state_path)?;
+        let monitor = state.monitor.clone().ok_or(AutoForwardError::NoMonitor)?;
+        let mut qmp = QmpClient::connect(&monitor)?;
+        let mut events = Vec::new();
+
+        let closed: Vec<ActiveForward> = auto_forwards(&state)
+            .filter(|f| !wanted.contains(&f.guest))
+            .cloned()
+            .collect();
+        for forward in closed {
+            qmp.hostfwd_remove(USER_NETDEV_ID, &format!("{}:{}:{}", forward.proto, forward.bind, forward.host))?;
+            state.forwards.retain(|f| f != &forward);
+            events.push((PORT_CLOSED, forward));
+        }
+
+        let current: BTreeSet<u16> = auto_forwards(&state).map(|f| f.guest).collect();
+        for &guest in wanted.difference(&current) {
+            let host = pick_host_port(guest, &state, self.config.network.ssh_host_port)?;
+            let forward = ActiveForward {
+                proto: Protocol::Tcp,
+                bind: Ipv4Addr::LOCALHOST,
+                host,
+                guest_addr: None,
+                guest,
+                name: None,
+                runtime: false,
+                auto: true,
+            };
+            qmp.hostfwd_add(
+                USER_NETDEV_ID,
+                &hostfwd_rule(forward.proto, forward.bind, forward.host, None, forward.guest),
+            )?;
+            state.forwards.push(forward.clone());
+            events.push((PORT_OPENED, forward));
+        }
+        save_state(&self.state_path, &state)?;
+
+        for (event, forward) in events {
+            self.event_bus.emit(event, json!({
+                "port": forward.guest,
+                "host_port": forward.host,
+                "proto": forward.proto,
+            })).await;
+        }
+        Ok(true)
+    }
+
+    /// Remove all auto forwards from the VM and state.
+    pub async fn cleanup(&self) -> Result<(), AutoForwardError> {
+        let _lock = Lock::try_acquire(&self.lock_path).ok();
+        let mut state = load_state(&self.state_path)?;
+        let forwards: Vec<ActiveForward> = auto_forwards(&state).cloned().collect();
+        if forwards.is_empty() {
+            return Ok(());
+        }
+
+        if let (true, Some(monitor)) = (vm_alive(&state), state.monitor.clone()) {
+            let mut qmp = QmpClient::connect(&monitor)?;
+            for forward in &forwards {
+                qmp.hostfwd_remove(USER_NETDEV_ID, &format!("{}:{}:{}", forward.proto, forward.bind, forward.host))?;
+            }
+        }
+        state.forwards.retain(|f| !f.auto);
+        save_state(&self.state_path, &state)?;
+
+        for forward in forwards {
+            self.event_bus.emit(PORT_CLOSED, json!({
+                "port": forward.guest,
+                "host_port": forward.host,
+                "proto": forward.proto,
+            })).await;
+        }
+        Ok(())
+    }
+
+    /// Listening guest ports that should be forwarded.
+    fn wanted_ports(&self, state: &VmState, listening: &BTreeSet<u16>) -> BTreeSet<u16> {
+        // Ports already covered by config or manual forwards
+        let covered: BTreeSet<u16> = state.forwards
+            .iter()
+            .filter(|f| !f.auto && f.proto == Protocol::Tcp)
+            .map(|f| f.guest)
+            .collect();
+        listening
+            .iter()
+            .copied()
+            .filter(|&port| port != GUEST_SSH_PORT && !covered.contains(&port))
+            .filter(|&port| self.config.network.auto_forward.permits(port))
+            .collect()
+    }
+}
+
+fn vm_alive(state: &VmState) -> bool {
+    state.running && state.qemu_pid.map(is_process_running).unwrap_or(false)
+}
+
+fn auto_forwards(state: &VmState) -> impl Iterator<Item = &ActiveForward> {
+    state.forwards.iter().filter(|f| f.auto)
+}
+
+/// Use the guest port on the host when free, otherwise any free port.
+fn pick_host_port(guest: u16, state: &VmState, ssh_host_port: u16) -> Result<u16, AutoForwardError> {
+    let taken = guest == ssh_host_port
+        || state.forwards.iter().any(|f| f.proto == Protocol::Tcp && f.host == guest);
+    let binding = PortBinding::tcp_loopback(guest);
+    if !taken && is_binding_available(&binding).unwrap_or(false) {
+        return Ok(guest);
+    }
+    Ok(reserve_port(Protocol::Tcp, Ipv4Addr::LOCALHOST.into())?.port)
+}
@@ -1,6 +1,6 @@
 use std::env;
 use std::fs;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -44,7 +44,7 @@ pub fn get_autostart_filename(vm_name: &str) -> String {
 }
 
 /// Enable autostart for a VM.
-pub fn enable_autostart(vm_name: &str, root: &PathBuf) -> Result<PathBuf, AutostartError> {
+pub fn enable_autostart(vm_name: &str, root: &Path) -> Result<PathBuf, AutostartError> {
     let startup_folder = get_startup_folder()?;
     let script_name = get_autostart_filename(vm_name);
     let script_path = startup_folder.join(&script_name);
@@ -0,0 +1,55 @@
+use crate::cli::AppContext;
+use crate::config::load::load_config;
+use crate::config::validate::validate_caps;
+use crate::config::paths::get_monitor_socket;
+use crate::qemu::{locate_qemu, detect_caps, accels_from_caps, choose_accel, launch_argv, assign_host_ports};
+use crate::output::OutputMode;
+
+pub fn handle_argv(ctx: &AppContext, print: bool) -> Result<i32, anyhow::Error> {
+    // While the VM runs, "auto" ports are the ones recorded at startup
+    let mut config = load_config(&ctx.config_path, &ctx.root)?;
+    let picked = config.network.has_auto_ports();
+    assign_host_ports(&mut config)?;
+    let qemu_path = locate_qemu(&ctx.root, &config.qemu)?;
+    let caps = detect_caps(&qemu_path, &ctx.root.join("config").join("qemu-caps.json"))?;
+    validate_caps(&config, &caps)?;
+    let accel = choose_accel(config.accel.preferred, &accels_from_caps(&caps))?;
+    let argv = launch_argv(&config, &caps, accel, &[], &get_monitor_socket(&ctx.root))?;
+    
+    match ctx.output_mode {
+        OutputMode::Json => {
+            use serde_json::json;
+            let args: Vec<_> = argv.iter().map(|a| a.to_string_lossy()).collect();
+            println!("{}", serde_json::to_string_pretty(&json!({
+                "qemu": qemu_path.to_string_lossy(),
+                "argv": args,
+            }))?);
+        }
+        OutputMode::Human => {
+            if picked {
+                eprintln!("Note: \"auto\" ports were picked for this listing; `up` picks its own");
+            }
+            if print {
+                let mut line = vec![quote_arg(&qemu_path.to_string_lossy())];
+                line.extend(argv.iter().map(|a| quote_arg(&a.to_string_lossy())));
+                println!("{}", line.join(" "));
+            } else {
+                println!("{}", qemu_path.to_string_lossy());
+                for arg in &argv {
+                    println!("  {}", arg.to_string_lossy());
+                }
+            }
+        }
+    }
+    
+    Ok(0)
+}
+
+/// Quote an argument for display on a command line.
+pub(crate) fn quote_arg(arg: &str) -> String {
+    if arg.is_empty() || arg.contains(|c: char| c.is_whitespace() || c == '"') {
+        format!("\"{}\"", arg.replace('"', "\\\""))
+    } else {
+        arg.to_string()
+    }
+}
@@ -0,0 +1,33 @@
+use crate::cli::AppContext;
+use crate::cli::commands::up::{attach_console, handle_up};
+use crate::config::paths::get_console_token;
+use crate::console::{ConsoleEndpoint, ConsoleHub};
+use crate::state::load_state;
+use crate::util::process::is_process_running;
+use std::path::PathBuf;
+
+pub fn handle_console(ctx: &AppContext, read_only: bool, start: bool) -> Result<i32, anyhow::Error> {
+    let state_path = ctx.root.join("config").join("state.json");
+    let mut state = load_state(&state_path)?;
+    let running = state.running && state.qemu_pid.is_some_and(is_process_running);
+    
+    if !running {
+        if !start {
+            anyhow::bail!("VM is not running (start it with: portaqemu up, or use console --start)");
+        }
+        // Boot output is only in the log until a viewer attaches
+        handle_up(ctx, false, true)?;
+        state = load_state(&state_path)?;
+    }
+    
+    attach_console(&ctx.root, &state, read_only)?;
+    Ok(0)
+}
+
+pub fn handle_console_hub(ctx: &AppContext, chardev: PathBuf, listen: ConsoleEndpoint) -> Result<i32, anyhow::Error> {
+    let token = std::fs::read_to_string(get_console_token(&ctx.root))?;
+    let hub = ConsoleHub::new(chardev, listen, ctx.root.join("logs").join("serial.log"), token.trim());
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    runtime.block_on(hub.run())?;
+    Ok(0)
+}
@@ -0,0 +1,477 @@
+use crate::cli::AppContext;
+use crate::cli::commands::ssh::vm_running;
+use crate::config::load::load_config;
+use crate::config::paths::get_integration_relay_file;
+use crate::config::schema::{GuestOs, ResolvedConfig};
+use crate::integration::{connect_relay, Endpoint, GuestFiles, Secret};
+use crate::output::OutputMode;
+use crate::qemu::qga::QgaClient;
+use crate::ssh::{copy_command, pin_host_keys, probe_banner, CopyEndpoint};
+use std::fs::{self, File};
+use std::io::{BufWriter, IsTerminal, Read, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
+
+/// How long to try the SSH port before giving up.
+const SSH_PROBE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// How long a listing or mkdir in the guest may take when copying through the agent.
+const GUEST_COMMAND_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Bytes per `fs.write` call over the integration channel.
+const CHANNEL_CHUNK: usize = 256 * 1024;
+
+/// Least time between redraws of the progress line.
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+pub fn handle_cp(ctx: &AppContext, src: &str, dst: &str, recursive: bool) -> Result<i32, anyhow::Error> {
+    let config = load_config(&ctx.config_path, &ctx.root)?;
+    let quiet = ctx.output_mode == OutputMode::Json;
+    let (src_endpoint, dst_endpoint) = (CopyEndpoint::parse(src), CopyEndpoint::parse(dst));
+    let mut cmd = copy_command(&config, &src_endpoint, &dst_endpoint, recursive, quiet)?;
+    
+    let state_path = ctx.root.join("config").join("state.json");
+    if !vm_running(&state_path)? {
+        anyhow::bail!("VM is not running (start it with: portaqemu up)");
+    }
+    // The integration channel and the guest agent need no guest network
+    if probe_banner(config.network.ssh_host_port, SSH_PROBE_TIMEOUT).is_err() {
+        let show_progress = !quiet && std::io::stderr().is_terminal();
+        let (transport, bytes) = copy_without_ssh(ctx, &config, &src_endpoint, &dst_endpoint, recursive, show_progress)?;
+        match ctx.output_mode {
+            OutputMode::Json => {
+                use serde_json::json;
+                println!("{}", serde_json::to_string_pretty(&json!({
+                    "source": src,
+                    "destination": dst,
+                    "recursive": recursive,
+                    "transport": transport.name(),
+                    "bytes": bytes,
+                    "exit_code": 0,
+                }))?);
+            }
+            OutputMode::Human => println!(
+                "Copied {} through the {} (SSH is not reachable)",
+                format_bytes(bytes),
+                transport.description()
+            ),
+        }
+        return Ok(0);
+    }
+    pin_host_keys(&config)?;
+    
+    let status = cmd.status()?;
+    let code = status.code().unwrap_or(255);
+    
+    if ctx.output_mode == OutputMode::Json {
+        use serde_json::json;
+        println!("{}", serde_json::to_string_pretty(&json!({
+            "source": src,
+            "destination": dst,
+            "recursive": recursive,
+            "transport": "ssh",
+            "exit_code": code,
+        }))?);
+    }
+    
+    Ok(code)
+}
+
+/// How a copy without SSH reached the guest.
+#[derive(Debug, Clone, Copy)]
+enum Transport {
+    Integration,
+    GuestAgent,
+}
+
+impl Transport {
+    fn name(self) -> &'static str {
+        match self {
+            Transport::Integration => "integration",
+            Transport::GuestAgent => "guest-agent",
+        }
+    }
+
+    fn description(self) -> &'static str {
+        match self {
+            Transport::Integration => "integration channel",
+            Transport::GuestAgent => "guest agent",
+        }
+    }
+}
+
+/// Copy over the integration channel when the guest offers its file functions,
+/// otherwise through the guest agent. Returns the transport and the bytes copied.
+fn copy_without_ssh(
+    ctx: &AppContext,
+    config: &ResolvedConfig,
+    src: &CopyEndpoint,
+    dst: &CopyEndpoint,
+    recursive: bool,
+    show_progress: bool,
+) -> Result<(Transport, u64), anyhow::Error> {
+    let guest_os = config.vm.guest_os;
+    if let Some(mut channel) = ChannelFs::connect(&ctx.root, config)? {
+        let bytes = copy_files(&mut channel, guest_os, src, dst, recursive, show_progress);
+        channel.close();
+        return Ok((Transport::Integration, bytes?));
+    }
+    if !config.guest_agent.enabled {
+        anyhow::bail!(
+            "SSH is not reachable on port {}, no integration guest offers file access, and the guest agent is disabled (guest_agent.enabled)",
+            config.network.ssh_host_port
+        );
+    }
+    let mut agent = AgentFs { client: QgaClient::connect(&config.guest_agent.socket)?, guest_os };
+    Ok((Transport::GuestAgent, copy_files(&mut agent, guest_os, src, dst, recursive, show_progress)?))
+}
+
+/// Guest file access for copies without SSH. Files are streamed in chunks.
+trait GuestFs {
+    /// Copy a guest file into `out`. Returns the bytes copied.
+    fn read(&mut self, path: &str, out: &mut dyn Write, progress: &mut Progress) -> Result<u64, anyhow::Error>;
+    /// Create or replace a guest file with what `input` yields. Returns the bytes copied.
+    fn write(&mut self, path: &str, input: &mut dyn Read, progress: &mut Progress) -> Result<u64, anyhow::Error>;
+    /// Paths of the files (or directories) below `dir`, relative to it.
+    fn list(&mut self, dir: &str, files: bool) -> Result<Vec<String>, anyhow::Error>;
+    /// Create `dir` and its parents.
+    fn mkdir(&mut self, dir: &str) -> Result<(), anyhow::Error>;
+}
+
+/// Copy between the host and the guest with `guest`. Directories are walked on
+/// the host and listed by the guest. Returns the bytes copied.
+fn copy_files(
+    guest: &mut dyn GuestFs,
+    guest_os: GuestOs,
+    src: &CopyEndpoint,
+    dst: &CopyEndpoint,
+    recursive: bool,
+    show_progress: bool,
+) -> Result<u64, anyhow::Error> {
+    match (src, dst) {
+        (CopyEndpoint::Host(src), CopyEndpoint::Guest(dst)) => {
+            let name = src.file_name().map(|n| n.to_string_lossy()).unwrap_or_default();
+            if dst.is_empty() {
+                anyhow::bail!("Copying without SSH needs an absolute guest path");
+            }
+            // Guest file access opens files, not directories: complete `vm:/tmp/` like scp would
+            let dst = if dst.ends_with(['/', '\\']) {
+                format!("{}{}", dst, name)
+            } else {
+                dst.clone()
+            };
+            if src.is_dir() {
+                if !recursive {
+                    anyhow::bail!("{} is a directory (use -r)", src.to_string_lossy());
+                }
+                return upload_dir(guest, guest_os, src, &dst, show_progress);
+            }
+            upload_file(guest, src, &dst, show_progress)
+        }
+        (CopyEndpoint::Guest(src), CopyEndpoint::Host(dst)) => {
+            let name = src.trim_end_matches(['/', '\\']).rsplit(['/', '\\']).next().unwrap_or(src);
+            let dst = if dst.is_dir() { dst.join(name) } else { dst.clone() };
+            if recursive {
+                return download_dir(guest, guest_os, src, &dst, show_progress);
+            }
+            download_file(guest, src, &dst, show_progress)
+        }
+        _ => anyhow::bail!("Copy between the host and the guest (prefix guest paths with vm:)"),
+    }
+}
+
+fn upload_file(guest: &mut dyn GuestFs, src: &Path, dst: &str, show_progress: bool) -> Result<u64, anyhow::Error> {
+    let mut file = File::open(src)?;
+    let mut progress = Progress::new(show_progress, &src.to_string_lossy(), Some(file.metadata()?.len()));
+    let bytes = guest.write(dst, &mut file, &mut progress)?;
+    progress.finish();
+    Ok(bytes)
+}
+
+fn download_file(guest: &mut dyn GuestFs, src: &str, dst: &Path, show_progress: bool) -> Result<u64, anyhow::Error> {
+    let mut file = BufWriter::new(File::create(dst)?);
+    let mut progress = Progress::new(show_progress, src, None);
+    let bytes = guest.read(src, &mut file, &mut progress)?;
+    file.flush()?;
+    progress.finish();
+    Ok(bytes)
+}
+
+fn upload_dir(guest: &mut dyn GuestFs, guest_os: GuestOs, src: &Path, dst: &str, show_progress: bool) -> Result<u64, anyhow::Error> {
+    guest.mkdir(dst)?;
+    let mut bytes = 0;
+    for entry in fs::read_dir(src)? {
+        let entry = entry?;
+        let target = guest_join(guest_os, dst, &entry.file_name().to_string_lossy());
+        if entry.file_type()?.is_dir() {
+            bytes += upload_dir(guest, guest_os, &entry.path(), &target, show_progress)?;
+        } else {
+            bytes += upload_file(guest, &entry.path(), &target, show_progress)?;
+        }
+    }
+    Ok(bytes)
+}
+
+fn download_dir(guest: &mut dyn GuestFs, guest_os: GuestOs, src: &str, dst: &Path, show_progress: bool) -> Result<u64, anyhow::Error> {
+    fs::create_dir_all(dst)?;
+    for dir in guest.list(src, false)? {
+        fs::create_dir_all(dst.join(host_relative(&dir)?))?;
+    }
+    let mut bytes = 0;
+    for file in guest.list(src, true)? {
+        bytes += download_file(guest, &guest_join(guest_os, src, &file), &dst.join(host_relative(&file)?), show_progress)?;
+    }
+    Ok(bytes)
+}
+
+/// Guest files through the integration host's relay.
+struct ChannelFs {
+    runtime: tokio::runtime::Runtime,
+    files: GuestFiles,
+}
+
+impl ChannelFs {
+    /// `None` unless the integration host runs and its guest offers file access.
+    fn connect(root: &Path, config: &ResolvedConfig) -> Result<Option<Self>, anyhow::Error> {
+        if !config.integration.enabled {
+            return Ok(None);
+        }
+        let Ok(endpoint) = fs::read_to_string(get_integration_relay_file(root)) else {
+            return Ok(None);
+        };
+        let endpoint: Endpoint = endpoint.trim().parse()?;
+        let secret = Secret::load(&config.integration.secret_file)?;
+        let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+        let files = runtime.block_on(async {
+            let relay = connect_relay(&endpoint, &secret).await?;
+            GuestFiles::new(relay).await
+        });
+        match files {
+            Ok(files) => Ok(files.map(|files| Self { runtime, files })),
+            Err(e) => {
+                tracing::debug!("Integration channel unavailable for cp: {}", e);
+                Ok(None)
+            }
+        }
+    }
+
+    fn close(self) {
+        self.runtime.block_on(self.files.close());
+    }
+}
+
+impl GuestFs for ChannelFs {
+    fn read(&mut self, path: &str, out: &mut dyn Write, progress: &mut Progress) -> Result<u64, anyhow::Error> {
+        let mut bytes = 0;
+        // Both callbacks update the progress line, so share it through a cell
+        let progress = std::cell::RefCell::new(progress);
+        Ok(self.runtime.block_on(self.files.read(
+            path,
+            |size| progress.borrow_mut().set_total(size),
+            |chunk| {
+                out.write_all(chunk)?;
+                bytes += chunk.len() as u64;
+                progress.borrow_mut().update(bytes);
+                Ok(())
+            },
+        ))?)
+    }
+
+    fn write(&mut self, path: &str, input: &mut dyn Read, progress: &mut Progress) -> Result<u64, anyhow::Error> {
+        let mut bytes = 0;
+        let mut chunk = Vec::with_capacity(CHANNEL_CHUNK);
+        loop {
+            chunk.clear();
+            input.take(CHANNEL_CHUNK as u64).read_to_end(&mut chunk)?;
+            // The first write creates the file, even an empty one
+            if chunk.is_empty() && bytes > 0 {
+                return Ok(bytes);
+            }
+            self.runtime.block_on(self.files.write(path, &chunk, bytes > 0))?;
+            if chunk.is_empty() {
+                return Ok(bytes);
+            }
+            bytes += chunk.len() as u64;
+            progress.update(bytes);
+        }
+    }
+
+    fn list(&mut self, dir: &str, files: bool) -> Result<Vec<String>, anyhow::Error> {
+        let entries = self.runtime.block_on(self.files.list(dir))?;
+        Ok(entries.into_iter().filter(|entry| entry.dir != files).map(|entry| entry.path).collect())
+    }
+
+    fn mkdir(&mut self, dir: &str) -> Result<(), anyhow::Error> {
+        Ok(self.runtime.block_on(self.files.mkdir(dir))?)
+    }
+}
+
+/// Guest files through qemu-guest-agent's file commands; directories are
+/// listed and created with the guest's own tools.
+struct AgentFs {
+    client: QgaClient,
+    guest_os: GuestOs,
+}
+
+impl GuestFs for AgentFs {
+    fn read(&mut self, path: &str, out: &mut dyn Write, progress: &mut Progress) -> Result<u64, anyhow::Error> {
+        Ok(self.client.read_file(path, out, |bytes| progress.update(bytes))?)
+    }
+
+    fn write(&mut self, path: &str, input: &mut dyn Read, progress: &mut Progress) -> Result<u64, anyhow::Error> {
+        Ok(self.client.write_file(path, input, |bytes| progress.update(bytes))?)
+    }
+
+    fn list(&mut self, dir: &str, files: bool) -> Result<Vec<String>, anyhow::Error> {
+        guest_list(&mut self.client, self.guest_os, dir, files)
+    }
+
+    fn mkdir(&mut self, dir: &str) -> Result<(), anyhow::Error> {
+        guest_mkdir(&mut self.client, self.guest_os, dir)
+    }
+}
+
+/// One file's byte count on stderr, redrawn in place.
+struct Progress {
+    enabled: bool,
+    name: String,
+    total: Option<u64>,
+    done: u64,
+    drawn: Option<Instant>,
+}
+
+impl Progress {
+    fn new(enabled: bool, name: &str, total: Option<u64>) -> Self {
+        Self { enabled, name: name.to_string(), total, done: 0, drawn: None }
+    }
+
+    fn set_total(&mut self, total: u64) {
+        self.total = Some(total);
+    }
+
+    fn update(&mut self, done: u64) {
+        self.done = done;
+        if self.drawn.is_none_or(|drawn| drawn.elapsed() >= PROGRESS_INTERVAL) {
+            self.draw();
+        }
+    }
+
+    fn finish(mut self) {
+        self.draw();
+        if self.enabled {
+            eprintln!();
+        }
+    }
+
+    fn draw(&mut self) {
+        if !self.enabled {
+            return;
+        }
+        let amount = match self.total {
+            Some(total) if total > 0 => format!(
+                "{} / {}  {:>3}%",
+                format_bytes(self.done),
+                format_bytes(total),
+                self.done.min(total) * 100 / total
+            ),
+            _ => format_bytes(self.done),
+        };
+        // Clear the rest of the line in case the previous draw was longer
+        eprint!("\r{}  {}\x1b[K", self.name, amount);
+        let _ = std::io::stderr().flush();
+        self.drawn = Some(Instant::now());
+    }
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+    if bytes < 1024 {
+        return format!("{} B", bytes);
+    }
+    let mut value = bytes as f64 / 1024.0;
+    let mut unit = 0;
+    while value >= 1024.0 && unit < UNITS.len() - 1 {
+        value /= 1024.0;
+        unit += 1;
+    }
+    format!("{:.1} {}", value, UNITS[unit])
+}
+
+/// Paths of the files (or directories) below `dir` in the guest, relative to it.
+fn guest_list(client: &mut QgaClient, guest_os: GuestOs, dir: &str, files: bool) -> Result<Vec<String>, anyhow::Error> {
+    let output = match guest_os {
+        GuestOs::Linux => client.exec(
+            "/bin/sh",
+            &[
+                "-c".to_string(),
+                format!("cd \"$1\" && find . -mindepth 1 -type {}", if files { "f" } else { "d" }),
+                "sh".to_string(),
+                dir.to_string(),
+            ],
+            GUEST_COMMAND_TIMEOUT,
+        )?,
+        GuestOs::Windows => client.exec(
+            "powershell.exe",
+            &[
+                "-NoProfile".to_string(),
+                "-Command".to_string(),
+                format!(
+                    "Get-ChildItem -LiteralPath '{}' -Recurse -Force -Name {} -ErrorAction Stop",
+                    dir.replace('\'', "''"),
+                    if files { "-File" } else { "-Directory" }
+                ),
+            ],
+            GUEST_COMMAND_TIMEOUT,
+        )?,
+    };
+    if output.exit_code != Some(0) {
+        anyhow::bail!("Could not list {} in the guest: {}", dir, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    if output.truncated {
+        anyhow::bail!("Listing {} in the guest was truncated by the agent; copy a smaller directory", dir);
+    }
+    Ok(String::from_utf8_lossy(&output.stdout)
+        .lines()
+        .map(|line| line.trim_end_matches('\r').trim_start_matches("./").to_string())
+        .filter(|line| !line.is_empty())
+        .collect())
+}
+
+fn guest_mkdir(client: &mut QgaClient, guest_os: GuestOs, dir: &str) -> Result<(), anyhow::Error> {
+    let output = match guest_os {
+        GuestOs::Linux => client.exec("mkdir", &["-p".to_string(), dir.to_string()], GUEST_COMMAND_TIMEOUT)?,
+        GuestOs::Windows => client.exec(
+            "powershell.exe",
+            &[
+                "-NoProfile".to_string(),
+                "-Command".to_string(),
+                format!("New-Item -ItemType Directory -Force -Path '{}' | Out-Null", dir.replace('\'', "''")),
+            ],
+            GUEST_COMMAND_TIMEOUT,
+        )?,
+    };
+    if output.exit_code != Some(0) {
+        anyhow::bail!("Could not create {} in the guest: {}", dir, String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
+
+fn guest_join(guest_os: GuestOs, dir: &str, relative: &str) -> String {
+    let sep = match guest_os {
+        GuestOs::Linux => "/",
+        GuestOs::Windows => "\\",
+    };
+    format!("{}{}{}", dir.trim_end_matches(['/', '\\']), sep, relative.replace(['/', '\\'], sep))
+}
+
+/// A path listed by the guest, refusing anything that would land outside the destination.
+fn host_relative(relative: &str) -> Result<PathBuf, anyhow::Error> {
+    let mut path = PathBuf::new();
+    for part in relative.split(['/', '\\']) {
+        if part.is_empty() || part == "." || part == ".." || part.contains(':') {
+            anyhow::bail!("Refusing unexpected path from the guest: {}", relative);
+        }
+        path.push(part);
+    }
+    Ok(path)
+}
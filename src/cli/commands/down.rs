@@ -1,9 +1,16 @@
 use crate::cli::AppContext;
-use crate::state::{load_state, save_state, model::VmState};
+use crate::config::paths::{get_console_token, get_integration_relay_file, get_integration_relay_socket};
+use crate::console::ConsoleEndpoint;
+use crate::qemu::qga::QgaClient;
+use crate::state::{load_state, save_state, VmState};
 use crate::state::lock::Lock;
 use crate::util::process::{is_process_running, kill_process};
+use std::time::{Duration, Instant};
 
-pub fn handle_down(ctx: &AppContext) -> Result<i32, anyhow::Error> {
+/// How long the guest gets to power off after `guest-shutdown`.
+const GUEST_SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(60);
+
+pub fn handle_down(ctx: &AppContext, force: bool) -> Result<i32, anyhow::Error> {
     // Acquire lock
     let _lock = Lock::try_acquire(ctx.root.join("config").join("portaqemu.lock"))
         .map_err(|e| anyhow::anyhow!("Failed to acquire lock: {}", e))?;
@@ -18,17 +25,70 @@ pub fn handle_down(ctx: &AppContext) -> Result<i32, anyhow::Error> {
     
     if let Some(pid) = state.qemu_pid {
         if is_process_running(pid) {
-            kill_process(pid)?;
-            println!("VM stopped (PID: {})", pid);
+            if !force && shutdown_guest(&state, pid) {
+                println!("VM shut down (PID: {})", pid);
+            } else {
+                kill_process(pid)?;
+                println!("VM stopped (PID: {})", pid);
+            }
         } else {
             println!("VM process not found (PID: {})", pid);
         }
     }
     
+    // Background helpers exit with the VM, but don't leave them behind
+    let helpers = [state.autoforward_pid.take(), state.console_pid.take(), state.integration_pid.take()];
+    for pid in helpers.into_iter().flatten() {
+        if is_process_running(pid) {
+            let _ = kill_process(pid);
+        }
+    }
+    
+    // Killed helpers cannot remove their own sockets and files
+    if let Some(ConsoleEndpoint::Unix(path)) = state.console.as_deref().and_then(|c| c.parse().ok()) {
+        let _ = std::fs::remove_file(path);
+    }
+    for leftover in [
+        get_console_token(&ctx.root),
+        get_integration_relay_file(&ctx.root),
+        get_integration_relay_socket(&ctx.root),
+    ] {
+        let _ = std::fs::remove_file(leftover);
+    }
+    
     // Update state
     state.running = false;
     state.qemu_pid = None;
+    state.shares.clear();
+    state.monitor = None;
+    state.ssh_host_port = None;
+    state.console = None;
+    state.guest_agent_socket = None;
+    state.forwards.clear();
     save_state(&state_path, &state)?;
     
     Ok(0)
 }
+
+/// Power the guest off through the guest agent and wait for QEMU to exit.
+/// Returns false when QEMU still has to be killed.
+fn shutdown_guest(state: &VmState, pid: u32) -> bool {
+    let Some(socket) = &state.guest_agent_socket else {
+        return false;
+    };
+    if let Err(e) = QgaClient::connect(socket).and_then(|mut client| client.shutdown()) {
+        println!("Guest agent unavailable ({}); stopping QEMU", e);
+        return false;
+    }
+    
+    println!("Shutting down the guest...");
+    let start = Instant::now();
+    while start.elapsed() < GUEST_SHUTDOWN_TIMEOUT {
+        if !is_process_running(pid) {
+            return true;
+        }
+        std::thread::sleep(Duration::from_millis(500));
+    }
+    println!("Guest did not power off within {}s; stopping QEMU", GUEST_SHUTDOWN_TIMEOUT.as_secs());
+    false
+}
@@ -0,0 +1,53 @@
+use crate::cli::AppContext;
+use crate::cli::commands::ssh::vm_running;
+use crate::config::load::load_config;
+use crate::output::OutputMode;
+use crate::qemu::qga::QgaClient;
+use std::io::Write;
+use std::time::Duration;
+
+pub fn handle_exec(ctx: &AppContext, timeout: u64, command: &[String]) -> Result<i32, anyhow::Error> {
+    let config = load_config(&ctx.config_path, &ctx.root)?;
+    if !config.guest_agent.enabled {
+        anyhow::bail!("The guest agent is disabled (guest_agent.enabled = false)");
+    }
+    let state_path = ctx.root.join("config").join("state.json");
+    if !vm_running(&state_path)? {
+        anyhow::bail!("VM is not running (start it with: portaqemu up)");
+    }
+    let (path, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow::anyhow!("No command given"))?;
+    
+    let mut client = QgaClient::connect(&config.guest_agent.socket)?;
+    let output = client.exec(path, args, Duration::from_secs(timeout))?;
+    // Shells report a signal as 128 + signal number
+    let code = match (output.exit_code, output.signal) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 255,
+    };
+    
+    match ctx.output_mode {
+        OutputMode::Json => {
+            use serde_json::json;
+            println!("{}", serde_json::to_string_pretty(&json!({
+                "command": command,
+                "exit_code": output.exit_code,
+                "signal": output.signal,
+                "stdout": String::from_utf8_lossy(&output.stdout),
+                "stderr": String::from_utf8_lossy(&output.stderr),
+                "truncated": output.truncated,
+            }))?);
+        }
+        OutputMode::Human => {
+            std::io::stdout().write_all(&output.stdout)?;
+            std::io::stderr().write_all(&output.stderr)?;
+            if output.truncated {
+                eprintln!("Warning: the guest agent truncated the command output");
+            }
+        }
+    }
+    
+    Ok(code)
+}
@@ -0,0 +1,207 @@
+use crate::autoforward::{AutoForwarder, PORT_CLOSED, PORT_OPENED};
+use crate::cli::{AppContext, ForwardSubcommand};
+use crate::config::edit::{add_forward, remove_forward};
+use crate::config::load::load_config;
+use crate::config::schema::PortForward;
+use crate::integration::event_bus::EventBus;
+use crate::output::OutputMode;
+use crate::qemu::argv::{hostfwd_rule, USER_NETDEV_ID};
+use crate::qemu::monitor::QmpClient;
+use crate::state::{load_state, save_state, ActiveForward, VmState};
+use crate::state::lock::Lock;
+use crate::util::net::{is_binding_available, NetError, PortBinding, Protocol};
+use crate::util::process::is_process_running;
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub fn handle_forward(ctx: &AppContext, subcmd: ForwardSubcommand) -> Result<i32, anyhow::Error> {
+    let state_path = ctx.root.join("config").join("state.json");
+    
+    match subcmd {
+        ForwardSubcommand::Add { spec, proto, name, persist } => {
+            let _lock = Lock::try_acquire(ctx.root.join("config").join("portaqemu.lock"))
+                .map_err(|e| anyhow::anyhow!("Failed to acquire lock: {}", e))?;
+            let config = load_config(&ctx.config_path, &ctx.root)?;
+            let mut state = load_state(&state_path)?;
+            let monitor = running_monitor(&state)?;
+            
+            let (host, guest) = parse_spec(&spec)?;
+            let taken = state.forwards.iter().any(|f| f.proto == proto && f.host == host);
+            if taken || (proto == Protocol::Tcp && host == config.network.ssh_host_port) {
+                anyhow::bail!("Host port {}/{} is already forwarded", host, proto);
+            }
+            let binding = PortBinding { proto, addr: Ipv4Addr::LOCALHOST.into(), port: host };
+            if !is_binding_available(&binding)? {
+                return Err(NetError::PortInUse(proto, host).into());
+            }
+            
+            let forward = ActiveForward {
+                proto,
+                bind: Ipv4Addr::LOCALHOST,
+                host,
+                guest_addr: None,
+                guest,
+                name,
+                runtime: !persist,
+                auto: false,
+            };
+            // Save first so a config that can't be edited leaves the VM untouched
+            if persist {
+                add_forward(&ctx.config_path, &PortForward {
+                    host,
+                    guest,
+                    proto,
+                    bind: forward.bind,
+                    allow_external: false,
+                    guest_addr: None,
+                    name: forward.name.clone(),
+                })?;
+            }
+            let added = QmpClient::connect(&monitor).and_then(|mut qmp| {
+                qmp.hostfwd_add(
+                    USER_NETDEV_ID,
+                    &hostfwd_rule(forward.proto, forward.bind, forward.host, forward.guest_addr, forward.guest),
+                )
+            });
+            if let Err(e) = added {
+                if persist {
+                    let _ = remove_forward(&ctx.config_path, proto, host);
+                }
+                return Err(e.into());
+            }
+            state.forwards.push(forward);
+            save_state(&state_path, &state)?;
+            
+            println!("Forwarding {}/{} -> guest {}{}", host, proto, guest, if persist { " (saved to config)" } else { "" });
+        }
+        ForwardSubcommand::Remove { host, proto, persist } => {
+            let _lock = Lock::try_acquire(ctx.root.join("config").join("portaqemu.lock"))
+                .map_err(|e| anyhow::anyhow!("Failed to acquire lock: {}", e))?;
+            let mut state = load_state(&state_path)?;
+            
+            let live = state.forwards.iter().position(|f| f.proto == proto && f.host == host);
+            match (running_monitor(&state), live) {
+                (Ok(monitor), Some(index)) => {
+                    let forward = state.forwards.remove(index);
+                    QmpClient::connect(&monitor)?.hostfwd_remove(
+                        USER_NETDEV_ID,
+                        &format!("{}:{}:{}", forward.proto, forward.bind, forward.host),
+                    )?;
+                    save_state(&state_path, &state)?;
+                    println!("Removed forward {}/{}", host, proto);
+                }
+                // Without a live forward, only the config entry can be removed
+                (_, _) if persist => {}
+                (Ok(_), None) => anyhow::bail!("No active forward for host port {}/{}", host, proto),
+                (Err(e), _) => return Err(e),
+            }
+            
+            if persist {
+                remove_forward(&ctx.config_path, proto, host)?;
+                println!("Removed forward {}/{} from config", host, proto);
+            }
+        }
+        ForwardSubcommand::Watch => {
+            let config = load_config(&ctx.config_path, &ctx.root)?;
+            running_monitor(&load_state(&state_path)?)?;
+            let json = ctx.output_mode == OutputMode::Json;
+            let event_bus = Arc::new(EventBus::new());
+            
+            let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+            runtime.block_on(async {
+                for event in [PORT_OPENED, PORT_CLOSED] {
+                    event_bus.on(event.to_string(), move |data| print_event(event, &data, json)).await;
+                }
+                AutoForwarder::new(config, &ctx.root, event_bus.clone()).run().await
+            })?;
+        }
+        ForwardSubcommand::List => {
+            let state = load_state(&state_path)?;
+            let forwards = if running_monitor(&state).is_ok() { state.forwards } else { Vec::new() };
+            
+            match ctx.output_mode {
+                OutputMode::Json => {
+                    println!("{}", serde_json::to_string_pretty(&forwards)?);
+                }
+                OutputMode::Human => {
+                    if forwards.is_empty() {
+                        println!("No active forwards");
+                    }
+                    for forward in &forwards {
+                        println!("  {}", format_forward(forward));
+                    }
+                }
+            }
+        }
+    }
+    
+    Ok(0)
+}
+
+/// Print an auto-forward event as a line of text or JSON.
+fn print_event(event: &str, data: &serde_json::Value, json: bool) {
+    if json {
+        let mut line = serde_json::json!({ "event": event });
+        if let (Some(line), Some(data)) = (line.as_object_mut(), data.as_object()) {
+            line.extend(data.clone());
+        }
+        println!("{}", line);
+        return;
+    }
+    let port = &data["port"];
+    let host_port = &data["host_port"];
+    if event == PORT_OPENED {
+        println!("Forwarded guest port {} -> 127.0.0.1:{}", port, host_port);
+    } else {
+        println!("Guest port {} closed (was 127.0.0.1:{})", port, host_port);
+    }
+}
+
+/// Monitor address of the running VM.
+fn running_monitor(state: &VmState) -> Result<PathBuf, anyhow::Error> {
+    if !state.running || !state.qemu_pid.map(is_process_running).unwrap_or(false) {
+        anyhow::bail!("VM is not running");
+    }
+    state.monitor
+        .clone()
+        .ok_or_else(|| anyhow::anyhow!("VM was started without a monitor; restart it to manage forwards"))
+}
+
+/// Parse `HOST:GUEST`.
+fn parse_spec(spec: &str) -> Result<(u16, u16), anyhow::Error> {
+    let invalid = || anyhow::anyhow!("Invalid forward '{}' (expected HOST_PORT:GUEST_PORT)", spec);
+    let (host, guest) = spec.split_once(':').ok_or_else(invalid)?;
+    let host: u16 = host.trim().parse().map_err(|_| invalid())?;
+    let guest: u16 = guest.trim().parse().map_err(|_| invalid())?;
+    if host == 0 || guest == 0 {
+        return Err(invalid());
+    }
+    Ok((host, guest))
+}
+
+/// One-line description of a forward, e.g. `127.0.0.1:8080/tcp -> :80 (web, runtime)`.
+pub(crate) fn format_forward(forward: &ActiveForward) -> String {
+    let mut line = format!(
+        "{}:{}/{} -> {}:{}",
+        forward.bind,
+        forward.host,
+        forward.proto,
+        forward.guest_addr.map(|a| a.to_string()).unwrap_or_default(),
+        forward.guest
+    );
+    let mut tags = Vec::new();
+    if let Some(name) = &forward.name {
+        tags.push(name.as_str());
+    }
+    if forward.runtime {
+        tags.push("runtime");
+    }
+    if forward.auto {
+        tags.push("auto");
+    }
+    if !tags.is_empty() {
+        line.push_str(&format!(" ({})", tags.join(", ")));
+    }
+    line
+}
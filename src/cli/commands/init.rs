@@ -1,4 +1,7 @@
 use crate::cli::AppContext;
+use crate::config::load::load_config_unvalidated;
+use crate::integration::ensure_secret;
+use crate::ssh::{generate_key, key_comment};
 use std::fs;
 
 pub fn handle_init(ctx: &AppContext) -> Result<i32, anyhow::Error> {
@@ -40,10 +43,30 @@ mode = "ssh"
 [vscode]
 ssh_user = "dev"
 identity_file = "%ROOT%/config/ssh/id_ed25519"
+
+[install]
+disk_size_gb = 64
+# virtio_win_iso = "%ROOT%/iso/virtio-win.iso"
+
+[qemu]
+# path = "C:/Tools/qemu/qemu-system-x86_64.exe"
+# version = "8.2.0"
 "#;
         fs::write(config_path, default_config)?;
     }
     
+    // Create the SSH identity if missing
+    let config = load_config_unvalidated(config_path, &ctx.root)?;
+    if !config.vscode.identity_file.exists() {
+        let key = generate_key(&config.vscode.identity_file, &key_comment(&config), false)?;
+        println!("Generated SSH key: {} ({})", config.vscode.identity_file.to_string_lossy(), key.fingerprint());
+    }
+    
+    // Create the integration channel secret if missing
+    if ensure_secret(&config.integration.secret_file)? {
+        println!("Generated integration secret: {}", config.integration.secret_file.to_string_lossy());
+    }
+    
     println!("Initialized PortaQEMU at: {}", ctx.root.to_string_lossy());
     Ok(0)
 }
@@ -0,0 +1,144 @@
+use crate::cli::AppContext;
+use crate::cli::commands::up::{launch_vm, pin_guest_host_keys, refresh_ssh_entries, wait_for_guest_ssh};
+use crate::config::load::load_config_unvalidated;
+use crate::config::schema::{GuestOs, ReadinessConfig};
+use crate::config::validate::{validate_caps, validate_settings};
+use crate::provision::autounattend::{generate_autounattend, UnattendParams};
+use crate::provision::iso9660::{build_iso, IsoFile};
+use crate::config::paths::{get_install_password_file, public_key_path};
+use crate::qemu::{locate_qemu, detect_caps, cdrom_args, detect_disk_format, assign_host_ports, check_host_ports};
+use crate::qemu::img::{locate_qemu_img, create_disk};
+use crate::state::{load_state, save_state};
+use crate::state::lock::Lock;
+use crate::ssh::write_private;
+use crate::util::net::AUTO_PORT;
+use crate::util::process::is_process_running;
+use crate::util::random::random_alphanumeric;
+use std::ffi::OsString;
+use std::fs;
+use std::path::Path;
+
+pub fn handle_install(ctx: &AppContext, iso: &Path, force: bool) -> Result<i32, anyhow::Error> {
+    // Acquire lock
+    let _lock = Lock::try_acquire(ctx.root.join("config").join("portaqemu.lock"))
+        .map_err(|e| anyhow::anyhow!("Failed to acquire lock: {}", e))?;
+    
+    let state_path = ctx.root.join("config").join("state.json");
+    let mut state = load_state(&state_path)?;
+    if state.running && state.qemu_pid.map(is_process_running).unwrap_or(false) {
+        anyhow::bail!("VM is running; stop it first with: portaqemu down");
+    }
+    
+    // The disk doesn't exist yet, so check everything else before touching it
+    let mut config = load_config_unvalidated(&ctx.config_path, &ctx.root)?;
+    if config.vm.guest_os != GuestOs::Windows {
+        anyhow::bail!("portaqemu install requires vm.guest_os = \"windows\"");
+    }
+    if !iso.is_file() {
+        anyhow::bail!("Installation ISO not found: {}", iso.to_string_lossy());
+    }
+    if config.vm.disk.exists() && !force {
+        anyhow::bail!(
+            "Disk image already exists: {} (use --force to overwrite)",
+            config.vm.disk.to_string_lossy()
+        );
+    }
+    validate_settings(&config)?;
+    
+    let pub_path = public_key_path(&config.vscode.identity_file);
+    let public_key = fs::read_to_string(&pub_path)
+        .map_err(|_| anyhow::anyhow!("Public key not found: {}", pub_path.to_string_lossy()))?;
+    
+    let qemu_path = locate_qemu(&ctx.root, &config.qemu)?;
+    let qemu_img = locate_qemu_img(&qemu_path)?;
+    let caps = detect_caps(&qemu_path, &ctx.root.join("config").join("qemu-caps.json"))?;
+    validate_caps(&config, &caps)?;
+    
+    let auto_ssh_port = config.network.ssh_host_port == AUTO_PORT;
+    let reserved = assign_host_ports(&mut config)?;
+    check_host_ports(&config, &reserved)?;
+    
+    // Create the disk
+    if config.vm.disk.exists() {
+        fs::remove_file(&config.vm.disk)?;
+    }
+    create_disk(
+        &qemu_img,
+        &config.vm.disk,
+        detect_disk_format(&config.vm.disk),
+        config.install.disk_size_gb,
+    )?;
+    println!(
+        "Created disk: {} ({} GB)",
+        config.vm.disk.to_string_lossy(),
+        config.install.disk_size_gb
+    );
+    
+    // Generate autounattend.xml and pack it into a small ISO. It holds the
+    // password in plaintext, so only the current user may read it.
+    let (password, generated) = match &config.install.password {
+        Some(password) => (password.clone(), false),
+        None => (random_alphanumeric(16)?, true),
+    };
+    // A generated password goes to a private file rather than the terminal or logs
+    let password_file = get_install_password_file(&ctx.root, &config.vm.name);
+    if generated {
+        write_private(&password_file, format!("{}\n", password).as_bytes())?;
+    } else {
+        let _ = fs::remove_file(&password_file);
+    }
+    let params = UnattendParams::from_config(&config, &password, &public_key);
+    let xml = generate_autounattend(&params);
+    let unattend_iso = ctx.root.join("vm").join("autounattend.iso");
+    write_private(
+        &unattend_iso,
+        &build_iso("UNATTEND", &[IsoFile { name: "autounattend.xml", data: xml.as_bytes() }]),
+    )?;
+    
+    // Install media: Windows ISO (boot), unattend ISO, virtio-win drivers
+    let mut extra: Vec<OsString> = cdrom_args(&caps, iso, "install", true);
+    extra.extend(cdrom_args(&caps, &unattend_iso, "unattend", false));
+    match &config.install.virtio_win_iso {
+        Some(virtio_iso) => extra.extend(cdrom_args(&caps, virtio_iso, "virtio", false)),
+        None => println!("Warning: install.virtio_win_iso not set; Setup may not find the virtio disk"),
+    }
+    
+    let vm = launch_vm(ctx, &config, &extra, reserved, &mut state)?;
+    println!("Windows setup started (PID: {})", vm.pid);
+    if auto_ssh_port {
+        refresh_ssh_entries(&config, &ctx.root, &mut state.pending_entries);
+        save_state(&state_path, &state)?;
+    }
+    if generated {
+        println!(
+            "Generated password for '{}' saved to {}",
+            config.vscode.ssh_user,
+            password_file.to_string_lossy()
+        );
+    }
+    
+    // Setup reboots several times; wait until OpenSSH answers
+    println!(
+        "Waiting up to {} minutes for SSH on port {}...",
+        config.install.timeout_minutes,
+        config.network.ssh_host_port
+    );
+    let readiness = ReadinessConfig {
+        timeout_secs: config.install.timeout_minutes * 60,
+        ..config.readiness.clone()
+    };
+    wait_for_guest_ssh(&config, &readiness)?;
+    println!("Install complete: SSH is reachable on port {}", config.network.ssh_host_port);
+    pin_guest_host_keys(&config);
+    
+    // Setup has consumed the answer file; don't leave the password lying around
+    if let Err(e) = fs::remove_file(&unattend_iso) {
+        println!(
+            "Warning: could not delete {} ({}); it contains the install password, remove it after: portaqemu down",
+            unattend_iso.to_string_lossy(),
+            e
+        );
+    }
+    
+    Ok(0)
+}
@@ -0,0 +1,29 @@
+use crate::cli::AppContext;
+use crate::config::load::load_config;
+use crate::config::paths::{get_integration_relay_file, get_integration_relay_socket};
+use crate::integration::{host_registry, relay_endpoint, serve_host, serve_relay, EventBus, GuestLink, Listener, Secret};
+use crate::ssh::keys::write_private;
+use std::path::PathBuf;
+use std::sync::Arc;
+
+pub fn handle_integration_host(ctx: &AppContext, chardev: PathBuf) -> Result<i32, anyhow::Error> {
+    let config = load_config(&ctx.config_path, &ctx.root)?;
+    let secret = Secret::load(&config.integration.secret_file)?;
+    let relay_file = get_integration_relay_file(&ctx.root);
+    let runtime = tokio::runtime::Builder::new_current_thread().enable_all().build()?;
+    let result = runtime.block_on(async {
+        // Other commands (such as cp) reach the guest through this process
+        let guest = GuestLink::new();
+        let listener = Listener::bind(&relay_endpoint(&get_integration_relay_socket(&ctx.root))).await?;
+        write_private(&relay_file, listener.local_endpoint()?.to_string().as_bytes())?;
+        let relay = tokio::spawn(serve_relay(listener, secret.clone(), guest.clone()));
+
+        let registry = Arc::new(host_registry().await);
+        let served = serve_host(&chardev, &secret, registry, Arc::new(EventBus::new()), guest).await;
+        relay.abort();
+        Ok::<_, anyhow::Error>(served?)
+    });
+    let _ = std::fs::remove_file(&relay_file);
+    result?;
+    Ok(0)
+}
@@ -0,0 +1,136 @@
+use crate::cli::{AppContext, KeysSubcommand};
+use crate::config::load::load_config_unvalidated;
+use crate::config::paths::public_key_path;
+use crate::output::OutputMode;
+use crate::ssh::{authorize_key, generate_key, key_comment, pin_host_keys, reset_pinned, SshKeyPair};
+use crate::state::load_state;
+use crate::util::process::is_process_running;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+pub fn handle_keys(ctx: &AppContext, subcmd: KeysSubcommand) -> Result<i32, anyhow::Error> {
+    let config = load_config_unvalidated(&ctx.config_path, &ctx.root)?;
+    let identity = &config.vscode.identity_file;
+    
+    match subcmd {
+        KeysSubcommand::Generate { force } => {
+            let key = generate_key(identity, &key_comment(&config), force)?;
+            print_key(ctx, identity, &key)?;
+        }
+        KeysSubcommand::Show => {
+            let key = SshKeyPair::load(identity)?;
+            print_key(ctx, identity, &key)?;
+        }
+        KeysSubcommand::Push => {
+            pin_host_keys(&config)?;
+            let key = SshKeyPair::load(identity)?;
+            authorize_key(&config, identity, &key.public_key_line(), None)?;
+            println!("Public key installed in guest ({})", key.fingerprint());
+        }
+        KeysSubcommand::Rotate => {
+            let state = load_state(ctx.root.join("config").join("state.json"))?;
+            if !state.running || !state.qemu_pid.map(is_process_running).unwrap_or(false) {
+                anyhow::bail!("VM is not running; start it first so the new key can be installed");
+            }
+            
+            pin_host_keys(&config)?;
+            
+            // Save the new key before the guest accepts it, so a failure at any
+            // step below leaves a key on disk that the guest still accepts
+            let old = SshKeyPair::load(identity)?;
+            let new = SshKeyPair::generate(&key_comment(&config))?;
+            let pending = with_suffix(identity, ".new");
+            new.save(&pending)?;
+            
+            // Install the new key using the old one, removing the old key in the same step
+            if let Err(e) = authorize_key(&config, identity, &new.public_key_line(), Some(&old.public_key_line())) {
+                anyhow::bail!(
+                    "{} (the new key is kept at {} in case the guest already accepts it)",
+                    e,
+                    pending.to_string_lossy()
+                );
+            }
+            
+            // Swap the files, keeping the previous key next to the new one
+            let backup = with_suffix(identity, ".old");
+            if let Err(e) = swap_keys(identity, &pending, &backup) {
+                anyhow::bail!(
+                    "The guest now accepts only the new key, but moving it into place failed: {} (the new key is at {})",
+                    e,
+                    pending.to_string_lossy()
+                );
+            }
+            
+            println!("Rotated SSH key: {} -> {}", old.fingerprint(), new.fingerprint());
+            println!("Previous key kept at: {}", backup.to_string_lossy());
+        }
+        KeysSubcommand::Trust { reset } => {
+            if reset && reset_pinned(&config)? {
+                println!("Removed pinned host keys: {}", config.vscode.known_hosts_file.to_string_lossy());
+            }
+            let pin = pin_host_keys(&config)?;
+            match ctx.output_mode {
+                OutputMode::Json => {
+                    use serde_json::json;
+                    let keys: Vec<_> = pin.keys.iter().map(|key| json!({
+                        "type": key.key_type,
+                        "fingerprint": key.fingerprint(),
+                    })).collect();
+                    println!("{}", serde_json::to_string_pretty(&json!({
+                        "known_hosts_file": config.vscode.known_hosts_file.to_string_lossy(),
+                        "newly_pinned": pin.newly_pinned,
+                        "keys": keys,
+                    }))?);
+                }
+                OutputMode::Human => {
+                    if pin.newly_pinned {
+                        println!("Pinned host keys: {}", config.vscode.known_hosts_file.to_string_lossy());
+                    } else {
+                        println!("Host keys already pinned (use --reset after re-imaging the VM)");
+                    }
+                    for key in &pin.keys {
+                        println!("  {} {}", key.key_type, key.fingerprint());
+                    }
+                }
+            }
+        }
+    }
+    
+    Ok(0)
+}
+
+fn print_key(ctx: &AppContext, identity: &Path, key: &SshKeyPair) -> Result<(), anyhow::Error> {
+    match ctx.output_mode {
+        OutputMode::Json => {
+            use serde_json::json;
+            println!("{}", serde_json::to_string_pretty(&json!({
+                "identity_file": identity.to_string_lossy(),
+                "public_key": key.public_key_line(),
+                "fingerprint": key.fingerprint(),
+            }))?);
+        }
+        OutputMode::Human => {
+            println!("Identity: {}", identity.to_string_lossy());
+            println!("Fingerprint: {}", key.fingerprint());
+            println!("{}", key.public_key_line());
+        }
+    }
+    Ok(())
+}
+
+/// Move `identity` and its public key to `backup`, then `pending` into their place.
+fn swap_keys(identity: &Path, pending: &Path, backup: &Path) -> std::io::Result<()> {
+    fs::rename(identity, backup)?;
+    let old_pub = public_key_path(identity);
+    if old_pub.exists() {
+        fs::rename(&old_pub, public_key_path(backup))?;
+    }
+    fs::rename(pending, identity)?;
+    fs::rename(public_key_path(pending), public_key_path(identity))
+}
+
+fn with_suffix(path: &Path, suffix: &str) -> PathBuf {
+    let mut name = path.as_os_str().to_os_string();
+    name.push(suffix);
+    PathBuf::from(name)
+}
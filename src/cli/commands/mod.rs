@@ -7,6 +7,16 @@ pub mod terminal;
 pub mod vscode;
 pub mod autostart;
 pub mod doctor;
+pub mod qemu;
+pub mod argv;
+pub mod seed;
+pub mod install;
+pub mod keys;
+pub mod cp;
+pub mod forward;
+pub mod console;
+pub mod exec;
+pub mod integration;
 
 pub use init::*;
 pub use up::*;
@@ -17,3 +27,13 @@ pub use terminal::*;
 pub use vscode::*;
 pub use autostart::*;
 pub use doctor::*;
+pub use qemu::*;
+pub use argv::*;
+pub use seed::*;
+pub use install::*;
+pub use keys::*;
+pub use cp::*;
+pub use forward::*;
+pub use console::*;
+pub use exec::*;
+pub use integration::*;
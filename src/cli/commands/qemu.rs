@@ -0,0 +1,75 @@
+use crate::cli::{AppContext, QemuSubcommand};
+use crate::config::load::load_config_unvalidated;
+use crate::qemu::caps::detect_caps;
+use crate::qemu::install::install_qemu_zip;
+use crate::qemu::locate::{discover_qemu, locate_qemu};
+use crate::output::OutputMode;
+
+pub fn handle_qemu(ctx: &AppContext, subcmd: QemuSubcommand) -> Result<i32, anyhow::Error> {
+    match subcmd {
+        QemuSubcommand::Install { zip, sha256 } => {
+            let installed = install_qemu_zip(&ctx.root, &zip, sha256.as_deref())?;
+            match ctx.output_mode {
+                OutputMode::Json => {
+                    use serde_json::json;
+                    println!("{}", serde_json::to_string_pretty(&json!({
+                        "version": installed.version,
+                        "path": installed.path.to_string_lossy(),
+                        "sha256": installed.sha256,
+                    }))?);
+                }
+                OutputMode::Human => {
+                    println!("Installed QEMU {}: {}", installed.version, installed.path.to_string_lossy());
+                    println!("Pin it with: [qemu] version = \"{}\"", installed.version);
+                }
+            }
+            Ok(0)
+        }
+        QemuSubcommand::List => {
+            // Listing must work while the config points at a missing binary or disk
+            let config = load_config_unvalidated(&ctx.config_path, &ctx.root)?;
+            let selected = locate_qemu(&ctx.root, &config.qemu).ok();
+            let cache_path = ctx.root.join("config").join("qemu-caps.json");
+            
+            let entries: Vec<_> = discover_qemu(&ctx.root, &config.qemu)
+                .into_iter()
+                .map(|d| {
+                    let version = detect_caps(&d.path, &cache_path)
+                        .map(|caps| caps.version.to_string())
+                        .ok();
+                    let is_selected = selected.as_ref() == Some(&d.path);
+                    (d, version, is_selected)
+                })
+                .collect();
+            
+            match ctx.output_mode {
+                OutputMode::Json => {
+                    use serde_json::json;
+                    let list: Vec<_> = entries.iter().map(|(d, version, is_selected)| json!({
+                        "path": d.path.to_string_lossy(),
+                        "source": d.source.to_string(),
+                        "installed_version": d.installed_version,
+                        "version": version,
+                        "selected": is_selected,
+                    })).collect();
+                    println!("{}", serde_json::to_string_pretty(&list)?);
+                }
+                OutputMode::Human => {
+                    if entries.is_empty() {
+                        println!("No QEMU binaries found");
+                    }
+                    for (d, version, is_selected) in &entries {
+                        println!(
+                            "{} {:<10} {:<7} {}",
+                            if *is_selected { "*" } else { " " },
+                            version.as_deref().unwrap_or("unknown"),
+                            d.source,
+                            d.path.to_string_lossy()
+                        );
+                    }
+                }
+            }
+            Ok(0)
+        }
+    }
+}
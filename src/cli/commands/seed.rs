@@ -0,0 +1,28 @@
+use crate::cli::AppContext;
+use crate::config::load::load_config;
+use crate::provision::ensure_seed;
+use crate::output::OutputMode;
+
+pub fn handle_seed(ctx: &AppContext, force: bool) -> Result<i32, anyhow::Error> {
+    let config = load_config(&ctx.config_path, &ctx.root)?;
+    let seed = ensure_seed(&config, force)?;
+    
+    match ctx.output_mode {
+        OutputMode::Json => {
+            use serde_json::json;
+            println!("{}", serde_json::to_string_pretty(&json!({
+                "seed": seed.path.to_string_lossy(),
+                "regenerated": seed.regenerated,
+            }))?);
+        }
+        OutputMode::Human => {
+            if seed.regenerated {
+                println!("Seed written: {}", seed.path.to_string_lossy());
+            } else {
+                println!("Seed up to date: {}", seed.path.to_string_lossy());
+            }
+        }
+    }
+    
+    Ok(0)
+}
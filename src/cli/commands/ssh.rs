@@ -1,26 +1,89 @@
-use crate::cli::AppContext;
+use crate::cli::{AppContext, SshArgs};
+use crate::cli::commands::argv::quote_arg;
 use crate::config::load::load_config;
+use crate::config::schema::{ReadinessConfig, ResolvedConfig};
+use crate::ssh::{pin_host_keys, ssh_destination, ssh_options, wait_for_ssh};
+use crate::state::load_state;
+use crate::util::net::AUTO_PORT;
+use crate::util::process::is_process_running;
+use std::ffi::OsString;
+use std::path::Path;
 use std::process::Command;
+use std::time::{Duration, Instant};
 
-pub fn handle_ssh(ctx: &AppContext, exec: bool) -> Result<i32, anyhow::Error> {
-    let config = load_config(&ctx.config_path, &ctx.root)?;
+/// How long `--wait` waits for the VM to come up.
+const WAIT_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Exit code when ssh could not report one (killed by a signal).
+const SSH_FAILURE_CODE: i32 = 255;
+
+pub fn handle_ssh(ctx: &AppContext, args: SshArgs) -> Result<i32, anyhow::Error> {
+    let mut config = load_config(&ctx.config_path, &ctx.root)?;
     
-    let ssh_cmd = format!(
-        "ssh -p {} {}@localhost -i {}",
-        config.network.ssh_host_port,
-        config.vscode.ssh_user,
-        config.vscode.identity_file.to_string_lossy()
-    );
+    if !args.exec && args.command.is_empty() {
+        if config.network.ssh_host_port == AUTO_PORT {
+            anyhow::bail!("VM is not running and ssh_host_port is \"auto\"; the port is chosen at: portaqemu up");
+        }
+        let line: Vec<_> = ssh_command_args(&config, &args).iter().map(|a| quote_arg(&a.to_string_lossy())).collect();
+        println!("ssh {}", line.join(" "));
+        return Ok(0);
+    }
     
-    if exec {
-        let mut cmd = Command::new("ssh");
-        cmd.arg("-p").arg(config.network.ssh_host_port.to_string());
-        cmd.arg(format!("{}@localhost", config.vscode.ssh_user));
-        cmd.arg("-i").arg(&config.vscode.identity_file);
-        cmd.status()?;
-        Ok(0)
-    } else {
-        println!("{}", ssh_cmd);
-        Ok(0)
+    let state_path = ctx.root.join("config").join("state.json");
+    if args.wait {
+        let start = Instant::now();
+        wait_for_running(&state_path)?;
+        // "auto" ports are only known once the VM runs
+        config = load_config(&ctx.config_path, &ctx.root)?;
+        let readiness = ReadinessConfig {
+            timeout_secs: WAIT_TIMEOUT.saturating_sub(start.elapsed()).as_secs(),
+            ..config.readiness.clone()
+        };
+        wait_for_ssh(&config, &readiness, |_| {})?;
+    } else if !vm_running(&state_path)? {
+        anyhow::bail!("VM is not running (start it with: portaqemu up, or pass --wait)");
+    }
+    pin_host_keys(&config)?;
+    
+    // The remote exit status becomes ours; ssh itself reports its own failures as 255
+    let status = Command::new("ssh").args(ssh_command_args(&config, &args)).status()?;
+    Ok(status.code().unwrap_or(SSH_FAILURE_CODE))
+}
+
+/// Arguments for `ssh`: options, destination, then the remote command.
+fn ssh_command_args(config: &ResolvedConfig, args: &SshArgs) -> Vec<OsString> {
+    let mut ssh_args = ssh_options(config, &config.vscode.identity_file);
+    if args.tty {
+        ssh_args.push("-t".into());
+    }
+    if args.no_tty {
+        ssh_args.push("-T".into());
+    }
+    for opt in &args.ssh_opt {
+        if !opt.starts_with('-') {
+            ssh_args.push("-o".into());
+        }
+        ssh_args.push(opt.into());
+    }
+    ssh_args.push(ssh_destination(config).into());
+    ssh_args.extend(args.command.iter().map(OsString::from));
+    ssh_args
+}
+
+/// Whether the state file records a live QEMU process.
+pub(crate) fn vm_running(state_path: &Path) -> Result<bool, anyhow::Error> {
+    let state = load_state(state_path)?;
+    Ok(state.running && state.qemu_pid.map(is_process_running).unwrap_or(false))
+}
+
+/// Block until the VM is recorded as running.
+fn wait_for_running(state_path: &Path) -> Result<(), anyhow::Error> {
+    let start = Instant::now();
+    while !vm_running(state_path)? {
+        if start.elapsed() >= WAIT_TIMEOUT {
+            anyhow::bail!("Timed out waiting for the VM to start");
+        }
+        std::thread::sleep(Duration::from_millis(500));
     }
+    Ok(())
 }
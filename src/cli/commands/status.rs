@@ -1,15 +1,26 @@
 use crate::cli::AppContext;
+use crate::cli::commands::forward::format_forward;
+use crate::qemu::qga::{GuestInfo, QgaClient, QgaError};
 use crate::state::load_state;
 use crate::util::process::is_process_running;
 use crate::output::{OutputMode, human::format_status};
+use std::path::Path;
+use std::time::Duration;
+
+/// Kept short so `status` stays quick when no agent runs in the guest.
+const AGENT_TIMEOUT: Duration = Duration::from_secs(1);
 
 pub fn handle_status(ctx: &AppContext) -> Result<i32, anyhow::Error> {
     let state_path = ctx.root.join("config").join("state.json");
     let state = load_state(&state_path)?;
     
     let actually_running = state.qemu_pid
-        .map(|pid| is_process_running(pid))
+        .map(is_process_running)
         .unwrap_or(false);
+    let guest = state.guest_agent_socket
+        .as_deref()
+        .filter(|_| actually_running)
+        .map(query_guest);
     
     match ctx.output_mode {
         OutputMode::Json => {
@@ -18,6 +29,12 @@ pub fn handle_status(ctx: &AppContext) -> Result<i32, anyhow::Error> {
                 "running": actually_running,
                 "pid": state.qemu_pid,
                 "started_at": state.started_at,
+                "last_error": state.last_error,
+                "ssh_port": if actually_running { state.ssh_host_port } else { None },
+                "console": if actually_running { state.console.clone() } else { None },
+                "guest": guest.as_ref().and_then(|g| g.as_ref().ok()),
+                "shares": if actually_running { state.shares.clone() } else { Vec::new() },
+                "forwards": if actually_running { state.forwards.clone() } else { Vec::new() },
             });
             println!("{}", serde_json::to_string_pretty(&json)?);
         }
@@ -26,8 +43,44 @@ pub fn handle_status(ctx: &AppContext) -> Result<i32, anyhow::Error> {
             if let Some(started_at) = &state.started_at {
                 println!("Started at: {}", started_at);
             }
+            if let Some(last_error) = &state.last_error {
+                println!("Last error: {}", last_error);
+            }
+            if let Some(port) = state.ssh_host_port.filter(|_| actually_running) {
+                println!("SSH port: {}", port);
+            }
+            if let Some(console) = state.console.as_ref().filter(|_| actually_running) {
+                println!("Console: {} (portaqemu console)", console);
+            }
+            match &guest {
+                Some(Ok(info)) => println!("Guest: {} ({})", info.host_name, info.addresses().join(", ")),
+                Some(Err(e)) => println!("Guest agent: {}", e),
+                None => {}
+            }
+            if actually_running && !state.forwards.is_empty() {
+                println!("Forwards:");
+                for forward in &state.forwards {
+                    println!("  {}", format_forward(forward));
+                }
+            }
+            if actually_running && !state.shares.is_empty() {
+                println!("Shares:");
+                for share in &state.shares {
+                    println!(
+                        "  {} -> {}{}",
+                        share.tag,
+                        share.host_path,
+                        if share.read_only { " (read-only)" } else { "" }
+                    );
+                    println!("    mount: {}", share.mount_command);
+                }
+            }
         }
     }
     
     Ok(0)
 }
+
+fn query_guest(socket: &Path) -> Result<GuestInfo, QgaError> {
+    QgaClient::connect_timeout(socket, AGENT_TIMEOUT)?.guest_info()
+}
@@ -1,15 +1,34 @@
 use crate::cli::{AppContext, TerminalSubcommand};
+use crate::cli::commands::up::update_pending_entries;
 use crate::config::load::load_config;
+use crate::config::schema::TerminalMode;
 use crate::config::paths::get_fragment_file;
 use crate::terminal::fragment::{install_fragment, remove_fragment};
 use crate::output::OutputMode;
+use crate::util::net::AUTO_PORT;
 
 pub fn handle_terminal(ctx: &AppContext, subcmd: TerminalSubcommand) -> Result<i32, anyhow::Error> {
     let config = load_config(&ctx.config_path, &ctx.root)?;
     
     match subcmd {
         TerminalSubcommand::Install => {
-            let fragment_path = install_fragment(&config)?;
+            // An "auto" port is only chosen at `up`
+            if config.terminal.mode == TerminalMode::Ssh && config.network.ssh_host_port == AUTO_PORT {
+                update_pending_entries(ctx, |pending| pending.terminal_fragment = true)?;
+                match ctx.output_mode {
+                    OutputMode::Json => {
+                        println!("{}", serde_json::to_string_pretty(&serde_json::json!({
+                            "fragment_file": null,
+                            "pending": true
+                        }))?);
+                    }
+                    OutputMode::Human => {
+                        println!("SSH port is \"auto\" and the VM is not running; the terminal fragment will be written at: portaqemu up");
+                    }
+                }
+                return Ok(0);
+            }
+            let fragment_path = install_fragment(&config, &ctx.root)?;
             match ctx.output_mode {
                 OutputMode::Json => {
                     use serde_json::json;
@@ -24,6 +43,7 @@ pub fn handle_terminal(ctx: &AppContext, subcmd: TerminalSubcommand) -> Result<i
             Ok(0)
         }
         TerminalSubcommand::Remove => {
+            update_pending_entries(ctx, |pending| pending.terminal_fragment = false)?;
             remove_fragment(&config.vm.name)?;
             match ctx.output_mode {
                 OutputMode::Json => {
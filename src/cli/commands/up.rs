@@ -1,17 +1,38 @@
 use crate::cli::AppContext;
 use crate::config::load::load_config;
-use crate::qemu::{locate_qemu, detect_available_accels, choose_accel, build_argv, spawn_qemu};
-use crate::qemu::probe::is_accel_failure;
-use crate::state::{load_state, save_state, model::VmState};
+use crate::config::validate::validate_caps;
+use crate::qemu::{locate_qemu, detect_caps, accels_from_caps, choose_accel, launch_argv, assign_host_ports, check_host_ports, spawn_qemu};
+use crate::qemu::probe::watch_startup;
+use crate::qemu::qga::wait_for_agent;
+use crate::provision::ensure_seed;
+use crate::integration::{new_nonce, Secret};
+use crate::ssh::{pin_host_keys, wait_for_ssh};
+use crate::ssh::keys::write_private;
+use crate::qemu::share::guest_mount_command;
+use crate::config::schema::{ConsoleTransport, ReadinessConfig, ResolvedConfig};
+use crate::config::paths::{get_console_socket, get_console_token, get_fragment_file, get_monitor_socket, get_run_dir};
+use crate::terminal::fragment::install_fragment;
+use crate::vscode::ssh_config::{install_ssh_config, ssh_config_installed};
+use crate::qemu::spawn::RunningVm;
+use crate::state::{load_state, save_state, ActiveForward, ActiveShare, PendingEntries, VmState};
 use crate::state::lock::Lock;
-use crate::util::net::{check_ports_available, wait_for_port};
+use crate::util::net::{ReservedPort, AUTO_PORT};
+use crate::console::{attach, compile_markers, wait_for_marker, ConsoleEndpoint, DETACH_KEY};
 use crate::util::hashing::hash_argv;
+use crate::util::local_socket::{check_socket_path, create_private_dir};
 use crate::util::time::now_iso;
+use crate::util::process::{detach, kill_process};
+use std::ffi::OsString;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
 use std::time::Duration;
 
-pub fn handle_up(ctx: &AppContext, _attach: bool, no_wait: bool) -> Result<i32, anyhow::Error> {
+/// How long a freshly spawned QEMU is watched for an early exit.
+const ACCEL_GRACE_PERIOD: Duration = Duration::from_secs(3);
+
+pub fn handle_up(ctx: &AppContext, attach: bool, no_wait: bool) -> Result<i32, anyhow::Error> {
     // Acquire lock
-    let _lock = Lock::try_acquire(ctx.root.join("config").join("portaqemu.lock"))
+    let lock = Lock::try_acquire(ctx.root.join("config").join("portaqemu.lock"))
         .map_err(|e| anyhow::anyhow!("Failed to acquire lock: {}", e))?;
     
     // Check if already running
@@ -22,69 +43,309 @@ pub fn handle_up(ctx: &AppContext, _attach: bool, no_wait: bool) -> Result<i32,
             use crate::util::process::is_process_running;
             if is_process_running(pid) {
                 println!("VM is already running (PID: {})", pid);
+                if attach {
+                    drop(lock);
+                    attach_console(&ctx.root, &state, false)?;
+                }
                 return Ok(0);
             }
         }
     }
     
     // Load config
-    let config = load_config(&ctx.config_path, &ctx.root)?;
+    let mut config = load_config(&ctx.config_path, &ctx.root)?;
+    let auto_ssh_port = config.network.ssh_host_port == AUTO_PORT;
+    
+    // Pick "auto" ports, then check the rest
+    let reserved = assign_host_ports(&mut config)?;
+    check_host_ports(&config, &reserved)?;
+    
+    let vm = launch_vm(ctx, &config, &[], reserved, &mut state)?;
+    
+    println!("VM started (PID: {})", vm.pid);
+    if auto_ssh_port {
+        println!("SSH port: {}", config.network.ssh_host_port);
+        refresh_ssh_entries(&config, &ctx.root, &mut state.pending_entries);
+        save_state(&state_path, &state)?;
+    }
+    
+    if config.network.auto_forward.enabled {
+        state.autoforward_pid = Some(spawn_background(ctx, &["forward", "watch"], "autoforward.log")?);
+        save_state(&state_path, &state)?;
+    }
+    if config.integration.enabled {
+        let chardev = config.integration.socket.to_string_lossy();
+        state.integration_pid = Some(spawn_background(ctx, &["integration-host", "--chardev", &chardev], "integration.log")?);
+        save_state(&state_path, &state)?;
+    }
+    
+    if attach {
+        drop(lock);
+        attach_console(&ctx.root, &state, false)?;
+        return Ok(0);
+    }
     
-    // Check ports
-    let mut ports_to_check = vec![config.network.ssh_host_port];
-    for forward in &config.network.forwards {
-        ports_to_check.push(forward.host);
+    // Wait for readiness if requested
+    if !no_wait {
+        if config.readiness.agent {
+            println!("Waiting for the guest agent to respond...");
+            wait_for_agent(&config.guest_agent.socket, Duration::from_secs(config.readiness.timeout_secs))?;
+            println!("Guest agent is ready");
+        } else if config.readiness.markers.is_empty() {
+            println!("Waiting for SSH to be ready...");
+            wait_for_guest_ssh(&config, &config.readiness)?;
+            pin_guest_host_keys(&config);
+        } else {
+            println!("Waiting for the serial console to show a readiness marker...");
+            let markers = compile_markers(&config.readiness.markers)?;
+            let seen = wait_for_marker(
+                &ctx.root.join("logs").join("serial.log"),
+                &markers,
+                Duration::from_secs(config.readiness.timeout_secs),
+            )?;
+            println!("Guest is ready: {}", seen);
+        }
+    }
+    
+    Ok(0)
+}
+
+/// Connect the terminal to the running VM's serial console.
+pub(crate) fn attach_console(root: &Path, state: &VmState, read_only: bool) -> Result<(), anyhow::Error> {
+    let endpoint: ConsoleEndpoint = state.console
+        .as_deref()
+        .ok_or_else(|| anyhow::anyhow!("VM was started without a serial console"))?
+        .parse()
+        .map_err(|e: String| anyhow::anyhow!(e))?;
+    let token_path = get_console_token(root);
+    let token = std::fs::read_to_string(&token_path)
+        .map_err(|e| anyhow::anyhow!("Could not read the console token {}: {}", token_path.display(), e))?;
+    if read_only {
+        println!("Watching the serial console (read-only); press {} to detach", DETACH_KEY);
+    } else {
+        println!("Attached to the serial console; press {} to detach", DETACH_KEY);
+    }
+    attach(&endpoint, token.trim(), read_only)?;
+    println!();
+    println!("Detached; the VM is still running");
+    Ok(())
+}
+
+/// Run `portaqemu <args>` in the background, logging to `logs/<log_name>`.
+fn spawn_background(ctx: &AppContext, args: &[&str], log_name: &str) -> Result<u32, anyhow::Error> {
+    let log_file = ctx.root.join("logs").join(log_name);
+    let log = std::fs::File::create(&log_file)?;
+    let mut cmd = std::process::Command::new(std::env::current_exe()?);
+    cmd.arg("--root")
+        .arg(&ctx.root)
+        .args(args)
+        .stdin(std::process::Stdio::null())
+        .stdout(log.try_clone()?)
+        .stderr(log);
+    detach(&mut cmd);
+    Ok(cmd.spawn()?.id())
+}
+
+/// Stop a console hub whose QEMU failed to start.
+fn stop_console_hub(console: &Option<(u32, ConsoleEndpoint)>) {
+    if let Some((pid, _)) = console {
+        let _ = kill_process(*pid);
+    }
+}
+
+/// Start the console hub for a freshly spawned QEMU, returning its PID and viewer endpoint.
+fn spawn_console_hub(ctx: &AppContext, config: &ResolvedConfig) -> Result<(u32, ConsoleEndpoint), anyhow::Error> {
+    let listen = match config.serial.console {
+        ConsoleTransport::Tcp => ConsoleEndpoint::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, config.serial.console_port))),
+        ConsoleTransport::Unix => ConsoleEndpoint::Unix(get_console_socket(&ctx.root)),
+    };
+    // A fresh token per hub; viewers read it from the owner-only run directory
+    write_private(&get_console_token(&ctx.root), format!("{}\n", new_nonce()).as_bytes())?;
+    let pid = spawn_background(
+        ctx,
+        &["console-hub", "--chardev", &config.serial.socket.to_string_lossy(), "--listen", &listen.to_string()],
+        "console.log",
+    )?;
+    Ok((pid, listen))
+}
+
+/// Wait for the guest's sshd, printing a line whenever the probe result changes.
+pub(crate) fn wait_for_guest_ssh(config: &ResolvedConfig, readiness: &ReadinessConfig) -> Result<(), anyhow::Error> {
+    let mut last = String::new();
+    let banner = wait_for_ssh(config, readiness, |progress| {
+        let message = progress.error.to_string();
+        if message != last {
+            println!("  {} ({}s)", message, progress.elapsed.as_secs());
+            last = message;
+        }
+    })?;
+    println!("SSH is ready: {}", banner);
+    Ok(())
+}
+
+/// Rewrite an installed Terminal fragment and SSH config block with the chosen SSH
+/// port, and write the ones requested while it was unknown.
+pub(crate) fn refresh_ssh_entries(config: &ResolvedConfig, root: &Path, pending: &mut PendingEntries) {
+    if pending.terminal_fragment || get_fragment_file(&config.vm.name).exists() {
+        match install_fragment(config, root) {
+            Ok(path) if pending.terminal_fragment => {
+                println!("Terminal fragment installed: {}", path.to_string_lossy());
+                pending.terminal_fragment = false;
+            }
+            Ok(_) => {}
+            Err(e) => println!("Warning: could not update terminal fragment: {}", e),
+        }
+    }
+    match ssh_config_installed() {
+        Ok(installed) if installed || pending.ssh_config => match install_ssh_config(config) {
+            Ok(()) if pending.ssh_config => {
+                println!("VS Code SSH config installed");
+                pending.ssh_config = false;
+            }
+            Ok(()) => {}
+            Err(e) => println!("Warning: could not update SSH config: {}", e),
+        },
+        Ok(_) => {}
+        Err(e) => println!("Warning: could not read SSH config: {}", e),
+    }
+}
+
+/// Change which SSH entries `up` writes once the `"auto"` SSH port is chosen.
+pub(crate) fn update_pending_entries(ctx: &AppContext, update: impl FnOnce(&mut PendingEntries)) -> Result<(), anyhow::Error> {
+    let _lock = Lock::try_acquire(ctx.root.join("config").join("portaqemu.lock"))
+        .map_err(|e| anyhow::anyhow!("Failed to acquire lock: {}", e))?;
+    let state_path = ctx.root.join("config").join("state.json");
+    let mut state = load_state(&state_path)?;
+    update(&mut state.pending_entries);
+    save_state(&state_path, &state)?;
+    Ok(())
+}
+
+/// Pin the guest host keys on first boot; failures only warn.
+pub(crate) fn pin_guest_host_keys(config: &ResolvedConfig) {
+    match pin_host_keys(config) {
+        Ok(pin) if pin.newly_pinned => {
+            for key in &pin.keys {
+                println!("Pinned host key: {} {}", key.key_type, key.fingerprint());
+            }
+        }
+        Ok(_) => {}
+        Err(e) => println!("Warning: could not pin host keys: {} (run: portaqemu keys trust)", e),
     }
-    check_ports_available(&ports_to_check)?;
+}
+
+/// Locate QEMU, build the command line (plus `extra_args`) and spawn it,
+/// retrying with TCG if WHPX fails in auto mode. The outcome is saved to `state`.
+pub(crate) fn launch_vm(
+    ctx: &AppContext,
+    config: &ResolvedConfig,
+    extra_args: &[OsString],
+    reserved: Vec<ReservedPort>,
+    state: &mut VmState,
+) -> Result<RunningVm, anyhow::Error> {
+    let state_path = ctx.root.join("config").join("state.json");
     
     // Locate QEMU
-    let qemu_path = locate_qemu(&ctx.root)?;
+    let qemu_path = locate_qemu(&ctx.root, &config.qemu)?;
+    
+    // Detect capabilities
+    let caps = detect_caps(&qemu_path, &ctx.root.join("config").join("qemu-caps.json"))?;
+    validate_caps(config, &caps)?;
     
     // Detect acceleration
-    let availability = detect_available_accels(&qemu_path);
+    let availability = accels_from_caps(&caps);
     let mut accel = choose_accel(config.accel.preferred, &availability)?;
     
-    // Build argv
-    let mut argv = build_argv(&config, &qemu_path, accel);
+    // Integration secret, handed to the guest over fw_cfg and the seed
+    if config.integration.enabled {
+        Secret::load(&config.integration.secret_file)?;
+    }
     
-    // Spawn QEMU
+    // Provisioning seed
+    if let Some(provision) = &config.provision {
+        if provision.auto {
+            let seed = ensure_seed(config, false)?;
+            if seed.regenerated {
+                println!("Provisioning seed written: {}", seed.path.to_string_lossy());
+            }
+        } else if !provision.seed.exists() {
+            anyhow::bail!(
+                "Provisioning seed not found: {} (run: portaqemu seed)",
+                provision.seed.to_string_lossy()
+            );
+        }
+    }
+    
+    // QMP and the chardevs listen on sockets only the current user can reach
+    let monitor = get_monitor_socket(&ctx.root);
+    create_private_dir(&get_run_dir(&ctx.root))?;
+    for socket in [&monitor, &config.serial.socket, &config.guest_agent.socket, &config.integration.socket] {
+        check_socket_path(socket)?;
+    }
+    
+    // Build argv, with a QMP monitor for runtime changes
+    let mut argv = launch_argv(config, &caps, accel, extra_args, &monitor)?;
+    
+    // Spawn QEMU; the console hub starts right away so early boot output is logged
     let log_file = ctx.root.join("logs").join("qemu.log");
+    if config.serial.enabled {
+        std::fs::File::create(ctx.root.join("logs").join("serial.log"))?;
+    }
+    // Free the "auto" picks only now, for QEMU and the console hub to bind
+    drop(reserved);
     let mut vm = spawn_qemu(&qemu_path, &argv, &log_file)?;
+    let mut console = config.serial.enabled.then(|| spawn_console_hub(ctx, config)).transpose()?;
+    let mut startup = watch_startup(&mut vm, &log_file, ACCEL_GRACE_PERIOD);
     
     // If auto mode and WHPX fails, retry with TCG
-    if config.accel.preferred == crate::config::schema::AccelPreferred::Auto
-        && accel == crate::qemu::accel::AccelChoice::Whpx
-    {
-        if is_accel_failure(&qemu_path, &argv, Duration::from_secs(3)) {
+    if let Err(e) = &startup {
+        if e.is_accel_failure()
+            && config.accel.preferred == crate::config::schema::AccelPreferred::Auto
+            && accel == crate::qemu::accel::AccelChoice::Whpx
+        {
+            tracing::warn!("{}", e);
             println!("WHPX failed, retrying with TCG...");
             accel = crate::qemu::accel::AccelChoice::Tcg;
-            argv = build_argv(&config, &qemu_path, accel);
-            
-            // Kill the failed process
-            use crate::util::process::kill_process;
-            let _ = kill_process(vm.pid);
-            
-            // Retry spawn
+            argv = launch_argv(config, &caps, accel, extra_args, &monitor)?;
+            // The first hub may still be retrying the old chardev; only one hub may own it
+            stop_console_hub(&console);
             vm = spawn_qemu(&qemu_path, &argv, &log_file)?;
+            console = config.serial.enabled.then(|| spawn_console_hub(ctx, config)).transpose()?;
+            startup = watch_startup(&mut vm, &log_file, ACCEL_GRACE_PERIOD);
         }
     }
     
+    if let Err(e) = startup {
+        stop_console_hub(&console);
+        state.running = false;
+        state.qemu_pid = None;
+        state.last_error = Some(e.to_string());
+        save_state(&state_path, state)?;
+        return Err(e.into());
+    }
+    
     // Update state
     state.running = true;
     state.qemu_pid = Some(vm.pid);
     state.started_at = Some(now_iso());
     state.qemu_args_hash = Some(hash_argv(&argv));
     state.last_error = None;
-    save_state(&state_path, &state)?;
-    
-    println!("VM started (PID: {})", vm.pid);
+    state.shares = config.shares
+        .iter()
+        .map(|share| ActiveShare {
+            tag: share.tag.clone(),
+            host_path: share.host_path.to_string_lossy().to_string(),
+            read_only: share.read_only,
+            mount_command: guest_mount_command(share, config.vm.guest_os, &config.network),
+        })
+        .collect();
+    state.monitor = Some(monitor);
+    state.ssh_host_port = Some(config.network.ssh_host_port);
+    state.console_pid = console.as_ref().map(|(pid, _)| *pid);
+    state.console = console.map(|(_, endpoint)| endpoint.to_string());
+    state.guest_agent_socket = config.guest_agent.enabled.then(|| config.guest_agent.socket.clone());
+    state.forwards = config.network.forwards.iter().map(ActiveForward::from).collect();
+    save_state(&state_path, state)?;
     
-    // Wait for SSH if requested
-    if !no_wait {
-        println!("Waiting for SSH to be ready...");
-        wait_for_port("127.0.0.1", config.network.ssh_host_port, Duration::from_secs(30))?;
-        println!("SSH is ready!");
-    }
-    
-    Ok(0)
+    Ok(vm)
 }
@@ -1,5 +1,7 @@
 use crate::cli::{AppContext, VscodeSubcommand};
+use crate::cli::commands::up::update_pending_entries;
 use crate::config::load::load_config;
+use crate::util::net::AUTO_PORT;
 use crate::vscode::ssh_config::{print_ssh_config, install_ssh_config, remove_ssh_config};
 
 pub fn handle_vscode(ctx: &AppContext, subcmd: VscodeSubcommand) -> Result<i32, anyhow::Error> {
@@ -7,15 +9,22 @@ pub fn handle_vscode(ctx: &AppContext, subcmd: VscodeSubcommand) -> Result<i32,
     
     match subcmd {
         VscodeSubcommand::Print => {
-            println!("{}", print_ssh_config(&config));
+            println!("{}", print_ssh_config(&config)?);
             Ok(0)
         }
         VscodeSubcommand::Install => {
+            // An "auto" port is only chosen at `up`
+            if config.network.ssh_host_port == AUTO_PORT {
+                update_pending_entries(ctx, |pending| pending.ssh_config = true)?;
+                println!("SSH port is \"auto\" and the VM is not running; the SSH config will be written at: portaqemu up");
+                return Ok(0);
+            }
             install_ssh_config(&config)?;
             println!("VS Code SSH config installed");
             Ok(0)
         }
         VscodeSubcommand::Remove => {
+            update_pending_entries(ctx, |pending| pending.ssh_config = false)?;
             remove_ssh_config(&config)?;
             println!("VS Code SSH config removed");
             Ok(0)
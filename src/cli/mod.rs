@@ -2,7 +2,9 @@ pub mod commands;
 
 use clap::{Parser, Subcommand};
 use crate::config::paths::get_root;
+use crate::console::ConsoleEndpoint;
 use crate::output::OutputMode;
+use crate::util::net::Protocol;
 use std::path::PathBuf;
 
 #[derive(Parser)]
@@ -26,7 +28,7 @@ pub enum Commands {
     
     /// Start the VM
     Up {
-        /// Attach to VM console
+        /// Attach to the serial console (Ctrl-] detaches; the VM keeps running)
         #[arg(long)]
         attach: bool,
         /// Don't wait for SSH readiness
@@ -34,17 +36,79 @@ pub enum Commands {
         no_wait: bool,
     },
     
-    /// Stop the VM
-    Down,
+    /// Stop the VM (shuts the guest down through the guest agent when it responds)
+    Down {
+        /// Kill QEMU right away instead of asking the guest to power off
+        #[arg(long)]
+        force: bool,
+    },
     
     /// Show VM status
     Status,
     
-    /// SSH commands
+    /// Attach to the running VM's serial console (Ctrl-] detaches)
+    Console {
+        /// Watch only; never send input to the guest
+        #[arg(long)]
+        read_only: bool,
+        /// Start the VM first if it is not running
+        #[arg(long)]
+        start: bool,
+    },
+    
+    /// SSH into the VM, or run a remote command: portaqemu ssh -- make test
     Ssh {
-        /// Execute SSH command
+        /// Open an interactive session instead of printing the ssh command
         #[arg(long)]
         exec: bool,
+        /// Extra ssh option (`Key=Value` is passed as `-o Key=Value`, `-x` flags as-is)
+        #[arg(long = "ssh-opt", allow_hyphen_values = true)]
+        ssh_opt: Vec<String>,
+        /// Force pseudo-terminal allocation
+        #[arg(short = 't', conflicts_with = "no_tty")]
+        tty: bool,
+        /// Disable pseudo-terminal allocation
+        #[arg(short = 'T')]
+        no_tty: bool,
+        /// Wait until the VM is up and SSH is reachable
+        #[arg(long)]
+        wait: bool,
+        /// Remote command to run
+        #[arg(last = true)]
+        command: Vec<String>,
+    },
+    
+    /// Run a command through the guest agent, no SSH needed: portaqemu exec -- /bin/uname -a
+    Exec {
+        /// Seconds to wait for the command to finish
+        #[arg(long, default_value_t = 60)]
+        timeout: u64,
+        /// Program (full path) and arguments; no shell is involved
+        #[arg(last = true, required = true)]
+        command: Vec<String>,
+    },
+    
+    /// Copy files between host and guest: portaqemu cp ./build.zip vm:/tmp/
+    Cp {
+        /// Source path (prefix guest paths with vm:)
+        src: String,
+        /// Destination path (prefix guest paths with vm:)
+        dst: String,
+        /// Copy directories recursively
+        #[arg(short, long)]
+        recursive: bool,
+    },
+    
+    /// Runtime port forwards
+    Forward {
+        #[command(subcommand)]
+        subcmd: ForwardSubcommand,
+    },
+    
+    /// SSH key management
+    Keys {
+        #[command(subcommand)]
+        subcmd: KeysSubcommand,
     },
     
     /// Terminal fragment management
@@ -73,6 +137,107 @@ pub enum Commands {
     
     /// Run diagnostics
     Doctor,
+    
+    /// Create the disk and run an unattended Windows install
+    Install {
+        /// Windows installation ISO
+        #[arg(long)]
+        iso: PathBuf,
+        /// Overwrite an existing disk image
+        #[arg(long)]
+        force: bool,
+    },
+    
+    /// Build the cloud-init NoCloud seed image
+    Seed {
+        /// Rebuild even if the inputs are unchanged
+        #[arg(long)]
+        force: bool,
+    },
+    
+    /// Show the QEMU command line `up` would run
+    Argv {
+        /// Print as a single copy-pasteable command line
+        #[arg(long)]
+        print: bool,
+    },
+    
+    /// QEMU binary management
+    Qemu {
+        #[command(subcommand)]
+        subcmd: QemuSubcommand,
+    },
+    
+    /// Serve the host side of the integration channel (started by `up`)
+    #[command(hide = true)]
+    IntegrationHost {
+        /// QEMU's integration chardev socket
+        #[arg(long)]
+        chardev: PathBuf,
+    },
+    
+    /// Relay and log the serial console (started by `up`)
+    #[command(hide = true)]
+    ConsoleHub {
+        /// QEMU's serial chardev socket
+        #[arg(long)]
+        chardev: PathBuf,
+        /// Address viewers attach on (`HOST:PORT` or `unix:PATH`)
+        #[arg(long)]
+        listen: ConsoleEndpoint,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum ForwardSubcommand {
+    /// Forward a host port to the guest: portaqemu forward add 8080:80
+    Add {
+        /// HOST_PORT:GUEST_PORT
+        spec: String,
+        #[arg(long, default_value = "tcp")]
+        proto: Protocol,
+        /// Name shown in `forward list`
+        #[arg(long)]
+        name: Option<String>,
+        /// Also add the forward to network.forwards
+        #[arg(long)]
+        persist: bool,
+    },
+    /// Remove the forward for a host port
+    Remove {
+        host: u16,
+        #[arg(long, default_value = "tcp")]
+        proto: Protocol,
+        /// Also remove the forward from network.forwards
+        #[arg(long)]
+        persist: bool,
+    },
+    /// List forwards active in the running VM
+    List,
+    /// Forward guest ports as services start listening (network.auto_forward)
+    Watch,
+}
+
+#[derive(Subcommand)]
+pub enum KeysSubcommand {
+    /// Generate an ed25519 keypair at vscode.identity_file
+    Generate {
+        /// Overwrite an existing key
+        #[arg(long)]
+        force: bool,
+    },
+    /// Show the public key and fingerprint
+    Show,
+    /// Replace the keypair, updating the running guest
+    Rotate,
+    /// Install the public key into the guest's authorized keys
+    Push,
+    /// Pin the guest host keys in the VM's known_hosts file
+    Trust {
+        /// Forget pinned keys first (after re-imaging the VM)
+        #[arg(long)]
+        reset: bool,
+    },
 }
 
 #[derive(Subcommand)]
@@ -101,6 +266,30 @@ pub enum AutostartSubcommand {
     Status,
 }
 
+#[derive(Subcommand)]
+pub enum QemuSubcommand {
+    /// Install a QEMU distribution zip into bin/qemu/<version>/
+    Install {
+        /// Path to the QEMU zip archive
+        zip: PathBuf,
+        /// Expected SHA-256 of the archive
+        #[arg(long)]
+        sha256: Option<String>,
+    },
+    /// List discovered QEMU binaries
+    List,
+}
+
+/// Arguments of `portaqemu ssh`.
+pub struct SshArgs {
+    pub exec: bool,
+    pub ssh_opt: Vec<String>,
+    pub tty: bool,
+    pub no_tty: bool,
+    pub wait: bool,
+    pub command: Vec<String>,
+}
+
 pub struct AppContext {
     pub root: PathBuf,
     pub config_path: PathBuf,
@@ -135,14 +324,28 @@ pub fn run(cli: Cli) -> Result<i32, anyhow::Error> {
     match cli.command {
         Init => commands::handle_init(&ctx),
         Up { attach, no_wait } => commands::handle_up(&ctx, attach, no_wait),
-        Down => commands::handle_down(&ctx),
+        Down { force } => commands::handle_down(&ctx, force),
         Status => commands::handle_status(&ctx),
-        Ssh { exec } => commands::handle_ssh(&ctx, exec),
+        Console { read_only, start } => commands::handle_console(&ctx, read_only, start),
+        Ssh { exec, ssh_opt, tty, no_tty, wait, command } => commands::handle_ssh(
+            &ctx,
+            SshArgs { exec, ssh_opt, tty, no_tty, wait, command },
+        ),
+        Exec { timeout, command } => commands::handle_exec(&ctx, timeout, &command),
+        Cp { src, dst, recursive } => commands::handle_cp(&ctx, &src, &dst, recursive),
+        Forward { subcmd } => commands::handle_forward(&ctx, subcmd),
+        Keys { subcmd } => commands::handle_keys(&ctx, subcmd),
         Terminal { subcmd } => commands::handle_terminal(&ctx, subcmd),
         Vscode { subcmd } => commands::handle_vscode(&ctx, subcmd),
         Enable => commands::handle_enable(&ctx),
         Disable => commands::handle_disable(&ctx),
         Autostart { subcmd } => commands::handle_autostart(&ctx, subcmd),
         Doctor => commands::handle_doctor(&ctx),
+        Install { iso, force } => commands::handle_install(&ctx, &iso, force),
+        Seed { force } => commands::handle_seed(&ctx, force),
+        Argv { print } => commands::handle_argv(&ctx, print),
+        Qemu { subcmd } => commands::handle_qemu(&ctx, subcmd),
+        ConsoleHub { chardev, listen } => commands::handle_console_hub(&ctx, chardev, listen),
+        IntegrationHost { chardev } => commands::handle_integration_host(&ctx, chardev),
     }
 }
@@ -0,0 +1,153 @@
+// Small textual edits to the config file, keeping the user's comments and layout.
+
+use crate::config::schema::{Config, PortForward};
+use crate::util::fs_atomic;
+use crate::util::net::Protocol;
+use std::fmt::Write;
+use std::fs;
+use std::net::Ipv4Addr;
+use std::path::Path;
+use thiserror::Error;
+
+const FORWARDS_HEADER: &str = "[[network.forwards]]";
+
+#[derive(Error, Debug)]
+pub enum ConfigEditError {
+    #[error("Failed to read config file: {0}")]
+    Read(#[from] std::io::Error),
+    #[error("Failed to write config file: {0}")]
+    Write(#[from] fs_atomic::AtomicWriteError),
+    #[error("Edited config does not parse: {0}")]
+    Parse(#[from] toml::de::Error),
+    #[error("network.forwards is an inline array; edit the config file by hand")]
+    InlineForwards,
+    #[error("No {0} forward for host port {1} in config")]
+    ForwardNotFound(Protocol, u16),
+}
+
+/// Append a `[[network.forwards]]` entry.
+pub fn add_forward(config_path: &Path, forward: &PortForward) -> Result<(), ConfigEditError> {
+    let contents = fs::read_to_string(config_path)?;
+
+    // An empty `forwards = []` would conflict with an array-of-tables entry
+    let mut lines = Vec::new();
+    for line in contents.lines() {
+        let compact: String = line.chars().filter(|c| !c.is_whitespace()).collect();
+        if compact == "forwards=[]" {
+            continue;
+        }
+        if compact.starts_with("forwards=") {
+            return Err(ConfigEditError::InlineForwards);
+        }
+        lines.push(line);
+    }
+
+    let mut edited = lines.join("\n");
+    let _ = write!(edited, "\n\n{}\nhost = {}\nguest = {}\n", FORWARDS_HEADER, forward.host, forward.guest);
+    if forward.proto != Protocol::Tcp {
+        let _ = writeln!(edited, "proto = \"{}\"", forward.proto);
+    }
+    if forward.bind != Ipv4Addr::LOCALHOST {
+        let _ = writeln!(edited, "bind = \"{}\"", forward.bind);
+    }
+    if let Some(guest_addr) = forward.guest_addr {
+        let _ = writeln!(edited, "guest_addr = \"{}\"", guest_addr);
+    }
+    if let Some(name) = &forward.name {
+        let _ = writeln!(edited, "name = \"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""));
+    }
+
+    write_checked(config_path, &edited)
+}
+
+/// Remove the `[[network.forwards]]` entry for a host port.
+pub fn remove_forward(config_path: &Path, proto: Protocol, host: u16) -> Result<(), ConfigEditError> {
+    let contents = fs::read_to_string(config_path)?;
+    let lines: Vec<&str> = contents.lines().collect();
+
+    let mut kept = Vec::new();
+    let mut removed = false;
+    let mut i = 0;
+    while i < lines.len() {
+        if lines[i].trim() != FORWARDS_HEADER {
+            kept.push(lines[i]);
+            i += 1;
+            continue;
+        }
+
+        // The entry runs until the next table header
+        let end = (i + 1..lines.len())
+            .find(|&j| lines[j].trim_start().starts_with('['))
+            .unwrap_or(lines.len());
+        let body = lines[i + 1..end].join("\n");
+        let matches = toml::from_str::<PortForward>(&body)
+            .map(|f| f.proto == proto && f.host == host)
+            .unwrap_or(false);
+        if matches && !removed {
+            removed = true;
+        } else {
+            kept.extend_from_slice(&lines[i..end]);
+        }
+        i = end;
+    }
+
+    if !removed {
+        return Err(ConfigEditError::ForwardNotFound(proto, host));
+    }
+    write_checked(config_path, &format!("{}\n", kept.join("\n").trim_end()))
+}
+
+fn write_checked(config_path: &Path, contents: &str) -> Result<(), ConfigEditError> {
+    toml::from_str::<Config>(contents)?;
+    fs_atomic::atomic_write_str(config_path, contents)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r#"[vm]
+name = "devvm"
+disk = "%ROOT%/vm/disk.qcow2"
+memory_mb = 4096
+cpus = 4
+
+[network]
+ssh_host_port = 2222
+forwards = []
+
+[accel]
+preferred = "auto"
+
+[terminal]
+profile_name = "PortaQEMU Dev VM"
+icon = "%ROOT%/bin/icon.ico"
+mode = "ssh"
+
+[vscode]
+ssh_user = "dev"
+identity_file = "%ROOT%/config/ssh/id_ed25519"
+"#;
+
+    #[test]
+    fn test_add_and_remove_forward() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("portaqemu.toml");
+        fs::write(&path, CONFIG).unwrap();
+
+        let forward: PortForward = toml::from_str("host = 8080\nguest = 80\nproto = \"udp\"").unwrap();
+        add_forward(&path, &forward).unwrap();
+        let config: Config = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(config.network.forwards.len(), 1);
+        assert_eq!(config.network.forwards[0].proto, Protocol::Udp);
+
+        assert!(matches!(
+            remove_forward(&path, Protocol::Tcp, 8080),
+            Err(ConfigEditError::ForwardNotFound(Protocol::Tcp, 8080))
+        ));
+        remove_forward(&path, Protocol::Udp, 8080).unwrap();
+        let config: Config = toml::from_str(&fs::read_to_string(&path).unwrap()).unwrap();
+        assert!(config.network.forwards.is_empty());
+    }
+}
@@ -1,6 +1,12 @@
 use crate::config::schema::*;
+use crate::config::paths::{
+    get_guest_agent_socket, get_integration_secret_file, get_integration_socket, get_known_hosts_file,
+    get_serial_socket,
+};
 use crate::config::vars::resolve_vars;
 use crate::config::validate::validate_config;
+use crate::state::{load_state, StateError};
+use crate::util::process::is_process_running;
 use std::fs;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
@@ -15,10 +21,20 @@ pub enum ConfigLoadError {
     Var(#[from] crate::config::vars::VarError),
     #[error("Validation error: {0}")]
     Validation(#[from] crate::config::validate::ValidationError),
+    #[error("Failed to read VM state: {0}")]
+    State(#[from] StateError),
 }
 
 /// Load and resolve configuration from a TOML file.
 pub fn load_config<P: AsRef<Path>>(config_path: P, root: &Path) -> Result<ResolvedConfig, ConfigLoadError> {
+    let resolved = load_config_unvalidated(config_path, root)?;
+    validate_config(&resolved)?;
+    Ok(resolved)
+}
+
+/// Load and resolve configuration without validating it.
+/// Used by commands that create the files validation expects (e.g. the disk).
+pub fn load_config_unvalidated<P: AsRef<Path>>(config_path: P, root: &Path) -> Result<ResolvedConfig, ConfigLoadError> {
     let config_path = config_path.as_ref();
     let contents = fs::read_to_string(config_path)?;
     let config: Config = toml::from_str(&contents)?;
@@ -47,12 +63,68 @@ pub fn load_config<P: AsRef<Path>>(config_path: P, root: &Path) -> Result<Resolv
         root.join(identity_path)
     };
     
-    let resolved = ResolvedConfig {
+    let known_hosts_file = match &config.vscode.known_hosts_file {
+        Some(path) => resolve_path(path, root)?,
+        None => get_known_hosts_file(root, &config.vm.name),
+    };
+    
+    let integration = ResolvedIntegrationConfig {
+        enabled: config.integration.enabled,
+        socket: get_integration_socket(root),
+        secret_file: match &config.integration.secret_file {
+            Some(path) => resolve_path(path, root)?,
+            None => get_integration_secret_file(root, &config.vm.name),
+        },
+    };
+    
+    let qemu_path = config.qemu.path
+        .as_deref()
+        .map(|p| resolve_path(p, root))
+        .transpose()?;
+    
+    let shares = config.shares
+        .iter()
+        .map(|share| -> Result<_, ConfigLoadError> {
+            Ok(ResolvedShareConfig {
+                host_path: resolve_path(&share.host_path, root)?,
+                tag: share.tag.clone(),
+                read_only: share.read_only,
+            })
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    
+    let provision = match config.provision {
+        Some(provision) => Some(ResolvedProvisionConfig {
+            hostname: provision.hostname.unwrap_or_else(|| config.vm.name.clone()),
+            seed: resolve_path(&provision.seed, root)?,
+            auto: provision.auto,
+            packages: provision.packages,
+        }),
+        None => None,
+    };
+    
+    let install = ResolvedInstallConfig {
+        disk_size_gb: config.install.disk_size_gb,
+        virtio_win_iso: config.install.virtio_win_iso
+            .as_deref()
+            .map(|p| resolve_path(p, root))
+            .transpose()?,
+        keyboard: config.install.keyboard.unwrap_or_else(|| config.install.locale.clone()),
+        locale: config.install.locale,
+        timezone: config.install.timezone,
+        edition: config.install.edition,
+        product_key: config.install.product_key,
+        password: config.install.password,
+        timeout_minutes: config.install.timeout_minutes,
+    };
+    
+    let mut resolved = ResolvedConfig {
         vm: ResolvedVmConfig {
             name: config.vm.name,
             disk: disk.canonicalize().unwrap_or(disk),
             memory_mb: config.vm.memory_mb,
             cpus: config.vm.cpus,
+            guest_os: config.vm.guest_os,
         },
         network: config.network,
         accel: config.accel,
@@ -64,11 +136,49 @@ pub fn load_config<P: AsRef<Path>>(config_path: P, root: &Path) -> Result<Resolv
         vscode: ResolvedVscodeConfig {
             ssh_user: config.vscode.ssh_user,
             identity_file: identity_file.canonicalize().unwrap_or(identity_file),
+            known_hosts_file,
+        },
+        qemu: ResolvedQemuConfig {
+            path: qemu_path,
+            version: config.qemu.version,
+        },
+        shares,
+        provision,
+        install,
+        readiness: config.readiness,
+        serial: ResolvedSerialConfig {
+            enabled: config.serial.enabled,
+            socket: get_serial_socket(root),
+            console: config.serial.console,
+            console_port: config.serial.console_port,
+        },
+        guest_agent: ResolvedGuestAgentConfig {
+            enabled: config.guest_agent.enabled,
+            socket: get_guest_agent_socket(root),
         },
+        integration,
     };
     
-    // Validate
-    validate_config(&resolved)?;
+    if resolved.network.has_auto_ports() {
+        apply_running_ports(&mut resolved, root)?;
+    }
     
     Ok(resolved)
 }
+
+/// Use the ports picked for `"auto"` entries while the VM is running, so every
+/// command connects to the actual port.
+fn apply_running_ports(config: &mut ResolvedConfig, root: &Path) -> Result<(), ConfigLoadError> {
+    let state = load_state(root.join("config").join("state.json"))?;
+    if state.qemu_pid.is_some_and(is_process_running) {
+        state.apply_ports(config);
+    }
+    Ok(())
+}
+
+/// Resolve variables in a path setting and make it absolute relative to root.
+fn resolve_path(input: &str, root: &Path) -> Result<PathBuf, crate::config::vars::VarError> {
+    let path = PathBuf::from(resolve_vars(input, root)?);
+    let path = if path.is_absolute() { path } else { root.join(path) };
+    Ok(path.canonicalize().unwrap_or(path))
+}
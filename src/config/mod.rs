@@ -3,6 +3,7 @@ pub mod vars;
 pub mod load;
 pub mod validate;
 pub mod paths;
+pub mod edit;
 
 pub use schema::*;
 pub use load::*;
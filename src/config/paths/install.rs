@@ -0,0 +1,6 @@
+use std::path::{Path, PathBuf};
+
+/// Where `install` saves a generated account password, readable only by the current user.
+pub fn get_install_password_file(root: &Path, vm_name: &str) -> PathBuf {
+    root.join("config").join(format!("install_password_{}.txt", vm_name))
+}
@@ -0,0 +1,6 @@
+use std::path::{Path, PathBuf};
+
+/// Get the integration channel secret for a VM.
+pub fn get_integration_secret_file(root: &Path, vm_name: &str) -> PathBuf {
+    root.join("config").join(format!("integration_{}.key", vm_name))
+}
@@ -2,6 +2,14 @@ pub mod root;
 pub mod terminal_fragments;
 pub mod vscode;
 pub mod ssh;
+pub mod integration;
+pub mod run;
+pub mod install;
 
 pub use root::*;
 pub use terminal_fragments::*;
+pub use vscode::*;
+pub use ssh::*;
+pub use integration::*;
+pub use run::*;
+pub use install::*;
@@ -0,0 +1,46 @@
+use std::path::{Path, PathBuf};
+
+/// Owner-only directory holding the running VM's QMP and chardev sockets.
+pub fn get_run_dir(root: &Path) -> PathBuf {
+    root.join("run")
+}
+
+/// QMP monitor socket.
+pub fn get_monitor_socket(root: &Path) -> PathBuf {
+    get_run_dir(root).join("qmp.sock")
+}
+
+/// Serial console chardev socket, relayed by the console hub.
+pub fn get_serial_socket(root: &Path) -> PathBuf {
+    get_run_dir(root).join("serial.sock")
+}
+
+/// Guest agent chardev socket.
+pub fn get_guest_agent_socket(root: &Path) -> PathBuf {
+    get_run_dir(root).join("qga.sock")
+}
+
+/// Integration channel chardev socket.
+pub fn get_integration_socket(root: &Path) -> PathBuf {
+    get_run_dir(root).join("integration.sock")
+}
+
+/// Where `unix` console viewers attach to the console hub.
+pub fn get_console_socket(root: &Path) -> PathBuf {
+    get_run_dir(root).join("console.sock")
+}
+
+/// Token console viewers send before the hub relays anything, rewritten by each `up`.
+pub fn get_console_token(root: &Path) -> PathBuf {
+    get_run_dir(root).join("console.token")
+}
+
+/// Socket of the integration host's relay, for `unix` relay endpoints.
+pub fn get_integration_relay_socket(root: &Path) -> PathBuf {
+    get_run_dir(root).join("integration-relay.sock")
+}
+
+/// Endpoint the running integration host's relay listens on, written when it starts.
+pub fn get_integration_relay_file(root: &Path) -> PathBuf {
+    get_run_dir(root).join("integration-relay")
+}
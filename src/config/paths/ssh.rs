@@ -1,11 +1,23 @@
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// Get SSH directory path within PortaQEMU root.
-pub fn get_ssh_dir(root: &PathBuf) -> PathBuf {
+pub fn get_ssh_dir(root: &Path) -> PathBuf {
     root.join("config").join("ssh")
 }
 
 /// Get SSH identity file path.
-pub fn get_identity_file(root: &PathBuf, name: &str) -> PathBuf {
+pub fn get_identity_file(root: &Path, name: &str) -> PathBuf {
     get_ssh_dir(root).join(name)
 }
+
+/// Get the pinned known_hosts file for a VM.
+pub fn get_known_hosts_file(root: &Path, vm_name: &str) -> PathBuf {
+    get_ssh_dir(root).join(format!("known_hosts_{}", vm_name))
+}
+
+/// Path of the public half of an identity file (`<identity>.pub`).
+pub fn public_key_path(identity_file: &Path) -> PathBuf {
+    let mut path = identity_file.as_os_str().to_os_string();
+    path.push(".pub");
+    PathBuf::from(path)
+}
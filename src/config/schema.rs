@@ -1,4 +1,6 @@
-use serde::{Deserialize, Serialize};
+use crate::util::net::{deserialize_host_port, reserve_port, NetError, PortBinding, PortRange, Protocol, ReservedPort, AUTO_PORT};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr};
 use std::path::PathBuf;
 
 #[derive(Debug, Clone, Deserialize)]
@@ -8,6 +10,21 @@ pub struct Config {
     pub accel: AccelConfig,
     pub terminal: TerminalConfig,
     pub vscode: VscodeConfig,
+    #[serde(default)]
+    pub qemu: QemuConfig,
+    #[serde(default)]
+    pub shares: Vec<ShareConfig>,
+    pub provision: Option<ProvisionConfig>,
+    #[serde(default)]
+    pub install: InstallConfig,
+    #[serde(default)]
+    pub readiness: ReadinessConfig,
+    #[serde(default)]
+    pub serial: SerialConfig,
+    #[serde(default)]
+    pub guest_agent: GuestAgentConfig,
+    #[serde(default)]
+    pub integration: IntegrationConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -16,19 +33,170 @@ pub struct VmConfig {
     pub disk: String, // Will be resolved to PathBuf
     pub memory_mb: u32,
     pub cpus: u32,
+    #[serde(default)]
+    pub guest_os: GuestOs,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GuestOs {
+    #[default]
+    Windows,
+    Linux,
+}
+
+/// Host folder shared into the guest.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ShareConfig {
+    pub host_path: String, // Will be resolved to PathBuf
+    pub tag: String,
+    #[serde(default)]
+    pub read_only: bool,
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct NetworkConfig {
+    /// Host port forwarded to guest SSH; `"auto"` picks a free port at `up`.
+    #[serde(deserialize_with = "deserialize_host_port")]
     pub ssh_host_port: u16,
     #[serde(default)]
     pub forwards: Vec<PortForward>,
+    /// Isolate the guest from the host network (only forwards remain reachable).
+    #[serde(default)]
+    pub restrict: bool,
+    /// Guest subnet in CIDR notation (QEMU default: 10.0.2.0/24).
+    pub net: Option<String>,
+    /// Host (gateway) address as seen from the guest.
+    pub host: Option<Ipv4Addr>,
+    /// Built-in DNS server address as seen from the guest.
+    pub dns: Option<Ipv4Addr>,
+    #[serde(default = "default_ipv6")]
+    pub ipv6: bool,
+    #[serde(default)]
+    pub dnssearch: Vec<String>,
+    pub domainname: Option<String>,
+    #[serde(default)]
+    pub guestfwd: Vec<GuestForward>,
+    #[serde(default)]
+    pub auto_forward: AutoForwardConfig,
+}
+
+fn default_ipv6() -> bool {
+    true
+}
+
+/// Forward TCP ports the guest starts listening on (detected over SSH).
+#[derive(Debug, Clone, Deserialize)]
+pub struct AutoForwardConfig {
+    #[serde(default)]
+    pub enabled: bool,
+    #[serde(default = "default_auto_forward_interval")]
+    pub interval_secs: u64,
+    /// Guest ports to forward; empty means all.
+    #[serde(default)]
+    pub allow: Vec<PortRange>,
+    /// Guest ports never forwarded (checked after `allow`).
+    #[serde(default)]
+    pub deny: Vec<PortRange>,
+}
+
+fn default_auto_forward_interval() -> u64 {
+    5
+}
+
+impl Default for AutoForwardConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            interval_secs: default_auto_forward_interval(),
+            allow: Vec::new(),
+            deny: Vec::new(),
+        }
+    }
+}
+
+impl AutoForwardConfig {
+    /// Whether a guest port passes the allow/deny lists.
+    pub fn permits(&self, port: u16) -> bool {
+        let allowed = self.allow.is_empty() || self.allow.iter().any(|r| r.contains(port));
+        allowed && !self.deny.iter().any(|r| r.contains(port))
+    }
+}
+
+/// Default user-mode network when `network.net` is not set.
+pub const DEFAULT_USER_NET: &str = "10.0.2.0/24";
+
+impl NetworkConfig {
+    /// Host-side bindings needed by the SSH forward and all configured forwards.
+    pub fn host_bindings(&self) -> Vec<PortBinding> {
+        let mut bindings = vec![PortBinding::tcp_loopback(self.ssh_host_port)];
+        for forward in &self.forwards {
+            bindings.push(PortBinding {
+                proto: forward.proto,
+                addr: IpAddr::V4(forward.bind),
+                port: forward.host,
+            });
+        }
+        bindings
+    }
+
+    /// Whether the SSH port or any forward uses `"auto"`.
+    pub fn has_auto_ports(&self) -> bool {
+        self.ssh_host_port == AUTO_PORT || self.forwards.iter().any(|f| f.host == AUTO_PORT)
+    }
+
+    /// Replace `"auto"` host ports with free ports. Each pick stays bound until the
+    /// returned reservations are dropped, so later picks can't repeat it; hold them
+    /// until just before QEMU starts.
+    pub fn assign_auto_ports(&mut self) -> Result<Vec<ReservedPort>, NetError> {
+        let mut reserved = Vec::new();
+        if self.ssh_host_port == AUTO_PORT {
+            let port = reserve_port(Protocol::Tcp, Ipv4Addr::LOCALHOST.into())?;
+            self.ssh_host_port = port.port;
+            reserved.push(port);
+        }
+        for forward in &mut self.forwards {
+            if forward.host == AUTO_PORT {
+                let port = reserve_port(forward.proto, forward.bind.into())?;
+                forward.host = port.port;
+                reserved.push(port);
+            }
+        }
+        Ok(reserved)
+    }
 }
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct PortForward {
+    /// Host port; `"auto"` picks a free port at `up`.
+    #[serde(deserialize_with = "deserialize_host_port")]
     pub host: u16,
     pub guest: u16,
+    #[serde(default)]
+    pub proto: Protocol,
+    /// Host address to listen on; non-loopback requires `allow_external`.
+    #[serde(default = "default_forward_bind")]
+    pub bind: Ipv4Addr,
+    #[serde(default)]
+    pub allow_external: bool,
+    /// Guest address to forward to (defaults to the guest's DHCP address).
+    pub guest_addr: Option<Ipv4Addr>,
+    pub name: Option<String>,
+}
+
+fn default_forward_bind() -> Ipv4Addr {
+    Ipv4Addr::LOCALHOST
+}
+
+/// Forward guest connections to `guest_addr:guest_port` to a host service.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GuestForward {
+    pub guest_addr: Ipv4Addr,
+    pub guest_port: u16,
+    #[serde(default = "default_forward_bind")]
+    pub host_addr: Ipv4Addr,
+    pub host_port: u16,
+    pub name: Option<String>,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -72,6 +240,242 @@ pub enum TerminalMode {
 pub struct VscodeConfig {
     pub ssh_user: String,
     pub identity_file: String, // Will be resolved to PathBuf
+    /// Pinned guest host keys; defaults to `config/ssh/known_hosts_<vm>`.
+    #[serde(default)]
+    pub known_hosts_file: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct QemuConfig {
+    pub path: Option<String>, // Will be resolved to PathBuf
+    pub version: Option<String>,
+}
+
+/// First-boot provisioning through a cloud-init NoCloud seed.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ProvisionConfig {
+    /// Guest hostname (defaults to vm.name).
+    pub hostname: Option<String>,
+    #[serde(default = "default_provision_seed")]
+    pub seed: String, // Will be resolved to PathBuf
+    /// Build or refresh the seed automatically on `up`.
+    #[serde(default = "default_provision_auto")]
+    pub auto: bool,
+    #[serde(default)]
+    pub packages: Vec<String>,
+}
+
+fn default_provision_seed() -> String {
+    "%ROOT%/vm/seed.iso".to_string()
+}
+
+fn default_provision_auto() -> bool {
+    true
+}
+
+/// How `up` decides the guest is ready.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ReadinessConfig {
+    #[serde(default = "default_readiness_timeout_secs")]
+    pub timeout_secs: u64,
+    /// Also run an authenticated no-op command, not just read the SSH banner.
+    #[serde(default)]
+    pub auth: bool,
+    /// Delay after the first failed probe; doubles up to `max_backoff_ms`.
+    #[serde(default = "default_readiness_initial_backoff_ms")]
+    pub initial_backoff_ms: u64,
+    #[serde(default = "default_readiness_max_backoff_ms")]
+    pub max_backoff_ms: u64,
+    /// Regexes matched against the serial console; when set, `up` waits for one
+    /// of them instead of SSH.
+    #[serde(default)]
+    pub markers: Vec<String>,
+    /// Wait for the guest agent to answer `guest-ping` instead of SSH.
+    #[serde(default)]
+    pub agent: bool,
+}
+
+fn default_readiness_timeout_secs() -> u64 {
+    120
+}
+
+fn default_readiness_initial_backoff_ms() -> u64 {
+    250
+}
+
+fn default_readiness_max_backoff_ms() -> u64 {
+    5000
+}
+
+impl Default for ReadinessConfig {
+    fn default() -> Self {
+        Self {
+            timeout_secs: default_readiness_timeout_secs(),
+            auth: false,
+            initial_backoff_ms: default_readiness_initial_backoff_ms(),
+            max_backoff_ms: default_readiness_max_backoff_ms(),
+            markers: Vec::new(),
+            agent: false,
+        }
+    }
+}
+
+/// Guest serial console, logged to `logs/serial.log`.
+#[derive(Debug, Clone, Deserialize)]
+pub struct SerialConfig {
+    #[serde(default = "default_serial_enabled")]
+    pub enabled: bool,
+    /// How `portaqemu console` viewers reach the console.
+    #[serde(default)]
+    pub console: ConsoleTransport,
+    /// Loopback port for `tcp` viewers; `"auto"` picks a free port.
+    #[serde(default, deserialize_with = "deserialize_host_port")]
+    pub console_port: u16,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ConsoleTransport {
+    #[default]
+    Tcp,
+    /// A Unix socket at `run/console.sock` (not available on Windows).
+    Unix,
+}
+
+fn default_serial_enabled() -> bool {
+    true
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_serial_enabled(),
+            console: ConsoleTransport::default(),
+            console_port: AUTO_PORT,
+        }
+    }
+}
+
+impl ResolvedSerialConfig {
+    /// Replace an `"auto"` console port with a free one, held like
+    /// `NetworkConfig::assign_auto_ports`.
+    pub fn assign_auto_port(&mut self) -> Result<Option<ReservedPort>, NetError> {
+        if self.enabled && self.console == ConsoleTransport::Tcp && self.console_port == AUTO_PORT {
+            let port = reserve_port(Protocol::Tcp, Ipv4Addr::LOCALHOST.into())?;
+            self.console_port = port.port;
+            return Ok(Some(port));
+        }
+        Ok(None)
+    }
+
+    /// Loopback port a TCP console listens on.
+    pub fn host_bindings(&self) -> Vec<PortBinding> {
+        if self.enabled && self.console == ConsoleTransport::Tcp {
+            vec![PortBinding::tcp_loopback(self.console_port)]
+        } else {
+            Vec::new()
+        }
+    }
+}
+
+/// qemu-guest-agent channel (`org.qemu.guest_agent.0` on virtio-serial).
+#[derive(Debug, Clone, Deserialize)]
+pub struct GuestAgentConfig {
+    #[serde(default = "default_guest_agent_enabled")]
+    pub enabled: bool,
+}
+
+fn default_guest_agent_enabled() -> bool {
+    true
+}
+
+impl Default for GuestAgentConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_guest_agent_enabled(),
+        }
+    }
+}
+
+/// Host/guest integration channel (`org.portaqemu.integration` on virtio-serial).
+#[derive(Debug, Clone, Deserialize)]
+pub struct IntegrationConfig {
+    #[serde(default = "default_integration_enabled")]
+    pub enabled: bool,
+    /// Shared secret authenticating the channel; defaults to `config/integration_<vm>.key`.
+    #[serde(default)]
+    pub secret_file: Option<String>,
+}
+
+fn default_integration_enabled() -> bool {
+    true
+}
+
+impl Default for IntegrationConfig {
+    fn default() -> Self {
+        Self {
+            enabled: default_integration_enabled(),
+            secret_file: None,
+        }
+    }
+}
+
+/// Unattended Windows guest installation (`portaqemu install`).
+#[derive(Debug, Clone, Deserialize)]
+pub struct InstallConfig {
+    #[serde(default = "default_install_disk_size_gb")]
+    pub disk_size_gb: u32,
+    pub virtio_win_iso: Option<String>, // Will be resolved to PathBuf
+    #[serde(default = "default_install_locale")]
+    pub locale: String,
+    /// Input locale, e.g. "en-US" or "0409:00000409" (defaults to locale).
+    pub keyboard: Option<String>,
+    #[serde(default = "default_install_timezone")]
+    pub timezone: String,
+    /// Image name inside install.wim, e.g. "Windows 11 Pro".
+    #[serde(default = "default_install_edition")]
+    pub edition: String,
+    pub product_key: Option<String>,
+    /// Local account password (a random one is generated when unset).
+    pub password: Option<String>,
+    #[serde(default = "default_install_timeout_minutes")]
+    pub timeout_minutes: u64,
+}
+
+impl Default for InstallConfig {
+    fn default() -> Self {
+        Self {
+            disk_size_gb: default_install_disk_size_gb(),
+            virtio_win_iso: None,
+            locale: default_install_locale(),
+            keyboard: None,
+            timezone: default_install_timezone(),
+            edition: default_install_edition(),
+            product_key: None,
+            password: None,
+            timeout_minutes: default_install_timeout_minutes(),
+        }
+    }
+}
+
+fn default_install_disk_size_gb() -> u32 {
+    64
+}
+
+fn default_install_locale() -> String {
+    "en-US".to_string()
+}
+
+fn default_install_timezone() -> String {
+    "UTC".to_string()
+}
+
+fn default_install_edition() -> String {
+    "Windows 11 Pro".to_string()
+}
+
+fn default_install_timeout_minutes() -> u64 {
+    90
 }
 
 /// Resolved configuration with absolute paths.
@@ -82,6 +486,14 @@ pub struct ResolvedConfig {
     pub accel: AccelConfig,
     pub terminal: ResolvedTerminalConfig,
     pub vscode: ResolvedVscodeConfig,
+    pub qemu: ResolvedQemuConfig,
+    pub shares: Vec<ResolvedShareConfig>,
+    pub provision: Option<ResolvedProvisionConfig>,
+    pub install: ResolvedInstallConfig,
+    pub readiness: ReadinessConfig,
+    pub serial: ResolvedSerialConfig,
+    pub guest_agent: ResolvedGuestAgentConfig,
+    pub integration: ResolvedIntegrationConfig,
 }
 
 #[derive(Debug, Clone)]
@@ -90,6 +502,14 @@ pub struct ResolvedVmConfig {
     pub disk: PathBuf,
     pub memory_mb: u32,
     pub cpus: u32,
+    pub guest_os: GuestOs,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedShareConfig {
+    pub host_path: PathBuf,
+    pub tag: String,
+    pub read_only: bool,
 }
 
 #[derive(Debug, Clone)]
@@ -103,4 +523,56 @@ pub struct ResolvedTerminalConfig {
 pub struct ResolvedVscodeConfig {
     pub ssh_user: String,
     pub identity_file: PathBuf,
+    pub known_hosts_file: PathBuf,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ResolvedQemuConfig {
+    pub path: Option<PathBuf>,
+    pub version: Option<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedSerialConfig {
+    pub enabled: bool,
+    /// QEMU's serial chardev, in the owner-only run directory.
+    pub socket: PathBuf,
+    pub console: ConsoleTransport,
+    pub console_port: u16,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedGuestAgentConfig {
+    pub enabled: bool,
+    /// The agent's chardev, in the owner-only run directory.
+    pub socket: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedIntegrationConfig {
+    pub enabled: bool,
+    /// The channel's chardev, in the owner-only run directory.
+    pub socket: PathBuf,
+    pub secret_file: PathBuf,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedProvisionConfig {
+    pub hostname: String,
+    pub seed: PathBuf,
+    pub auto: bool,
+    pub packages: Vec<String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedInstallConfig {
+    pub disk_size_gb: u32,
+    pub virtio_win_iso: Option<PathBuf>,
+    pub locale: String,
+    pub keyboard: String,
+    pub timezone: String,
+    pub edition: String,
+    pub product_key: Option<String>,
+    pub password: Option<String>,
+    pub timeout_minutes: u64,
 }
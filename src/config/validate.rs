@@ -1,5 +1,10 @@
-use crate::config::schema::ResolvedConfig;
+use crate::config::schema::{AccelPreferred, ConsoleTransport, NetworkConfig, ResolvedConfig, DEFAULT_USER_NET};
+use crate::console::log::compile_markers;
+use crate::qemu::caps::QemuCaps;
+use crate::qemu::share::{share_transport, ShareTransport, MAX_MOUNT_TAG_LEN};
+use crate::util::net::{Ipv4Cidr, Protocol, AUTO_PORT};
 use std::fs;
+use std::net::Ipv4Addr;
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -12,23 +17,51 @@ pub enum ValidationError {
     InvalidMemory(u32),
     #[error("Invalid CPU count: {0} (must be > 0)")]
     InvalidCpus(u32),
-    #[error("Invalid SSH host port: {0} (must be 1-65535)")]
-    InvalidSshPort(u16),
-    #[error("Invalid port forward: host={0}, guest={1} (must be 1-65535)")]
+    #[error("Invalid port forward: host={0}, guest={1} (guest port must be 1-65535)")]
     InvalidPortForward(u16, u16),
-    #[error("Duplicate host port: {0}")]
-    DuplicateHostPort(u16),
+    #[error("Duplicate host port: {0}/{1}")]
+    DuplicateHostPort(u16, Protocol),
+    #[error("Forward {0} binds non-loopback address {1}; set allow_external = true to expose it")]
+    ExternalBindNotAllowed(String, String),
+    #[error("Duplicate forward name: {0}")]
+    DuplicateForwardName(String),
+    #[error("Invalid network.net: {0}")]
+    InvalidCidr(String),
+    #[error("network.{0} address {1} is not a host address in {2}")]
+    AddressOutsideNet(&'static str, String, String),
+    #[error("Address conflict: {0}")]
+    AddressConflict(String),
+    #[error("Invalid network.{0}: {1}")]
+    InvalidNetworkOption(&'static str, String),
+    #[error("Share folder not found: {0}")]
+    ShareNotFound(String),
+    #[error("Invalid share tag '{0}' (1-31 characters: letters, digits, '-' or '_')")]
+    InvalidShareTag(String),
+    #[error("Duplicate share tag: {0}")]
+    DuplicateShareTag(String),
+    #[error("Invalid provision.hostname: {0}")]
+    InvalidHostname(String),
+    #[error("Unsupported share configuration for Windows guests: {0}")]
+    UnsupportedSmbShare(&'static str),
+    #[error("{0}")]
+    InvalidMarker(String),
+    #[error("readiness.markers needs the serial console (serial.enabled = true)")]
+    MarkersNeedSerial,
+    #[error("readiness.agent needs the guest agent (guest_agent.enabled = true)")]
+    AgentReadinessNeedsAgent,
+    #[error("readiness.agent and readiness.markers cannot both be set")]
+    ConflictingReadiness,
+    #[error("serial.console = \"unix\" is not supported on this platform")]
+    UnixConsoleUnsupported,
+    #[error("qemu.path and qemu.version cannot both be set")]
+    ConflictingQemuSelection,
+    #[error("Unsupported by QEMU {version}: {what}")]
+    Unsupported { version: String, what: String },
 }
 
 /// Validate resolved configuration.
 pub fn validate_config(config: &ResolvedConfig) -> Result<(), ValidationError> {
-    // Validate VM config
-    if config.vm.memory_mb == 0 {
-        return Err(ValidationError::InvalidMemory(config.vm.memory_mb));
-    }
-    if config.vm.cpus == 0 {
-        return Err(ValidationError::InvalidCpus(config.vm.cpus));
-    }
+    validate_settings(config)?;
     
     // Validate disk
     if !config.vm.disk.exists() {
@@ -42,22 +75,250 @@ pub fn validate_config(config: &ResolvedConfig) -> Result<(), ValidationError> {
         ));
     }
     
-    // Validate network
-    if config.network.ssh_host_port == 0 {
-        return Err(ValidationError::InvalidSshPort(config.network.ssh_host_port));
+    Ok(())
+}
+
+/// Validate everything but the disk image, which `install` only creates afterwards.
+pub fn validate_settings(config: &ResolvedConfig) -> Result<(), ValidationError> {
+    // Validate VM config
+    if config.vm.memory_mb == 0 {
+        return Err(ValidationError::InvalidMemory(config.vm.memory_mb));
+    }
+    if config.vm.cpus == 0 {
+        return Err(ValidationError::InvalidCpus(config.vm.cpus));
     }
     
-    // Validate port forwards
-    let mut host_ports = vec![config.network.ssh_host_port];
+    // Validate port forwards (a host port may be used once per protocol; "auto" ports never clash)
+    let mut host_ports = vec![(Protocol::Tcp, config.network.ssh_host_port)];
+    let mut names = Vec::new();
     for forward in &config.network.forwards {
-        if forward.host == 0 || forward.guest == 0 {
+        if forward.guest == 0 {
             return Err(ValidationError::InvalidPortForward(forward.host, forward.guest));
         }
-        if host_ports.contains(&forward.host) {
-            return Err(ValidationError::DuplicateHostPort(forward.host));
+        if forward.host != AUTO_PORT {
+            if host_ports.contains(&(forward.proto, forward.host)) {
+                return Err(ValidationError::DuplicateHostPort(forward.host, forward.proto));
+            }
+            host_ports.push((forward.proto, forward.host));
+        }
+        
+        if !forward.bind.is_loopback() && !forward.allow_external {
+            let label = forward.name.clone().unwrap_or_else(|| forward.host.to_string());
+            return Err(ValidationError::ExternalBindNotAllowed(label, forward.bind.to_string()));
+        }
+        if let Some(name) = &forward.name {
+            if names.contains(&name) {
+                return Err(ValidationError::DuplicateForwardName(name.clone()));
+            }
+            names.push(name);
+        }
+    }
+    
+    validate_user_net(&config.network)?;
+    validate_shares(config)?;
+    
+    if let Some(provision) = &config.provision {
+        let hostname_ok = !provision.hostname.is_empty()
+            && provision.hostname.len() <= 63
+            && !provision.hostname.starts_with('-')
+            && provision.hostname.chars().all(|c| c.is_ascii_alphanumeric() || c == '-');
+        if !hostname_ok {
+            return Err(ValidationError::InvalidHostname(provision.hostname.clone()));
+        }
+    }
+    
+    if cfg!(not(unix)) && config.serial.console == ConsoleTransport::Unix {
+        return Err(ValidationError::UnixConsoleUnsupported);
+    }
+    if !config.readiness.markers.is_empty() {
+        if !config.serial.enabled {
+            return Err(ValidationError::MarkersNeedSerial);
+        }
+        compile_markers(&config.readiness.markers).map_err(|e| ValidationError::InvalidMarker(e.to_string()))?;
+    }
+    if config.readiness.agent {
+        if !config.guest_agent.enabled {
+            return Err(ValidationError::AgentReadinessNeedsAgent);
+        }
+        if !config.readiness.markers.is_empty() {
+            return Err(ValidationError::ConflictingReadiness);
+        }
+    }
+    
+    // Validate QEMU selection
+    if config.qemu.path.is_some() && config.qemu.version.is_some() {
+        return Err(ValidationError::ConflictingQemuSelection);
+    }
+    // A missing qemu.path is reported by `locate_qemu`, only where QEMU is needed
+    
+    Ok(())
+}
+
+/// Validate user-mode network addressing and guestfwd rules.
+fn validate_user_net(network: &NetworkConfig) -> Result<(), ValidationError> {
+    let net_str = network.net.as_deref().unwrap_or(DEFAULT_USER_NET);
+    let net: Ipv4Cidr = net_str.parse().map_err(ValidationError::InvalidCidr)?;
+    
+    let outside = |field: &'static str, addr: std::net::Ipv4Addr| {
+        ValidationError::AddressOutsideNet(field, addr.to_string(), net.to_string())
+    };
+    
+    if let Some(host) = network.host {
+        if !net.is_host(host) {
+            return Err(outside("host", host));
+        }
+    }
+    if let Some(dns) = network.dns {
+        if !net.is_host(dns) {
+            return Err(outside("dns", dns));
+        }
+    }
+    // QEMU puts the gateway and DNS at the 2nd and 3rd addresses unless told otherwise
+    let host = network.host.unwrap_or_else(|| Ipv4Addr::from(u32::from(net.network()) + 2));
+    let dns = network.dns.unwrap_or_else(|| Ipv4Addr::from(u32::from(net.network()) + 3));
+    if host == dns {
+        return Err(ValidationError::AddressConflict(format!(
+            "network.host and network.dns are both {}",
+            dns
+        )));
+    }
+    
+    for forward in &network.forwards {
+        if let Some(guest_addr) = forward.guest_addr {
+            if !net.is_host(guest_addr) {
+                return Err(outside("forwards.guest_addr", guest_addr));
+            }
+        }
+    }
+    
+    for domain in network.dnssearch.iter().chain(network.domainname.iter()) {
+        if domain.is_empty() || domain.contains(|c: char| c == ',' || c.is_whitespace()) {
+            return Err(ValidationError::InvalidNetworkOption("dnssearch/domainname", domain.clone()));
+        }
+    }
+    
+    let mut guest_endpoints = Vec::new();
+    for fwd in &network.guestfwd {
+        if fwd.guest_port == 0 || fwd.host_port == 0 {
+            return Err(ValidationError::InvalidNetworkOption(
+                "guestfwd",
+                format!("{}:{} -> {}:{}", fwd.guest_addr, fwd.guest_port, fwd.host_addr, fwd.host_port),
+            ));
+        }
+        if !net.is_host(fwd.guest_addr) {
+            return Err(outside("guestfwd.guest_addr", fwd.guest_addr));
+        }
+        if fwd.guest_addr == host || fwd.guest_addr == dns {
+            return Err(ValidationError::AddressConflict(format!(
+                "guestfwd address {} is already the guest's gateway or DNS server (network.host/network.dns)",
+                fwd.guest_addr
+            )));
+        }
+        if guest_endpoints.contains(&(fwd.guest_addr, fwd.guest_port)) {
+            return Err(ValidationError::AddressConflict(format!(
+                "duplicate guestfwd endpoint {}:{}",
+                fwd.guest_addr, fwd.guest_port
+            )));
         }
-        host_ports.push(forward.host);
+        guest_endpoints.push((fwd.guest_addr, fwd.guest_port));
     }
     
     Ok(())
 }
+
+/// Validate shared folders.
+fn validate_shares(config: &ResolvedConfig) -> Result<(), ValidationError> {
+    let mut tags = Vec::new();
+    for share in &config.shares {
+        if !share.host_path.is_dir() {
+            return Err(ValidationError::ShareNotFound(
+                share.host_path.to_string_lossy().to_string(),
+            ));
+        }
+        let tag_ok = !share.tag.is_empty()
+            && share.tag.len() <= MAX_MOUNT_TAG_LEN
+            && share.tag.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+        if !tag_ok {
+            return Err(ValidationError::InvalidShareTag(share.tag.clone()));
+        }
+        if tags.contains(&&share.tag) {
+            return Err(ValidationError::DuplicateShareTag(share.tag.clone()));
+        }
+        tags.push(&share.tag);
+    }
+    
+    if share_transport(config.vm.guest_os) == ShareTransport::Smb {
+        if config.shares.len() > 1 {
+            return Err(ValidationError::UnsupportedSmbShare("only one share can be exported over SMB"));
+        }
+        if config.shares.iter().any(|s| s.read_only) {
+            return Err(ValidationError::UnsupportedSmbShare("read_only is not supported over SMB"));
+        }
+        // smb= makes QEMU run the host's smbd, which Windows builds of QEMU leave out
+        if cfg!(windows) && !config.shares.is_empty() {
+            return Err(ValidationError::UnsupportedSmbShare(
+                "QEMU on a Windows host cannot export shares over SMB (smb=); remove [[shares]] and copy files with: portaqemu cp",
+            ));
+        }
+    }
+    
+    Ok(())
+}
+
+/// Validate configuration against the capabilities of the selected QEMU binary.
+pub fn validate_caps(config: &ResolvedConfig, caps: &QemuCaps) -> Result<(), ValidationError> {
+    let unsupported = |what: &str| ValidationError::Unsupported {
+        version: caps.version.to_string(),
+        what: what.to_string(),
+    };
+    
+    if !caps.has_machine("q35") {
+        return Err(unsupported("machine type 'q35'"));
+    }
+    if !caps.supports_unix_sockets() {
+        return Err(unsupported("private monitor and chardev sockets (AF_UNIX needs QEMU 7.1 on Windows)"));
+    }
+    if (config.guest_agent.enabled || config.integration.enabled) && !caps.has_device("virtio-serial-pci") {
+        return Err(unsupported("guest agent and integration channels (virtio-serial-pci)"));
+    }
+    if !caps.has_device("virtio-net-pci") && !caps.has_device("e1000") {
+        return Err(unsupported("network device (virtio-net-pci or e1000)"));
+    }
+    if share_transport(config.vm.guest_os) == ShareTransport::Virtio9p
+        && !config.shares.is_empty()
+        && !caps.has_device("virtio-9p-pci")
+    {
+        return Err(unsupported("shared folders (virtio-9p-pci)"));
+    }
+    if config.accel.preferred == AccelPreferred::Whpx && !caps.has_accel("whpx") {
+        return Err(unsupported("accelerator 'whpx'"));
+    }
+    
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn network(extra: &str) -> NetworkConfig {
+        toml::from_str(&format!("ssh_host_port = 2222\n{}", extra)).unwrap()
+    }
+
+    #[test]
+    fn test_guestfwd_conflicts_with_effective_gateway_and_dns() {
+        let forward = |addr: &str| format!("guestfwd = [{{ guest_addr = \"{}\", guest_port = 80, host_port = 8080 }}]", addr);
+        for conflict in ["10.0.2.2", "10.0.2.3"] {
+            let result = validate_user_net(&network(&forward(conflict)));
+            assert!(matches!(result, Err(ValidationError::AddressConflict(_))), "{}", conflict);
+        }
+        assert!(validate_user_net(&network(&forward("10.0.2.100"))).is_ok());
+
+        // With a custom net the defaults move along, and explicit addresses free them up
+        let custom = |extra: &str, addr: &str| network(&format!("net = \"192.168.76.0/24\"\n{}\n{}", extra, forward(addr)));
+        assert!(matches!(validate_user_net(&custom("", "192.168.76.3")), Err(ValidationError::AddressConflict(_))));
+        assert!(validate_user_net(&custom("", "192.168.76.2")).is_err());
+        assert!(validate_user_net(&custom("host = \"192.168.76.10\"", "192.168.76.2")).is_ok());
+        assert!(validate_user_net(&custom("dns = \"192.168.76.2\"", "192.168.76.9")).is_err());
+    }
+}
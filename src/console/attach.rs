@@ -0,0 +1,165 @@
+use crate::console::hub::{ConsoleEndpoint, ConsoleError};
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use std::io::{Read, Write};
+use std::net::{Shutdown, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Detach key, as in telnet.
+pub const DETACH_KEY: &str = "Ctrl-]";
+
+/// Connection to the console hub.
+enum ConsoleStream {
+    Tcp(TcpStream),
+    #[cfg(unix)]
+    Unix(std::os::unix::net::UnixStream),
+}
+
+impl ConsoleStream {
+    fn connect(endpoint: &ConsoleEndpoint) -> Result<Self, ConsoleError> {
+        match endpoint {
+            ConsoleEndpoint::Tcp(addr) => Ok(ConsoleStream::Tcp(TcpStream::connect(addr)?)),
+            #[cfg(unix)]
+            ConsoleEndpoint::Unix(path) => Ok(ConsoleStream::Unix(std::os::unix::net::UnixStream::connect(path)?)),
+            #[cfg(not(unix))]
+            ConsoleEndpoint::Unix(_) => Err(ConsoleError::UnixUnsupported),
+        }
+    }
+
+    fn try_clone(&self) -> std::io::Result<Self> {
+        match self {
+            ConsoleStream::Tcp(stream) => stream.try_clone().map(ConsoleStream::Tcp),
+            #[cfg(unix)]
+            ConsoleStream::Unix(stream) => stream.try_clone().map(ConsoleStream::Unix),
+        }
+    }
+
+    fn shutdown(&self) {
+        let _ = match self {
+            ConsoleStream::Tcp(stream) => stream.shutdown(Shutdown::Both),
+            #[cfg(unix)]
+            ConsoleStream::Unix(stream) => stream.shutdown(Shutdown::Both),
+        };
+    }
+}
+
+impl Read for ConsoleStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        match self {
+            ConsoleStream::Tcp(stream) => stream.read(buf),
+            #[cfg(unix)]
+            ConsoleStream::Unix(stream) => stream.read(buf),
+        }
+    }
+}
+
+impl Write for ConsoleStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self {
+            ConsoleStream::Tcp(stream) => stream.write(buf),
+            #[cfg(unix)]
+            ConsoleStream::Unix(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self {
+            ConsoleStream::Tcp(stream) => stream.flush(),
+            #[cfg(unix)]
+            ConsoleStream::Unix(stream) => stream.flush(),
+        }
+    }
+}
+
+/// Connect the terminal to the console hub at `endpoint`, presenting `token`, until
+/// the detach key is pressed or the VM exits. The VM keeps running after a detach.
+/// A `read_only` viewer sends nothing more, so it never takes the writer slot from
+/// another viewer.
+pub fn attach(endpoint: &ConsoleEndpoint, token: &str, read_only: bool) -> Result<(), ConsoleError> {
+    let mut stream = ConsoleStream::connect(endpoint)?;
+    stream.write_all(format!("{}\n", token).as_bytes())?;
+    let mut reader = stream.try_clone()?;
+    let closed = Arc::new(AtomicBool::new(false));
+
+    let output_closed = closed.clone();
+    let output = std::thread::spawn(move || {
+        let mut stdout = std::io::stdout();
+        let mut buf = [0u8; 4096];
+        while let Ok(n @ 1..) = reader.read(&mut buf) {
+            if stdout.write_all(&buf[..n]).and_then(|_| stdout.flush()).is_err() {
+                break;
+            }
+        }
+        output_closed.store(true, Ordering::SeqCst);
+    });
+
+    terminal::enable_raw_mode()?;
+    let result = forward_keys(&mut stream, &closed, read_only);
+    terminal::disable_raw_mode()?;
+
+    stream.shutdown();
+    let _ = output.join();
+    result
+}
+
+fn forward_keys(stream: &mut ConsoleStream, closed: &AtomicBool, read_only: bool) -> Result<(), ConsoleError> {
+    while !closed.load(Ordering::SeqCst) {
+        // Poll so a closed console is noticed without waiting for a key
+        if !event::poll(Duration::from_millis(100))? {
+            continue;
+        }
+        let key = match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => key,
+            Event::Paste(text) if !read_only => {
+                stream.write_all(text.as_bytes())?;
+                continue;
+            }
+            _ => continue,
+        };
+        if is_detach(&key) {
+            break;
+        }
+        if read_only {
+            continue;
+        }
+        if let Some(bytes) = key_bytes(&key) {
+            if stream.write_all(&bytes).is_err() {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn is_detach(key: &KeyEvent) -> bool {
+    // Some terminals report Ctrl-] as Ctrl-5
+    key.modifiers.contains(KeyModifiers::CONTROL) && matches!(key.code, KeyCode::Char(']') | KeyCode::Char('5'))
+}
+
+/// Bytes a serial terminal expects for a key press.
+fn key_bytes(key: &KeyEvent) -> Option<Vec<u8>> {
+    let bytes: &[u8] = match key.code {
+        KeyCode::Char(c) if key.modifiers.contains(KeyModifiers::CONTROL) => {
+            let c = c.to_ascii_uppercase();
+            return ('@'..='_').contains(&c).then(|| vec![c as u8 - b'@']);
+        }
+        KeyCode::Char(c) => return Some(c.to_string().into_bytes()),
+        KeyCode::Enter => b"\r",
+        KeyCode::Backspace => b"\x7f",
+        KeyCode::Tab => b"\t",
+        KeyCode::Esc => b"\x1b",
+        KeyCode::Up => b"\x1b[A",
+        KeyCode::Down => b"\x1b[B",
+        KeyCode::Right => b"\x1b[C",
+        KeyCode::Left => b"\x1b[D",
+        KeyCode::Home => b"\x1b[H",
+        KeyCode::End => b"\x1b[F",
+        KeyCode::Delete => b"\x1b[3~",
+        KeyCode::PageUp => b"\x1b[5~",
+        KeyCode::PageDown => b"\x1b[6~",
+        _ => return None,
+    };
+    Some(bytes.to_vec())
+}
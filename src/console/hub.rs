@@ -0,0 +1,373 @@
+use crate::console::log::{SerialLog, SerialLogError};
+use crate::util::local_socket::{connect_async, AsyncLocalStream};
+use std::collections::VecDeque;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::{broadcast, mpsc};
+
+/// How long to keep retrying the QEMU chardev after launch.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Recent output replayed to a viewer when it attaches, so the current prompt shows.
+const SCROLLBACK_BYTES: usize = 4096;
+
+/// How long a new viewer has to send the console token.
+const AUTH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest token line accepted from a viewer.
+const MAX_TOKEN_LINE: usize = 256;
+
+/// Sent before closing a viewer that did not present the token.
+const TOKEN_REJECTED: &[u8] = b"[portaqemu: console token rejected]\r\n";
+
+/// Shown once to a viewer typing while another viewer is the writer.
+const READ_ONLY_NOTICE: &[u8] = b"\r\n[portaqemu: another viewer is typing; this console is read-only]\r\n";
+
+#[derive(Error, Debug)]
+pub enum ConsoleError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Serial log error: {0}")]
+    Log(#[from] SerialLogError),
+    #[error("Could not connect to the serial chardev at {}", .0.display())]
+    Connect(PathBuf),
+    #[error("Unix socket consoles are not supported on this platform")]
+    UnixUnsupported,
+}
+
+/// Where viewers attach to the hub: `127.0.0.1:PORT` or `unix:PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConsoleEndpoint {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl std::str::FromStr for ConsoleEndpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(ConsoleEndpoint::Unix(PathBuf::from(path))),
+            None => s
+                .parse()
+                .map(ConsoleEndpoint::Tcp)
+                .map_err(|_| format!("invalid console address '{}'", s)),
+        }
+    }
+}
+
+impl fmt::Display for ConsoleEndpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConsoleEndpoint::Tcp(addr) => write!(f, "{}", addr),
+            ConsoleEndpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+trait ViewerStream: AsyncRead + AsyncWrite + Send + Unpin {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> ViewerStream for T {}
+
+enum ViewerListener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener),
+}
+
+impl ViewerListener {
+    fn bind(endpoint: &ConsoleEndpoint) -> Result<Self, ConsoleError> {
+        match endpoint {
+            ConsoleEndpoint::Tcp(addr) => {
+                let listener = std::net::TcpListener::bind(addr)?;
+                listener.set_nonblocking(true)?;
+                Ok(ViewerListener::Tcp(TcpListener::from_std(listener)?))
+            }
+            #[cfg(unix)]
+            ConsoleEndpoint::Unix(path) => {
+                // A socket left by a previous hub would make bind fail
+                let _ = std::fs::remove_file(path);
+                Ok(ViewerListener::Unix(tokio::net::UnixListener::bind(path)?))
+            }
+            #[cfg(not(unix))]
+            ConsoleEndpoint::Unix(_) => Err(ConsoleError::UnixUnsupported),
+        }
+    }
+
+    async fn accept(&self) -> std::io::Result<Box<dyn ViewerStream>> {
+        match self {
+            ViewerListener::Tcp(listener) => Ok(Box::new(listener.accept().await?.0)),
+            #[cfg(unix)]
+            ViewerListener::Unix(listener) => Ok(Box::new(listener.accept().await?.0)),
+        }
+    }
+}
+
+/// Relays the guest serial console: QEMU's chardev on one side, a timestamped
+/// log and attached viewers on the other. QEMU accepts a single chardev client,
+/// so everything else goes through the hub. A viewer's first line must be the
+/// console token; after that it sees the output, and the first viewer to type
+/// becomes the writer until it disconnects. A loopback port is open to every
+/// local user, so the token is what keeps them off the console.
+pub struct ConsoleHub {
+    chardev: PathBuf,
+    listen: ConsoleEndpoint,
+    log_path: PathBuf,
+    token: Arc<str>,
+}
+
+impl ConsoleHub {
+    pub fn new(chardev: PathBuf, listen: ConsoleEndpoint, log_path: PathBuf, token: &str) -> Self {
+        Self { chardev, listen, log_path, token: token.into() }
+    }
+
+    /// Run until QEMU closes the chardev (the VM exited).
+    pub async fn run(&self) -> Result<(), ConsoleError> {
+        let listener = ViewerListener::bind(&self.listen)?;
+        let result = self.relay(&listener).await;
+        if let ConsoleEndpoint::Unix(path) = &self.listen {
+            let _ = std::fs::remove_file(path);
+        }
+        result
+    }
+
+    async fn relay(&self, listener: &ViewerListener) -> Result<(), ConsoleError> {
+        let guest = self.connect_chardev().await?;
+        let (mut guest_rx, mut guest_tx) = tokio::io::split(guest);
+        let mut log = SerialLog::open(&self.log_path)?;
+
+        let (output_tx, _) = broadcast::channel::<Vec<u8>>(256);
+        let (input_tx, mut input_rx) = mpsc::channel::<Vec<u8>>(64);
+        tokio::spawn(async move {
+            while let Some(data) = input_rx.recv().await {
+                if guest_tx.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let writer = Arc::new(Mutex::new(None));
+        let mut next_id = 0u64;
+        let mut scrollback = VecDeque::with_capacity(SCROLLBACK_BYTES);
+        let mut buf = [0u8; 4096];
+        loop {
+            tokio::select! {
+                read = guest_rx.read(&mut buf) => {
+                    let n = match read {
+                        Ok(0) | Err(_) => break,
+                        Ok(n) => n,
+                    };
+                    log.write(&buf[..n])?;
+                    scrollback.extend(&buf[..n]);
+                    let excess = scrollback.len().saturating_sub(SCROLLBACK_BYTES);
+                    scrollback.drain(..excess);
+                    // No receivers just means nobody is attached
+                    let _ = output_tx.send(buf[..n].to_vec());
+                }
+                accepted = listener.accept() => {
+                    if let Ok(stream) = accepted {
+                        next_id += 1;
+                        let viewer = Viewer {
+                            id: next_id,
+                            writer: writer.clone(),
+                            input: input_tx.clone(),
+                            token: self.token.clone(),
+                        };
+                        let history: Vec<u8> = scrollback.iter().copied().collect();
+                        tokio::spawn(viewer.serve(stream, history, output_tx.subscribe()));
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+
+    async fn connect_chardev(&self) -> Result<AsyncLocalStream, ConsoleError> {
+        let deadline = tokio::time::Instant::now() + CONNECT_TIMEOUT;
+        loop {
+            match connect_async(&self.chardev).await {
+                Ok(stream) => return Ok(stream),
+                Err(_) if tokio::time::Instant::now() < deadline => {
+                    tokio::time::sleep(Duration::from_millis(100)).await;
+                }
+                Err(_) => return Err(ConsoleError::Connect(self.chardev.clone())),
+            }
+        }
+    }
+}
+
+/// One attached viewer.
+struct Viewer {
+    id: u64,
+    /// Id of the viewer whose input reaches the guest.
+    writer: Arc<Mutex<Option<u64>>>,
+    input: mpsc::Sender<Vec<u8>>,
+    /// Expected first line from the viewer.
+    token: Arc<str>,
+}
+
+impl Viewer {
+    /// Check the viewer's token, then send it console output and forward its
+    /// keystrokes while it holds the writer slot.
+    async fn serve(self, stream: Box<dyn ViewerStream>, history: Vec<u8>, mut output: broadcast::Receiver<Vec<u8>>) {
+        let (mut viewer_rx, mut viewer_tx) = tokio::io::split(stream);
+        let authenticated = tokio::time::timeout(AUTH_TIMEOUT, read_token_line(&mut viewer_rx))
+            .await
+            .ok()
+            .flatten()
+            .is_some_and(|line| tokens_match(&line, &self.token));
+        if !authenticated {
+            let _ = viewer_tx.write_all(TOKEN_REJECTED).await;
+            return;
+        }
+        let (notice_tx, mut notice_rx) = mpsc::channel::<&'static [u8]>(1);
+        let sender = tokio::spawn(async move {
+            if viewer_tx.write_all(&history).await.is_err() {
+                return;
+            }
+            loop {
+                let data = tokio::select! {
+                    received = output.recv() => match received {
+                        Ok(data) => data,
+                        // A slow viewer misses some output rather than stalling the guest
+                        Err(broadcast::error::RecvError::Lagged(_)) => continue,
+                        Err(broadcast::error::RecvError::Closed) => break,
+                    },
+                    Some(notice) = notice_rx.recv() => notice.to_vec(),
+                };
+                if viewer_tx.write_all(&data).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        let mut notified = false;
+        let mut buf = [0u8; 1024];
+        loop {
+            let n = match viewer_rx.read(&mut buf).await {
+                Ok(0) | Err(_) => break,
+                Ok(n) => n,
+            };
+            if !self.claim_writer() {
+                if !notified {
+                    let _ = notice_tx.try_send(READ_ONLY_NOTICE);
+                    notified = true;
+                }
+                continue;
+            }
+            if self.input.send(buf[..n].to_vec()).await.is_err() {
+                break;
+            }
+        }
+
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        if *writer == Some(self.id) {
+            *writer = None;
+        }
+        sender.abort();
+    }
+
+    /// Take the writer slot if it is free. Returns whether this viewer holds it.
+    fn claim_writer(&self) -> bool {
+        let mut writer = self.writer.lock().unwrap_or_else(|e| e.into_inner());
+        *writer.get_or_insert(self.id) == self.id
+    }
+}
+
+/// Read the viewer's first line, one byte at a time so no keystrokes after it are consumed.
+/// Trailing `\r` (telnet) and whitespace are dropped. `None` on EOF or an overlong line.
+async fn read_token_line(reader: &mut (impl AsyncRead + Unpin)) -> Option<Vec<u8>> {
+    let mut line = Vec::new();
+    loop {
+        match reader.read_u8().await.ok()? {
+            b'\n' => break,
+            byte if line.len() < MAX_TOKEN_LINE => line.push(byte),
+            _ => return None,
+        }
+    }
+    while line.last().is_some_and(|b| b.is_ascii_whitespace()) {
+        line.pop();
+    }
+    Some(line)
+}
+
+/// Compare without stopping at the first differing byte.
+fn tokens_match(line: &[u8], token: &str) -> bool {
+    let token = token.as_bytes();
+    line.len() == token.len() && line.iter().zip(token).fold(0u8, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::util::net::{reserve_port, Protocol};
+    use std::net::Ipv4Addr;
+    use tokio::net::TcpStream;
+
+    #[test]
+    fn test_endpoint_round_trip() {
+        for text in ["127.0.0.1:4444", "unix:/tmp/vm/console.sock"] {
+            assert_eq!(text.parse::<ConsoleEndpoint>().unwrap().to_string(), text);
+        }
+        assert!("console.sock".parse::<ConsoleEndpoint>().is_err());
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_hub_relays_and_logs() {
+        let dir = tempfile::tempdir().unwrap();
+        let log_path = dir.path().join("serial.log");
+        let socket = dir.path().join("serial.sock");
+        let chardev = tokio::net::UnixListener::bind(&socket).unwrap();
+        let listen_port = reserve_port(Protocol::Tcp, Ipv4Addr::LOCALHOST.into()).unwrap().port;
+        let listen = SocketAddr::from((Ipv4Addr::LOCALHOST, listen_port));
+
+        let hub = ConsoleHub::new(socket, ConsoleEndpoint::Tcp(listen), log_path.clone(), "s3cret");
+        let hub = tokio::spawn(async move { hub.run().await });
+        let (mut guest, _) = chardev.accept().await.unwrap();
+        guest.write_all(b"devvm login: ").await.unwrap();
+        while !std::fs::read_to_string(&log_path).unwrap_or_default().contains("login:") {
+            tokio::time::sleep(Duration::from_millis(10)).await;
+        }
+
+        // A viewer without the token is turned away before seeing anything
+        let mut buf = [0u8; 128];
+        let mut stranger = TcpStream::connect(listen).await.unwrap();
+        stranger.write_all(b"guess\r\nroot\r").await.unwrap();
+        let n = stranger.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], TOKEN_REJECTED);
+        // Closed with its keystrokes unread, which may surface as a reset
+        assert!(!matches!(stranger.read(&mut buf).await, Ok(1..)));
+
+        // A late viewer sees the scrollback, and its keystrokes reach the guest
+        let mut viewer = TcpStream::connect(listen).await.unwrap();
+        viewer.write_all(b"s3cret\r\n").await.unwrap();
+        let n = viewer.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"devvm login: ");
+        viewer.write_all(b"root\r").await.unwrap();
+        let n = guest.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"root\r");
+
+        // A second viewer is read-only while the first holds the writer slot
+        let mut second = TcpStream::connect(listen).await.unwrap();
+        second.write_all(b"s3cret\n").await.unwrap();
+        let n = second.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"devvm login: ");
+        second.write_all(b"x").await.unwrap();
+        let n = second.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], READ_ONLY_NOTICE);
+        drop(viewer);
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        second.write_all(b"y").await.unwrap();
+        let n = guest.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"y");
+
+        drop(guest);
+        hub.await.unwrap().unwrap();
+    }
+}
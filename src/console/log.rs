@@ -0,0 +1,133 @@
+use chrono::Local;
+use regex::Regex;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Width of the `[YYYY-mm-dd HH:MM:SS.mmm] ` prefix on each log line.
+const TIMESTAMP_WIDTH: usize = 26;
+
+#[derive(Error, Debug)]
+pub enum SerialLogError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid readiness marker '{0}': {1}")]
+    InvalidMarker(String, regex::Error),
+    #[error("No readiness marker seen on the serial console after {0}s")]
+    Timeout(u64),
+}
+
+/// Appends guest console output to a log file, timestamping each line as it starts.
+/// Partial lines (such as a `login: ` prompt) are written immediately.
+pub struct SerialLog {
+    file: File,
+    at_line_start: bool,
+}
+
+impl SerialLog {
+    pub fn open(path: &Path) -> Result<Self, SerialLogError> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        Ok(Self { file, at_line_start: true })
+    }
+
+    pub fn write(&mut self, data: &[u8]) -> Result<(), SerialLogError> {
+        let mut out = Vec::with_capacity(data.len() + TIMESTAMP_WIDTH);
+        for &byte in data {
+            // Serial consoles send CRLF; keep plain newlines in the log
+            if byte == b'\r' {
+                continue;
+            }
+            if self.at_line_start {
+                out.extend_from_slice(format!("[{}] ", Local::now().format("%Y-%m-%d %H:%M:%S%.3f")).as_bytes());
+                self.at_line_start = false;
+            }
+            out.push(byte);
+            if byte == b'\n' {
+                self.at_line_start = true;
+            }
+        }
+        self.file.write_all(&out)?;
+        Ok(())
+    }
+}
+
+/// Line text without the timestamp prefix.
+fn strip_timestamp(line: &str) -> &str {
+    match line.strip_prefix('[').and_then(|rest| rest.split_once("] ")) {
+        Some((_, text)) => text,
+        None => line,
+    }
+}
+
+/// Compile readiness marker patterns.
+pub fn compile_markers(markers: &[String]) -> Result<Vec<Regex>, SerialLogError> {
+    markers
+        .iter()
+        .map(|m| Regex::new(m).map_err(|e| SerialLogError::InvalidMarker(m.clone(), e)))
+        .collect()
+}
+
+/// Follow the serial log until a line (complete or not) matches one of `markers`.
+/// Returns the matching text.
+pub fn wait_for_marker(path: &Path, markers: &[Regex], timeout: Duration) -> Result<String, SerialLogError> {
+    let start = Instant::now();
+    let mut offset = 0;
+    let mut pending = String::new();
+
+    loop {
+        if let Ok(mut file) = File::open(path) {
+            file.seek(SeekFrom::Start(offset))?;
+            let mut chunk = Vec::new();
+            offset += file.read_to_end(&mut chunk)? as u64;
+            pending.push_str(&String::from_utf8_lossy(&chunk));
+
+            for line in pending.split('\n') {
+                let text = strip_timestamp(line);
+                if markers.iter().any(|m| m.is_match(text)) {
+                    return Ok(text.trim().to_string());
+                }
+            }
+            // Keep the incomplete last line for the next read
+            let last_line = pending.rfind('\n').map(|i| i + 1).unwrap_or(0);
+            pending.drain(..last_line);
+        }
+
+        if start.elapsed() >= timeout {
+            return Err(SerialLogError::Timeout(timeout.as_secs()));
+        }
+        std::thread::sleep(Duration::from_millis(200));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_and_marker() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("serial.log");
+        let mut log = SerialLog::open(&path).unwrap();
+        log.write(b"Booting\r\nUbuntu 24.04 devvm tty").unwrap();
+        log.write(b"S0\r\n\r\ndevvm login: ").unwrap();
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = contents.split('\n').collect();
+        assert_eq!(lines.len(), 4);
+        assert_eq!(strip_timestamp(lines[1]), "Ubuntu 24.04 devvm ttyS0");
+        assert_eq!(lines[0].len(), TIMESTAMP_WIDTH + "Booting".len());
+
+        let markers = compile_markers(&["login:\\s*$".to_string()]).unwrap();
+        assert_eq!(wait_for_marker(&path, &markers, Duration::ZERO).unwrap(), "devvm login:");
+        let markers = compile_markers(&["^Welcome".to_string()]).unwrap();
+        assert!(matches!(
+            wait_for_marker(&path, &markers, Duration::ZERO),
+            Err(SerialLogError::Timeout(0))
+        ));
+    }
+}
@@ -0,0 +1,7 @@
+pub mod hub;
+pub mod log;
+pub mod attach;
+
+pub use hub::*;
+pub use log::*;
+pub use attach::*;
@@ -1,10 +1,11 @@
 use crate::config::schema::ResolvedConfig;
 use crate::qemu::locate::locate_qemu;
-use crate::qemu::accel::{detect_available_accels, choose_accel};
-use crate::util::net::is_port_available;
+use crate::qemu::accel::{accels_from_caps, choose_accel};
+use crate::qemu::caps::{detect_caps, QemuCaps};
+use crate::util::net::is_binding_available;
 use crate::autostart::is_autostart_enabled;
-use crate::terminal::fragment::get_fragment_file;
-use std::path::PathBuf;
+use crate::config::paths::get_fragment_file;
+use std::path::Path;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 pub enum CheckStatus {
@@ -21,19 +22,19 @@ pub struct CheckResult {
     pub hint: Option<String>,
 }
 
-pub fn check_qemu_binary(root: &PathBuf) -> CheckResult {
-    match locate_qemu(root) {
+pub fn check_qemu_binary(root: &Path, config: &ResolvedConfig) -> CheckResult {
+    match locate_qemu(root, &config.qemu) {
         Ok(path) => CheckResult {
             id: "qemu_binary",
             status: CheckStatus::Pass,
             message: format!("QEMU found: {}", path.to_string_lossy()),
             hint: None,
         },
-        Err(_) => CheckResult {
+        Err(e) => CheckResult {
             id: "qemu_binary",
             status: CheckStatus::Fail,
-            message: "QEMU binary not found".to_string(),
-            hint: Some("Install QEMU or place qemu-system-x86_64.exe in bin/".to_string()),
+            message: e.to_string(),
+            hint: Some("Run: portaqemu qemu install <zip>, or place qemu-system-x86_64.exe in bin/".to_string()),
         },
     }
 }
@@ -56,9 +57,31 @@ pub fn check_disk_image(config: &ResolvedConfig) -> CheckResult {
     }
 }
 
-pub fn check_acceleration(root: &PathBuf, config: &ResolvedConfig) -> CheckResult {
-    if let Ok(qemu_path) = locate_qemu(root) {
-        let availability = detect_available_accels(&qemu_path);
+fn qemu_caps(root: &Path, config: &ResolvedConfig) -> Option<QemuCaps> {
+    let qemu_path = locate_qemu(root, &config.qemu).ok()?;
+    detect_caps(&qemu_path, &root.join("config").join("qemu-caps.json")).ok()
+}
+
+pub fn check_qemu_version(root: &Path, config: &ResolvedConfig) -> CheckResult {
+    match qemu_caps(root, config) {
+        Some(caps) => CheckResult {
+            id: "qemu_version",
+            status: CheckStatus::Pass,
+            message: format!("QEMU version: {}", caps.version),
+            hint: None,
+        },
+        None => CheckResult {
+            id: "qemu_version",
+            status: CheckStatus::Warn,
+            message: "Could not detect QEMU version".to_string(),
+            hint: Some("Check that the QEMU binary runs: qemu-system-x86_64 -version".to_string()),
+        },
+    }
+}
+
+pub fn check_acceleration(root: &Path, config: &ResolvedConfig) -> CheckResult {
+    if let Some(caps) = qemu_caps(root, config) {
+        let availability = accels_from_caps(&caps);
         match choose_accel(config.accel.preferred, &availability) {
             Ok(accel) => {
                 let accel_name = match accel {
@@ -83,24 +106,17 @@ pub fn check_acceleration(root: &PathBuf, config: &ResolvedConfig) -> CheckResul
         CheckResult {
             id: "acceleration",
             status: CheckStatus::Fail,
-            message: "Cannot check acceleration: QEMU not found".to_string(),
+            message: "Cannot check acceleration: QEMU not found or not runnable".to_string(),
             hint: None,
         }
     }
 }
 
 pub fn check_ports(config: &ResolvedConfig) -> CheckResult {
-    let mut ports_to_check = vec![config.network.ssh_host_port];
-    for forward in &config.network.forwards {
-        ports_to_check.push(forward.host);
-    }
-    
     let mut in_use = Vec::new();
-    for &port in &ports_to_check {
-        if let Ok(available) = is_port_available(port) {
-            if !available {
-                in_use.push(port);
-            }
+    for binding in config.network.host_bindings() {
+        if let Ok(false) = is_binding_available(&binding) {
+            in_use.push(format!("{}/{}", binding.port, binding.proto));
         }
     }
     
@@ -115,8 +131,8 @@ pub fn check_ports(config: &ResolvedConfig) -> CheckResult {
         CheckResult {
             id: "ports",
             status: CheckStatus::Warn,
-            message: format!("Ports in use: {:?}", in_use),
-            hint: Some("Stop conflicting services or change port configuration".to_string()),
+            message: format!("Ports in use: {}", in_use.join(", ")),
+            hint: Some("Stop conflicting services, change the ports or set them to \"auto\"".to_string()),
         }
     }
 }
@@ -134,7 +150,7 @@ pub fn check_ssh_key(config: &ResolvedConfig) -> CheckResult {
             id: "ssh_key",
             status: CheckStatus::Warn,
             message: format!("SSH key not found: {}", config.vscode.identity_file.to_string_lossy()),
-            hint: Some("Generate SSH key pair with: ssh-keygen -t ed25519".to_string()),
+            hint: Some("Generate SSH key pair with: portaqemu keys generate".to_string()),
         }
     }
 }
@@ -176,9 +192,10 @@ pub fn check_autostart(config: &ResolvedConfig) -> CheckResult {
     }
 }
 
-pub fn run_all_checks(config: &ResolvedConfig, root: &PathBuf) -> Vec<CheckResult> {
+pub fn run_all_checks(config: &ResolvedConfig, root: &Path) -> Vec<CheckResult> {
     vec![
-        check_qemu_binary(root),
+        check_qemu_binary(root, config),
+        check_qemu_version(root, config),
         check_disk_image(config),
         check_acceleration(root, config),
         check_ports(config),
@@ -0,0 +1,148 @@
+use crate::ssh::keys::{write_private, KeyError};
+use crate::util::random::random_bytes;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use thiserror::Error;
+
+/// fw_cfg file carrying the secret into the guest
+/// (`/sys/firmware/qemu_fw_cfg/by_name/opt/org.portaqemu/integration-key/raw` on Linux).
+pub const SECRET_FW_CFG_NAME: &str = "opt/org.portaqemu/integration-key";
+
+/// Where cloud-init writes the secret in Linux guests.
+pub const GUEST_SECRET_PATH: &str = "/etc/portaqemu/integration.key";
+
+/// Labels keep a responder's proof from being replayed as an initiator's.
+pub(crate) const RESPONDER_LABEL: &[u8] = b"portaqemu-integration-responder";
+pub(crate) const INITIATOR_LABEL: &[u8] = b"portaqemu-integration-initiator";
+
+/// Random bytes in a secret or nonce (hex-encoded on the wire and on disk).
+const RANDOM_BYTES: usize = 32;
+
+#[derive(Error, Debug)]
+pub enum AuthError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("{0}")]
+    Write(#[from] KeyError),
+    #[error("Integration secret not found: {0} (run: portaqemu init)")]
+    NotFound(String),
+    #[error("Integration secret is too short: {0}")]
+    TooShort(String),
+}
+
+/// Shared secret authenticating both ends of the integration channel.
+/// The key is the trimmed text of the secret file, so guests can use it as-is.
+#[derive(Clone)]
+pub struct Secret(String);
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Secret(..)")
+    }
+}
+
+impl Secret {
+    /// A new random secret from the OS CSPRNG.
+    pub fn generate() -> std::io::Result<Self> {
+        Ok(Self(hex(&random_bytes::<RANDOM_BYTES>()?)))
+    }
+
+    pub fn load(path: &Path) -> Result<Self, AuthError> {
+        if !path.exists() {
+            return Err(AuthError::NotFound(path.to_string_lossy().to_string()));
+        }
+        let secret = fs::read_to_string(path)?.trim().to_string();
+        if secret.len() < RANDOM_BYTES {
+            return Err(AuthError::TooShort(path.to_string_lossy().to_string()));
+        }
+        Ok(Self(secret))
+    }
+
+    /// Write the secret, readable only by the current user.
+    pub fn save(&self, path: &Path) -> Result<(), AuthError> {
+        write_private(path, format!("{}\n", self.0).as_bytes())?;
+        Ok(())
+    }
+
+    /// The secret text, for handing to the guest.
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    /// Hex HMAC-SHA256 over `label`, then each nonce, NUL-separated.
+    pub fn proof(&self, label: &[u8], nonces: [&str; 2]) -> String {
+        hex(&self.mac(label, nonces).finalize().into_bytes())
+    }
+
+    /// Check a peer's `proof` in constant time.
+    pub fn verify(&self, label: &[u8], nonces: [&str; 2], proof: &str) -> bool {
+        match unhex(proof) {
+            Some(proof) => self.mac(label, nonces).verify_slice(&proof).is_ok(),
+            None => false,
+        }
+    }
+
+    fn mac(&self, label: &[u8], nonces: [&str; 2]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(self.0.as_bytes()).expect("HMAC takes keys of any length");
+        mac.update(label);
+        for nonce in nonces {
+            mac.update(b"\0");
+            mac.update(nonce.as_bytes());
+        }
+        mac
+    }
+}
+
+/// Create the secret at `path` unless one exists. Returns whether it was created.
+pub fn ensure_secret(path: &Path) -> Result<bool, AuthError> {
+    if path.exists() {
+        return Ok(false);
+    }
+    Secret::generate()?.save(path)?;
+    Ok(true)
+}
+
+/// A fresh challenge nonce.
+pub fn new_nonce() -> String {
+    hex(&random_bytes::<RANDOM_BYTES>().expect("OS random number generator unavailable"))
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn unhex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| s.get(i..i + 2).and_then(|pair| u8::from_str_radix(pair, 16).ok()))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_secret_round_trip_and_proofs() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("integration.key");
+        assert!(ensure_secret(&path).unwrap());
+        assert!(!ensure_secret(&path).unwrap());
+        let secret = Secret::load(&path).unwrap();
+
+        let (a, b) = (new_nonce(), new_nonce());
+        let proof = secret.proof(INITIATOR_LABEL, [&a, &b]);
+        assert!(secret.verify(INITIATOR_LABEL, [&a, &b], &proof));
+        // Bound to the label, the nonce order and the key
+        assert!(!secret.verify(RESPONDER_LABEL, [&a, &b], &proof));
+        assert!(!secret.verify(INITIATOR_LABEL, [&b, &a], &proof));
+        assert!(!Secret::generate().unwrap().verify(INITIATOR_LABEL, [&a, &b], &proof));
+        assert!(!secret.verify(INITIATOR_LABEL, [&a, &b], "zz"));
+    }
+}
@@ -0,0 +1,278 @@
+use crate::integration::auth::Secret;
+use crate::integration::connection::{ConnectionError, ConnectionManager, Role};
+use crate::integration::event_bus::EventBus;
+use crate::integration::registry::{FunctionInfo, FunctionRegistry, NoArgs};
+use crate::integration::relay::GuestLink;
+use crate::integration::protocol::{Message, MessageType};
+use crate::integration::transport::{read_frame, skip_frame, Stream, TransportError, MAX_FRAME_BYTES};
+use serde::{Deserialize, Serialize};
+use crate::util::cancel::CancellationToken;
+use crate::util::local_socket::connect_async;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader, DuplexStream, WriteHalf};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+/// virtio-serial port name the guest side opens (`/dev/virtio-ports/...` on Linux,
+/// `\\.\Global\...` on Windows).
+pub const INTEGRATION_CHANNEL: &str = "org.portaqemu.integration";
+
+/// How long to keep retrying the QEMU chardev after launch.
+const CONNECT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Bytes buffered between the chardev and a session in each direction.
+const SESSION_BUFFER: usize = 64 * 1024;
+
+/// Connect to the host end of the integration channel, retrying while QEMU starts.
+/// QEMU accepts one client per chardev, so the host side is a single long-lived process.
+pub async fn connect_chardev(socket: &Path) -> Result<Box<dyn Stream>, TransportError> {
+    let deadline = tokio::time::Instant::now() + CONNECT_TIMEOUT;
+    loop {
+        match connect_async(socket).await {
+            Ok(stream) => return Ok(Box::new(stream)),
+            Err(_) if tokio::time::Instant::now() < deadline => {
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+            Err(e) => return Err(e.into()),
+        }
+    }
+}
+
+/// Result of `host.ping`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HostInfo {
+    pub version: String,
+}
+
+/// Functions the host offers the guest.
+pub async fn host_registry() -> FunctionRegistry {
+    let registry = FunctionRegistry::new();
+    registry
+        .register_typed(
+            FunctionInfo::new("host.ping").description("Check the host is listening and report its PortaQEMU version"),
+            |_: NoArgs, _| async {
+                Ok(HostInfo { version: env!("CARGO_PKG_VERSION").to_string() })
+            },
+        )
+        .await;
+    registry
+}
+
+/// Serve the host side of the channel until QEMU closes it (the VM exited).
+///
+/// The chardev stays connected across guest reboots and agent restarts, so each
+/// `hello` from the guest starts a new session on it: a running session is ended,
+/// and a failed handshake (such as a stale secret) just waits for the next hello.
+/// An oversized frame ends the session too, rather than the channel.
+/// The authenticated guest of the current session is published on `guest`.
+pub async fn serve_host(
+    chardev: &Path,
+    secret: &Secret,
+    registry: Arc<FunctionRegistry>,
+    event_bus: Arc<EventBus>,
+    guest: GuestLink,
+) -> Result<(), ConnectionError> {
+    let stream = connect_chardev(chardev).await?;
+    let (read_half, write_half) = tokio::io::split(stream);
+    let mut reader = BufReader::new(read_half);
+    let writer = Arc::new(Mutex::new(write_half));
+    let mut session: Option<Session> = None;
+
+    loop {
+        let frame = match read_frame(&mut reader).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            // The guest is misbehaving, not gone: drop its session and pick up at the next frame
+            Err(TransportError::FrameTooLarge) => {
+                tracing::warn!("Integration guest sent a frame over {} bytes; ending its session", MAX_FRAME_BYTES);
+                if let Some(old) = session.take() {
+                    old.end().await;
+                }
+                if !skip_frame(&mut reader).await? {
+                    break;
+                }
+                continue;
+            }
+            Err(e) => return Err(e.into()),
+        };
+        if is_hello(&frame) {
+            if let Some(old) = session.take() {
+                tracing::info!("Integration guest said hello again; starting a new session");
+                old.end().await;
+            }
+            session = Some(Session::start(
+                writer.clone(),
+                secret.clone(),
+                registry.clone(),
+                event_bus.clone(),
+                guest.clone(),
+            ));
+        }
+        match &mut session {
+            // Fails once the session's handshake was refused or its connection closed
+            Some(current) => {
+                if current.forward(&frame).await.is_err() {
+                    session = None;
+                }
+            }
+            None => tracing::debug!("Dropping integration message sent before hello"),
+        }
+    }
+    if let Some(session) = session {
+        session.end().await;
+    }
+    Ok(())
+}
+
+fn is_hello(frame: &[u8]) -> bool {
+    serde_json::from_slice::<Message>(frame).is_ok_and(|msg| msg.msg_type == MessageType::Hello)
+}
+
+/// One handshake and the connection that follows, fed frames from the chardev
+/// through an in-memory pipe.
+struct Session {
+    inbound: WriteHalf<DuplexStream>,
+    stop: CancellationToken,
+    relay: JoinHandle<()>,
+    connection: JoinHandle<()>,
+}
+
+impl Session {
+    fn start(
+        writer: Arc<Mutex<WriteHalf<Box<dyn Stream>>>>,
+        secret: Secret,
+        registry: Arc<FunctionRegistry>,
+        event_bus: Arc<EventBus>,
+        guest: GuestLink,
+    ) -> Self {
+        let (local, pipe) = tokio::io::duplex(SESSION_BUFFER);
+        let (outbound, inbound) = tokio::io::split(pipe);
+        let stop = CancellationToken::new();
+
+        // Copy whole frames to the chardev, so a session ended mid-write can't
+        // leave half a message in front of the next one
+        let relay = tokio::spawn({
+            let stop = stop.clone();
+            async move {
+                let mut outbound = BufReader::new(outbound);
+                loop {
+                    let frame = tokio::select! {
+                        frame = read_frame(&mut outbound) => frame,
+                        _ = stop.cancelled() => break,
+                    };
+                    let Ok(Some(mut frame)) = frame else { break };
+                    frame.push(b'\n');
+                    let mut writer = writer.lock().await;
+                    if writer.write_all(&frame).await.is_err() || writer.flush().await.is_err() {
+                        break;
+                    }
+                }
+            }
+        });
+        let connection = tokio::spawn(async move {
+            match ConnectionManager::new(local, Role::Responder, &secret, registry, event_bus).await {
+                Ok(connection) => {
+                    tracing::info!("Integration guest connected: {}", connection.peer_capabilities().implementation);
+                    let connection = Arc::new(connection);
+                    guest.set(connection.clone()).await;
+                    connection.closed().await;
+                    guest.clear(&connection).await;
+                }
+                Err(e) => tracing::warn!("Integration handshake failed: {}", e),
+            }
+        });
+
+        Self { inbound, stop, relay, connection }
+    }
+
+    async fn forward(&mut self, frame: &[u8]) -> std::io::Result<()> {
+        self.inbound.write_all(frame).await?;
+        self.inbound.write_all(b"\n").await
+    }
+
+    /// Stop relaying, then close the connection, cancelling its running calls.
+    async fn end(self) {
+        self.stop.cancel();
+        let _ = self.relay.await;
+        drop(self.inbound);
+        let _ = self.connection.await;
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::integration::auth::{new_nonce, INITIATOR_LABEL};
+    use crate::integration::protocol::Capabilities;
+    use crate::integration::transport::write_frame;
+    use crate::integration::FunctionRegistry;
+    use serde_json::json;
+
+    /// Say hello and authenticate with `secret` by hand, as a guest agent would.
+    /// Returns the host's verdict.
+    async fn raw_handshake(stream: &mut BufReader<tokio::net::UnixStream>, secret: &Secret) -> Message {
+        let challenge = new_nonce();
+        let hello = Message::new_hello(&Capabilities::new(vec![], vec![]), challenge.clone());
+        write_frame(stream.get_mut(), &hello).await.unwrap();
+        let welcome: Message = serde_json::from_slice(&read_frame(stream).await.unwrap().unwrap()).unwrap();
+        let proof = secret.proof(INITIATOR_LABEL, [welcome.nonce.as_deref().unwrap(), &challenge]);
+        write_frame(stream.get_mut(), &Message::new_auth(hello.id, Some(proof), None)).await.unwrap();
+        serde_json::from_slice(&read_frame(stream).await.unwrap().unwrap()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_guest_reconnects_over_chardev() {
+        // Stands in for QEMU's chardev, with the guest side of the channel behind it
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("integration.sock");
+        let chardev = tokio::net::UnixListener::bind(&socket).unwrap();
+        let secret = Secret::generate().unwrap();
+        let host = tokio::spawn({
+            let secret = secret.clone();
+            async move {
+                let registry = Arc::new(host_registry().await);
+                serve_host(&socket, &secret, registry, Arc::new(EventBus::new()), GuestLink::new()).await
+            }
+        });
+        let mut stream = BufReader::new(chardev.accept().await.unwrap().0);
+
+        // A guest with a stale secret is refused, but the host keeps listening
+        let verdict = raw_handshake(&mut stream, &Secret::generate().unwrap()).await;
+        assert!(verdict.error.is_some());
+
+        // The guest agent restarts with the right secret and calls the host
+        let verdict = raw_handshake(&mut stream, &secret).await;
+        assert!(verdict.error.is_none());
+        write_frame(stream.get_mut(), &Message::new_call("1".to_string(), "host.ping".to_string(), json!({})))
+            .await
+            .unwrap();
+        let pong: Message = serde_json::from_slice(&read_frame(&mut stream).await.unwrap().unwrap()).unwrap();
+        assert_eq!(pong.result.unwrap()["version"], env!("CARGO_PKG_VERSION"));
+
+        // An oversized frame ends that session, and the next hello is still heard
+        let oversized = vec![b'x'; MAX_FRAME_BYTES as usize + 10];
+        stream.get_mut().write_all(&oversized).await.unwrap();
+        stream.get_mut().write_all(b"\n").await.unwrap();
+        let verdict = raw_handshake(&mut stream, &secret).await;
+        assert!(verdict.error.is_none());
+
+        // ...then restarts again mid-session, which starts a new one
+        let guest = ConnectionManager::new(
+            stream.into_inner(),
+            Role::Initiator,
+            &secret,
+            Arc::new(FunctionRegistry::new()),
+            Arc::new(EventBus::new()),
+        )
+        .await
+        .unwrap();
+        let pong = guest.call("host.ping", json!({})).await.unwrap();
+        assert_eq!(pong["version"], env!("CARGO_PKG_VERSION"));
+
+        // QEMU exiting closes the chardev, which ends the host side
+        guest.close().await;
+        host.await.unwrap().unwrap();
+    }
+}
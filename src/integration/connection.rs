@@ -1,14 +1,34 @@
-use crate::integration::protocol::{Message, MessageType};
-use crate::integration::registry::FunctionRegistry;
+use crate::integration::protocol::{
+    Capabilities, Message, MessageType, MIN_PROTOCOL_VERSION, PROTOCOL_VERSION, STREAMING_VERSION,
+};
+use crate::integration::auth::{new_nonce, Secret, INITIATOR_LABEL, RESPONDER_LABEL};
+use crate::integration::registry::{CallContext, CallUpdate, FunctionRegistry, UpdateSink};
 use crate::integration::event_bus::EventBus;
+use crate::integration::transport::{read_frame, write_frame, Endpoint, Stream, TransportError};
 use serde_json;
 use std::collections::HashMap;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
-use tokio::sync::{oneshot, RwLock};
-// Note: Full implementation would use AsyncReadExt/AsyncWriteExt for vsock/TCP streams
-use uuid::Uuid;
+use std::task::{ready, Context, Poll};
+use std::time::Duration;
+use tokio::io::{AsyncWriteExt, BufReader, ReadHalf, WriteHalf};
+use tokio::sync::{mpsc, oneshot, Mutex, Notify, RwLock};
+use tokio::task::JoinHandle;
+use crate::util::cancel::CancellationToken;
+use crate::util::random::random_uuid;
+use async_trait::async_trait;
 use thiserror::Error;
 
+/// Timeout for `call` when none is given.
+pub const DEFAULT_CALL_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How long each handshake step may take once the initiator has said hello.
+pub const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages queued for the writer task before senders wait.
+const OUTGOING_QUEUE: usize = 256;
+
 #[derive(Error, Debug)]
 pub enum ConnectionError {
     #[error("IO error: {0}")]
@@ -17,102 +37,839 @@ pub enum ConnectionError {
     Json(#[from] serde_json::Error),
     #[error("Registry error: {0}")]
     Registry(#[from] crate::integration::registry::RegistryError),
+    #[error("Transport error: {0}")]
+    Transport(#[from] TransportError),
+    #[error("Remote error: {0}")]
+    Remote(String),
+    #[error("Handshake failed: {0}")]
+    Handshake(String),
+    #[error("Authentication failed: {0}")]
+    AuthFailed(String),
+    #[error("Incompatible protocol: peer speaks versions {peer_min}-{peer_max}, this side {MIN_PROTOCOL_VERSION}-{PROTOCOL_VERSION}")]
+    IncompatibleProtocol { peer_min: u32, peer_max: u32 },
     #[error("Connection closed")]
     Closed,
     #[error("Timeout waiting for response")]
     Timeout,
 }
 
-/// Connection manager for bidirectional RPC and events.
-/// Note: This is a simplified version. Full implementation would use vsock or TCP.
-pub struct ConnectionManager {
+/// Which side of the `hello`/`welcome` handshake a connection plays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    /// Sends `hello` and waits for `welcome`.
+    Initiator,
+    /// Waits for `hello` and answers with `welcome`.
+    Responder,
+}
+
+/// Work for the writer task.
+enum Outgoing {
+    Message(Box<Message>),
+    /// Flush, close the write side and stop.
+    Shutdown,
+}
+
+/// Where replies to an outgoing call go.
+enum Pending {
+    Call(oneshot::Sender<Message>),
+    /// Updates, then the response, for `call_streaming`.
+    Stream(mpsc::UnboundedSender<Message>),
+}
+
+/// State shared by the manager and its reader task.
+struct Shared {
     registry: Arc<FunctionRegistry>,
     event_bus: Arc<EventBus>,
-    pending_requests: Arc<RwLock<HashMap<String, oneshot::Sender<Message>>>>,
-    // In a real implementation, this would be a vsock or TCP stream
-    // For now, this is a placeholder structure
+    protocol: u32,
+    pending_requests: RwLock<HashMap<String, Pending>>,
+    /// Incoming calls still running, by id, so the caller can cancel them.
+    running: Mutex<HashMap<String, CancellationToken>>,
+    outgoing: mpsc::Sender<Outgoing>,
+    closed: AtomicBool,
+    closed_notify: Notify,
+}
+
+/// One item of a `CallStream`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StreamItem {
+    Chunk(serde_json::Value),
+    Progress(serde_json::Value),
+    /// The call's result; nothing follows.
+    Result(serde_json::Value),
+}
+
+/// Updates and then the result of a call made with `call_streaming`, as a
+/// `futures_core::Stream` or through `next`. Dropping it before the result cancels the call.
+pub struct CallStream {
+    id: String,
+    shared: Arc<Shared>,
+    replies: mpsc::UnboundedReceiver<Message>,
+    done: bool,
+}
+
+/// Connection manager for bidirectional RPC and events over a stream of
+/// newline-delimited JSON messages. Either side may call the other's
+/// registered functions and emit events onto the other's event bus.
+pub struct ConnectionManager {
+    shared: Arc<Shared>,
+    peer: Capabilities,
+    reader: JoinHandle<()>,
+    writer: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl ConnectionManager {
-    pub fn new(registry: Arc<FunctionRegistry>, event_bus: Arc<EventBus>) -> Self {
-        Self {
+    /// Handshake as `role`, authenticating with `secret`, then run the protocol over
+    /// `stream`. The capabilities offered are the functions and events registered at this point.
+    pub async fn new<S: Stream>(
+        stream: S,
+        role: Role,
+        secret: &Secret,
+        registry: Arc<FunctionRegistry>,
+        event_bus: Arc<EventBus>,
+    ) -> Result<Self, ConnectionError> {
+        let stream: Box<dyn Stream> = Box::new(stream);
+        let (read_half, mut write_half) = tokio::io::split(stream);
+        let mut reader = BufReader::new(read_half);
+        let functions = registry.list().await.into_iter().map(|f| f.name).collect();
+        let local = Capabilities::new(functions, event_bus.events().await);
+        let (peer, protocol) = handshake(&mut reader, &mut write_half, role, secret, &local).await?;
+        let (outgoing, outgoing_rx) = mpsc::channel(OUTGOING_QUEUE);
+        let shared = Arc::new(Shared {
             registry,
             event_bus,
-            pending_requests: Arc::new(RwLock::new(HashMap::new())),
+            protocol,
+            pending_requests: RwLock::new(HashMap::new()),
+            running: Mutex::new(HashMap::new()),
+            outgoing,
+            closed: AtomicBool::new(false),
+            closed_notify: Notify::new(),
+        });
+
+        let writer = tokio::spawn(write_loop(write_half, outgoing_rx));
+        let reader = tokio::spawn(read_loop(shared.clone(), reader));
+        Ok(Self {
+            shared,
+            peer,
+            reader,
+            writer: Mutex::new(Some(writer)),
+        })
+    }
+
+    /// Connect to a peer listening on `endpoint`, as the initiator.
+    pub async fn connect(
+        endpoint: &Endpoint,
+        secret: &Secret,
+        registry: Arc<FunctionRegistry>,
+        event_bus: Arc<EventBus>,
+    ) -> Result<Self, ConnectionError> {
+        Self::new(endpoint.connect().await?, Role::Initiator, secret, registry, event_bus).await
+    }
+
+    /// What the peer announced in the handshake.
+    pub fn peer_capabilities(&self) -> &Capabilities {
+        &self.peer
+    }
+
+    /// Protocol version agreed in the handshake.
+    pub fn protocol_version(&self) -> u32 {
+        self.shared.protocol
+    }
+
+    /// Whether the connection has shut down, locally or by the peer.
+    pub fn is_closed(&self) -> bool {
+        self.shared.closed.load(Ordering::SeqCst)
+    }
+
+    /// Wait until the connection shuts down.
+    pub async fn closed(&self) {
+        loop {
+            // Created before the check so a concurrent shutdown still wakes it
+            let notified = self.shared.closed_notify.notified();
+            if self.is_closed() {
+                return;
+            }
+            notified.await;
         }
     }
-    
-    /// Call a remote function.
+
+    /// Call a remote function, waiting up to `DEFAULT_CALL_TIMEOUT`.
     pub async fn call(&self, function: &str, args: serde_json::Value) -> Result<serde_json::Value, ConnectionError> {
-        let id = Uuid::new_v4().to_string();
-        let msg = Message::new_call(id.clone(), function.to_string(), args);
-        
-        // Create response channel
+        self.call_with_timeout(function, args, DEFAULT_CALL_TIMEOUT).await
+    }
+
+    /// Call a remote function, waiting up to `timeout` for its response.
+    /// A call that times out is cancelled on the remote side.
+    pub async fn call_with_timeout(
+        &self,
+        function: &str,
+        args: serde_json::Value,
+        timeout: Duration,
+    ) -> Result<serde_json::Value, ConnectionError> {
         let (tx, rx) = oneshot::channel();
-        {
-            let mut pending = self.pending_requests.write().await;
-            pending.insert(id.clone(), tx);
-        }
-        
-        // In real implementation, send message over vsock/TCP
-        // For now, simulate local call
-        let result = self.registry.call(function, msg.args.unwrap_or_default()).await?;
-        
-        // Remove from pending
-        {
-            let mut pending = self.pending_requests.write().await;
-            pending.remove(&id);
+        let id = self.start_call(function, args, Pending::Call(tx)).await?;
+
+        let response = match tokio::time::timeout(timeout, rx).await {
+            Ok(Ok(response)) => response,
+            // The sender was dropped by shutdown
+            Ok(Err(_)) => return Err(ConnectionError::Closed),
+            Err(_) => {
+                self.shared.pending_requests.write().await.remove(&id);
+                let _ = self.shared.cancel(&id).await;
+                return Err(ConnectionError::Timeout);
+            }
+        };
+        match response.error {
+            Some(error) => Err(ConnectionError::Remote(error)),
+            None => Ok(response.result.unwrap_or_default()),
+        }
+    }
+
+    /// Call a remote function, receiving its streamed output and progress before
+    /// the result. There is no timeout; cancel or drop the stream to give up.
+    /// Peers speaking protocol 1 only send the result.
+    pub async fn call_streaming(
+        &self,
+        function: &str,
+        args: serde_json::Value,
+    ) -> Result<CallStream, ConnectionError> {
+        let (tx, replies) = mpsc::unbounded_channel();
+        let id = self.start_call(function, args, Pending::Stream(tx)).await?;
+        Ok(CallStream {
+            id,
+            shared: self.shared.clone(),
+            replies,
+            done: false,
+        })
+    }
+
+    /// Send a call whose replies go to `pending`, returning its id.
+    async fn start_call(
+        &self,
+        function: &str,
+        args: serde_json::Value,
+        pending: Pending,
+    ) -> Result<String, ConnectionError> {
+        let id = random_uuid().to_string();
+        let msg = Message::new_call(id.clone(), function.to_string(), args);
+
+        // Register before sending so a fast response finds its waiter
+        self.shared.pending_requests.write().await.insert(id.clone(), pending);
+        // The reader marks the connection closed before failing waiters, so
+        // either it sees this entry or this check sees the flag
+        if self.is_closed() {
+            self.shared.pending_requests.write().await.remove(&id);
+            return Err(ConnectionError::Closed);
+        }
+        if let Err(e) = self.shared.send(msg).await {
+            self.shared.pending_requests.write().await.remove(&id);
+            return Err(e);
         }
-        
-        Ok(result)
+        Ok(id)
     }
-    
+
     /// Emit an event to the remote side.
     pub async fn emit(&self, event: &str, data: serde_json::Value) -> Result<(), ConnectionError> {
-        let msg = Message::new_event(event.to_string(), data);
-        
-        // In real implementation, send message over vsock/TCP
-        // For now, emit locally
-        self.event_bus.emit(event, msg.data.unwrap_or_default()).await;
-        
-        Ok(())
+        self.shared.send(Message::new_event(event.to_string(), data)).await
     }
-    
-    /// Handle incoming message (would be called from async event loop reading from stream).
+
+    /// Handle an incoming message: run calls against the local registry, complete
+    /// pending calls with responses and deliver events to the local event bus.
     pub async fn handle_message(&self, msg: Message) -> Result<(), ConnectionError> {
+        self.shared.clone().handle_message(msg).await;
+        Ok(())
+    }
+
+    /// Flush queued messages, close the stream and fail outstanding calls with `Closed`.
+    pub async fn close(&self) {
+        if let Some(writer) = self.writer.lock().await.take() {
+            let _ = self.shared.outgoing.send(Outgoing::Shutdown).await;
+            let _ = writer.await;
+        }
+        self.reader.abort();
+        self.shared.shut_down().await;
+    }
+}
+
+impl Drop for ConnectionManager {
+    fn drop(&mut self) {
+        // The writer stops once the last sender (held by `shared`) is gone
+        self.reader.abort();
+    }
+}
+
+impl CallStream {
+    /// Id of the underlying call.
+    pub fn id(&self) -> &str {
+        &self.id
+    }
+
+    /// The next update, or the result. `None` once the result or an error was returned.
+    pub async fn next(&mut self) -> Option<Result<StreamItem, ConnectionError>> {
+        std::future::poll_fn(|cx| futures_core::Stream::poll_next(Pin::new(&mut *self), cx)).await
+    }
+
+    /// Ask the remote side to stop the call. Items already sent still arrive,
+    /// followed by the handler's response (usually a cancellation error).
+    pub async fn cancel(&self) -> Result<(), ConnectionError> {
+        self.shared.cancel(&self.id).await
+    }
+}
+
+impl futures_core::Stream for CallStream {
+    type Item = Result<StreamItem, ConnectionError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        if this.done {
+            return Poll::Ready(None);
+        }
+        let Some(msg) = ready!(this.replies.poll_recv(cx)) else {
+            // The sender was dropped by shutdown
+            this.done = true;
+            return Poll::Ready(Some(Err(ConnectionError::Closed)));
+        };
+        let data = msg.data.unwrap_or_default();
+        Poll::Ready(match msg.msg_type {
+            MessageType::Stream => Some(Ok(StreamItem::Chunk(data))),
+            MessageType::Progress => Some(Ok(StreamItem::Progress(data))),
+            _ => {
+                this.done = true;
+                Some(match msg.error {
+                    Some(error) => Err(ConnectionError::Remote(error)),
+                    None => Ok(StreamItem::Result(msg.result.unwrap_or_default())),
+                })
+            }
+        })
+    }
+}
+
+impl Drop for CallStream {
+    fn drop(&mut self) {
+        if !self.done && self.shared.protocol >= STREAMING_VERSION && !self.shared.closed.load(Ordering::SeqCst) {
+            // The response still arrives and clears the pending entry
+            let cancel = Message::new_cancel(self.id.clone());
+            let _ = self.shared.outgoing.try_send(Outgoing::Message(Box::new(cancel)));
+        }
+    }
+}
+
+/// Forwards a running incoming call's updates to the caller.
+struct CallSink {
+    shared: Arc<Shared>,
+    id: String,
+}
+
+#[async_trait]
+impl UpdateSink for CallSink {
+    async fn send(&self, update: CallUpdate) {
+        if self.shared.protocol < STREAMING_VERSION {
+            return;
+        }
+        let (msg_type, data) = match update {
+            CallUpdate::Stream(chunk) => (MessageType::Stream, chunk),
+            CallUpdate::Progress(data) => (MessageType::Progress, data),
+        };
+        let _ = self.shared.send(Message::new_update(self.id.clone(), msg_type, data)).await;
+    }
+}
+
+impl Shared {
+    async fn send(&self, msg: Message) -> Result<(), ConnectionError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(ConnectionError::Closed);
+        }
+        self.outgoing
+            .send(Outgoing::Message(Box::new(msg)))
+            .await
+            .map_err(|_| ConnectionError::Closed)
+    }
+
+    /// Ask the peer to stop call `id`, if its protocol has cancellation.
+    async fn cancel(&self, id: &str) -> Result<(), ConnectionError> {
+        if self.protocol < STREAMING_VERSION {
+            return Ok(());
+        }
+        self.send(Message::new_cancel(id.to_string())).await
+    }
+
+    async fn handle_message(self: Arc<Self>, msg: Message) {
         match msg.msg_type {
             MessageType::Call => {
-                // Handle function call
-                if let Some(function) = msg.function {
+                let Some(function) = msg.function else {
+                    let _ = self.send(Message::new_error_response(msg.id, "call without a function".to_string())).await;
+                    return;
+                };
+                let cancel = CancellationToken::new();
+                self.running.lock().await.insert(msg.id.clone(), cancel.clone());
+                let sink = Arc::new(CallSink { shared: self.clone(), id: msg.id.clone() });
+                // Run the handler off the reader task so slow calls don't block responses
+                tokio::spawn(async move {
                     let args = msg.args.unwrap_or_default();
-                    let result = self.registry.call(&function, args).await;
-                    
-                    // In real implementation, send response back
-                    let response = match result {
+                    let ctx = CallContext::new(cancel, Some(sink));
+                    let response = match self.registry.call_with(&function, args, ctx).await {
                         Ok(value) => Message::new_response(msg.id.clone(), value),
                         Err(e) => Message::new_error_response(msg.id.clone(), e.to_string()),
                     };
-                    
-                    // Send response (would go over stream in real implementation)
-                    // For now, just log
-                    tracing::debug!("Response: {:?}", response);
-                }
+                    self.running.lock().await.remove(&msg.id);
+                    let _ = self.send(response).await;
+                });
             }
-            MessageType::Response => {
-                // Handle response
-                if let Some(tx) = self.pending_requests.write().await.remove(&msg.id) {
+            MessageType::Response => match self.pending_requests.write().await.remove(&msg.id) {
+                Some(Pending::Call(tx)) => {
+                    let _ = tx.send(msg);
+                }
+                Some(Pending::Stream(tx)) => {
                     let _ = tx.send(msg);
                 }
+                None => tracing::debug!("Response for unknown or timed-out call {}", msg.id),
+            },
+            MessageType::Stream | MessageType::Progress => match self.pending_requests.read().await.get(&msg.id) {
+                Some(Pending::Stream(tx)) => {
+                    let _ = tx.send(msg);
+                }
+                // Plain calls only want the result
+                Some(Pending::Call(_)) => {}
+                None => tracing::debug!("Update for unknown or finished call {}", msg.id),
+            },
+            MessageType::Cancel => {
+                if let Some(cancel) = self.running.lock().await.get(&msg.id) {
+                    cancel.cancel();
+                }
             }
             MessageType::Event => {
-                // Handle event
                 if let Some(event) = msg.event {
                     let data = msg.data.unwrap_or_default();
                     self.event_bus.emit(&event, data).await;
                 }
             }
+            // A restarted guest's hello on the chardev never gets here: `serve_host`
+            // starts a new connection for it
+            MessageType::Hello | MessageType::Welcome | MessageType::Auth => {
+                tracing::debug!("Ignoring {:?} after the handshake", msg.msg_type);
+            }
         }
-        
-        Ok(())
+    }
+
+    /// Mark the connection closed, fail every waiting call and cancel running ones.
+    async fn shut_down(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        // Dropping the senders wakes the waiters with `Closed`
+        self.pending_requests.write().await.clear();
+        for (_, cancel) in self.running.lock().await.drain() {
+            cancel.cancel();
+        }
+        self.closed_notify.notify_waiters();
+    }
+}
+
+/// Exchange `hello`/`welcome`, then prove knowledge of `secret` both ways before
+/// any call is accepted. Returns the peer's capabilities and the agreed version.
+async fn handshake(
+    reader: &mut BufReader<ReadHalf<Box<dyn Stream>>>,
+    writer: &mut WriteHalf<Box<dyn Stream>>,
+    role: Role,
+    secret: &Secret,
+    local: &Capabilities,
+) -> Result<(Capabilities, u32), ConnectionError> {
+    match role {
+        Role::Initiator => {
+            let challenge = new_nonce();
+            let hello = Message::new_hello(local, challenge.clone());
+            write_frame(writer, &hello).await?;
+            let welcome = read_reply(reader, MessageType::Welcome, &hello.id).await?;
+            let peer = peer_capabilities(&welcome)?;
+            let agreed = local.negotiate(&peer);
+            if let Some(error) = welcome.error {
+                return Err(match agreed {
+                    None => incompatible(&peer),
+                    Some(_) => ConnectionError::Handshake(error),
+                });
+            }
+            // The responder picks the version; it must be one this side speaks
+            if !(MIN_PROTOCOL_VERSION..=PROTOCOL_VERSION).contains(&peer.protocol) {
+                return Err(incompatible(&peer));
+            }
+
+            // The responder answers our challenge first, so an impostor learns nothing
+            let peer_challenge = welcome.nonce.unwrap_or_default();
+            let peer_proof = welcome.proof.unwrap_or_default();
+            if !secret.verify(RESPONDER_LABEL, [&challenge, &peer_challenge], &peer_proof) {
+                tracing::warn!("Integration peer {} failed authentication", peer.implementation);
+                return Err(ConnectionError::AuthFailed("peer does not know the secret".to_string()));
+            }
+            let proof = secret.proof(INITIATOR_LABEL, [&peer_challenge, &challenge]);
+            write_frame(writer, &Message::new_auth(hello.id.clone(), Some(proof), None)).await?;
+            let verdict = read_reply(reader, MessageType::Auth, &hello.id).await?;
+            if let Some(error) = verdict.error {
+                return Err(ConnectionError::AuthFailed(error));
+            }
+            let protocol = peer.protocol;
+            Ok((peer, protocol))
+        }
+        Role::Responder => {
+            let hello = read_message(reader).await?;
+            if hello.msg_type != MessageType::Hello {
+                return Err(ConnectionError::Handshake(format!("expected hello, got {:?}", hello.msg_type)));
+            }
+            let peer = peer_capabilities(&hello)?;
+            let Some(protocol) = local.negotiate(&peer) else {
+                let error = incompatible(&peer);
+                tracing::warn!("Refusing {}: {}", peer.implementation, error);
+                write_frame(writer, &Message::new_welcome(hello.id, local, Some(error.to_string()))).await?;
+                let _ = writer.shutdown().await;
+                return Err(error);
+            };
+            let Some(peer_challenge) = hello.nonce else {
+                tracing::warn!("Refusing {}: no authentication challenge", peer.implementation);
+                let error = "authentication required".to_string();
+                write_frame(writer, &Message::new_welcome(hello.id, local, Some(error.clone()))).await?;
+                let _ = writer.shutdown().await;
+                return Err(ConnectionError::AuthFailed(error));
+            };
+
+            let challenge = new_nonce();
+            let welcome = Message {
+                nonce: Some(challenge.clone()),
+                proof: Some(secret.proof(RESPONDER_LABEL, [&peer_challenge, &challenge])),
+                ..Message::new_welcome(hello.id.clone(), &Capabilities { protocol, ..local.clone() }, None)
+            };
+            write_frame(writer, &welcome).await?;
+            let auth = read_reply(reader, MessageType::Auth, &hello.id).await?;
+            if !secret.verify(INITIATOR_LABEL, [&challenge, &peer_challenge], auth.proof.as_deref().unwrap_or_default()) {
+                tracing::warn!("Integration peer {} failed authentication", peer.implementation);
+                let error = "authentication failed".to_string();
+                write_frame(writer, &Message::new_auth(hello.id, None, Some(error.clone()))).await?;
+                let _ = writer.shutdown().await;
+                return Err(ConnectionError::AuthFailed(error));
+            }
+            write_frame(writer, &Message::new_auth(hello.id, None, None)).await?;
+            Ok((peer, protocol))
+        }
+    }
+}
+
+async fn read_message(reader: &mut BufReader<ReadHalf<Box<dyn Stream>>>) -> Result<Message, ConnectionError> {
+    let frame = read_frame(reader).await?.ok_or(ConnectionError::Closed)?;
+    Ok(serde_json::from_slice(&frame)?)
+}
+
+/// Read the next handshake step, which must be `expected` for hello `id`.
+async fn read_reply(
+    reader: &mut BufReader<ReadHalf<Box<dyn Stream>>>,
+    expected: MessageType,
+    id: &str,
+) -> Result<Message, ConnectionError> {
+    let msg = tokio::time::timeout(HANDSHAKE_TIMEOUT, read_message(reader))
+        .await
+        .map_err(|_| ConnectionError::Timeout)??;
+    if msg.msg_type != expected || msg.id != id {
+        return Err(ConnectionError::Handshake(format!("expected {:?}, got {:?}", expected, msg.msg_type)));
+    }
+    Ok(msg)
+}
+
+fn peer_capabilities(msg: &Message) -> Result<Capabilities, ConnectionError> {
+    let data = msg.data.clone().unwrap_or_default();
+    serde_json::from_value(data).map_err(|e| ConnectionError::Handshake(format!("invalid capabilities: {}", e)))
+}
+
+fn incompatible(peer: &Capabilities) -> ConnectionError {
+    ConnectionError::IncompatibleProtocol {
+        peer_min: peer.min_protocol,
+        peer_max: peer.protocol,
+    }
+}
+
+async fn read_loop(shared: Arc<Shared>, mut reader: BufReader<ReadHalf<Box<dyn Stream>>>) {
+    loop {
+        let frame = match read_frame(&mut reader).await {
+            Ok(Some(frame)) => frame,
+            Ok(None) => break,
+            Err(e) => {
+                tracing::warn!("Integration connection failed: {}", e);
+                break;
+            }
+        };
+        match serde_json::from_slice::<Message>(&frame) {
+            Ok(msg) => shared.clone().handle_message(msg).await,
+            Err(e) => tracing::warn!("Ignoring malformed integration message: {}", e),
+        }
+    }
+    shared.shut_down().await;
+}
+
+async fn write_loop(mut write_half: WriteHalf<Box<dyn Stream>>, mut outgoing: mpsc::Receiver<Outgoing>) {
+    while let Some(item) = outgoing.recv().await {
+        match item {
+            Outgoing::Message(msg) => {
+                if let Err(e) = write_frame(&mut write_half, &msg).await {
+                    tracing::warn!("Integration connection failed: {}", e);
+                    break;
+                }
+            }
+            Outgoing::Shutdown => break,
+        }
+    }
+    let _ = write_half.shutdown().await;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::integration::transport::Listener;
+    use serde_json::json;
+    use std::net::{Ipv4Addr, SocketAddr};
+
+    fn loopback() -> Endpoint {
+        Endpoint::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+    }
+
+    async fn responder(listener: &Listener, secret: &Secret) -> Result<ConnectionManager, ConnectionError> {
+        let stream = listener.accept().await.unwrap();
+        ConnectionManager::new(stream, Role::Responder, secret, Arc::new(FunctionRegistry::new()), Arc::new(EventBus::new())).await
+    }
+
+    async fn pair(endpoint: Endpoint) -> (ConnectionManager, ConnectionManager, Arc<EventBus>) {
+        let listener = Listener::bind(&endpoint).await.unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        let secret = Secret::generate().unwrap();
+
+        let registry = Arc::new(FunctionRegistry::new());
+        registry
+            .register("add", |args: serde_json::Value, _| async move {
+                Ok(json!(args["a"].as_i64().unwrap_or(0) + args["b"].as_i64().unwrap_or(0)))
+            })
+            .await;
+        registry
+            .register("count", |args: serde_json::Value, ctx: CallContext| async move {
+                let n = args["n"].as_i64().unwrap_or(0);
+                for i in 1..=n {
+                    ctx.stream(json!(i)).await;
+                    ctx.progress(json!({ "done": i, "total": n })).await;
+                }
+                Ok(json!(n))
+            })
+            .await;
+        registry
+            .register("wait", |_, ctx: CallContext| async move {
+                ctx.cancellation().cancelled().await;
+                ctx.check_cancelled()?;
+                Ok(json!(null))
+            })
+            .await;
+        let server_bus = Arc::new(EventBus::new());
+        let (server, client) = tokio::join!(
+            async {
+                let stream = listener.accept().await.unwrap();
+                ConnectionManager::new(stream, Role::Responder, &secret, registry, server_bus.clone()).await
+            },
+            ConnectionManager::connect(&endpoint, &secret, Arc::new(FunctionRegistry::new()), Arc::new(EventBus::new())),
+        );
+        (server.unwrap(), client.unwrap(), server_bus)
+    }
+
+    /// Accept one connection and complete its handshake by hand, announcing `capabilities`.
+    async fn raw_responder(listener: Listener, secret: &Secret, capabilities: Capabilities) -> BufReader<Box<dyn Stream>> {
+        let mut stream = BufReader::new(listener.accept().await.unwrap());
+        let hello: Message = serde_json::from_slice(&read_frame(&mut stream).await.unwrap().unwrap()).unwrap();
+        assert_eq!(hello.msg_type, MessageType::Hello);
+        let challenge = new_nonce();
+        let welcome = Message {
+            proof: Some(secret.proof(RESPONDER_LABEL, [hello.nonce.as_deref().unwrap(), &challenge])),
+            nonce: Some(challenge),
+            ..Message::new_welcome(hello.id.clone(), &capabilities, None)
+        };
+        write_frame(stream.get_mut(), &welcome).await.unwrap();
+        // An initiator that rejects the welcome hangs up instead of authenticating
+        if let Ok(Some(_)) = read_frame(&mut stream).await {
+            write_frame(stream.get_mut(), &Message::new_auth(hello.id, None, None)).await.unwrap();
+        }
+        stream
+    }
+
+    /// Send `hello` by hand, then `auth` with `proof` if given. Returns the replies.
+    async fn raw_initiator(endpoint: &Endpoint, hello: Message, proof: Option<String>) -> Vec<Message> {
+        let mut stream = BufReader::new(endpoint.connect().await.unwrap());
+        write_frame(stream.get_mut(), &hello).await.unwrap();
+        let mut replies = Vec::new();
+        let welcome: Message = serde_json::from_slice(&read_frame(&mut stream).await.unwrap().unwrap()).unwrap();
+        replies.push(welcome);
+        if proof.is_some() {
+            write_frame(stream.get_mut(), &Message::new_auth(hello.id, proof, None)).await.unwrap();
+            let verdict = serde_json::from_slice(&read_frame(&mut stream).await.unwrap().unwrap()).unwrap();
+            replies.push(verdict);
+        }
+        replies
+    }
+
+    async fn exercise(endpoint: Endpoint) {
+        let (server, client, server_bus) = pair(endpoint).await;
+        assert_eq!(client.protocol_version(), PROTOCOL_VERSION);
+        assert_eq!(client.peer_capabilities().functions, ["add", "count", "rpc.list", "wait"]);
+        assert!(server.peer_capabilities().implementation.starts_with("portaqemu/"));
+
+        assert_eq!(client.call("add", json!({ "a": 2, "b": 3 })).await.unwrap(), json!(5));
+        assert!(matches!(
+            client.call("missing", json!({})).await,
+            Err(ConnectionError::Remote(e)) if e.contains("missing")
+        ));
+
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        server_bus.on("ping".to_string(), move |data| { let _ = tx.send(data); }).await;
+        client.emit("ping", json!({ "n": 1 })).await.unwrap();
+        assert_eq!(rx.recv().await.unwrap(), json!({ "n": 1 }));
+
+        // The client registers nothing, so a call from the server gets an error response
+        assert!(matches!(server.call("add", json!({})).await, Err(ConnectionError::Remote(_))));
+
+        server.close().await;
+        assert!(matches!(client.call("add", json!({})).await, Err(ConnectionError::Closed)));
+        assert!(client.is_closed());
+    }
+
+    #[tokio::test]
+    async fn test_calls_events_and_shutdown() {
+        exercise(loopback()).await;
+        #[cfg(unix)]
+        {
+            let dir = tempfile::tempdir().unwrap();
+            exercise(Endpoint::Unix(dir.path().join("integration.sock"))).await;
+        }
+    }
+
+    #[tokio::test]
+    async fn test_timeout_then_closed() {
+        let listener = Listener::bind(&loopback()).await.unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        let secret = Secret::generate().unwrap();
+        let (peer, client) = tokio::join!(
+            raw_responder(listener, &secret, Capabilities::new(vec![], vec![])),
+            ConnectionManager::connect(&endpoint, &secret, Arc::new(FunctionRegistry::new()), Arc::new(EventBus::new())),
+        );
+        let client = Arc::new(client.unwrap());
+
+        // A silent peer times out the call and leaves nothing pending
+        let result = client.call_with_timeout("add", json!({}), Duration::from_millis(50)).await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+        assert!(client.shared.pending_requests.read().await.is_empty());
+
+        // A waiter blocked on a call sees `Closed` when the peer goes away
+        let waiter = tokio::spawn({
+            let client = client.clone();
+            async move { client.call("add", json!({})).await }
+        });
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        drop(peer);
+        assert!(matches!(waiter.await.unwrap(), Err(ConnectionError::Closed)));
+    }
+
+    #[tokio::test]
+    async fn test_incompatible_versions_refused() {
+        let secret = Secret::generate().unwrap();
+        let future = Capabilities {
+            protocol: PROTOCOL_VERSION + 2,
+            min_protocol: PROTOCOL_VERSION + 1,
+            ..Capabilities::new(vec![], vec![])
+        };
+
+        // A responder refuses a hello it can't speak, telling the peer why
+        let listener = Listener::bind(&loopback()).await.unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        let (server, replies) = tokio::join!(
+            responder(&listener, &secret),
+            raw_initiator(&endpoint, Message::new_hello(&future, new_nonce()), None),
+        );
+        assert!(matches!(
+            server,
+            Err(ConnectionError::IncompatibleProtocol { peer_min, .. }) if peer_min == PROTOCOL_VERSION + 1
+        ));
+        assert_eq!(replies[0].msg_type, MessageType::Welcome);
+        assert!(replies[0].error.is_some());
+
+        // An initiator refuses a welcome picking a version it doesn't speak
+        let listener = Listener::bind(&loopback()).await.unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        let (_peer, client) = tokio::join!(
+            raw_responder(listener, &secret, future),
+            ConnectionManager::connect(&endpoint, &secret, Arc::new(FunctionRegistry::new()), Arc::new(EventBus::new())),
+        );
+        assert!(matches!(client, Err(ConnectionError::IncompatibleProtocol { .. })));
+    }
+
+    #[tokio::test]
+    async fn test_unauthenticated_peers_refused() {
+        let secret = Secret::generate().unwrap();
+        let other = Secret::generate().unwrap();
+        let listener = Listener::bind(&loopback()).await.unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        let local = Capabilities::new(vec![], vec![]);
+
+        // Each side rejects a peer holding a different secret
+        let (server, client) = tokio::join!(
+            responder(&listener, &secret),
+            ConnectionManager::connect(&endpoint, &other, Arc::new(FunctionRegistry::new()), Arc::new(EventBus::new())),
+        );
+        assert!(matches!(client, Err(ConnectionError::AuthFailed(_))));
+        assert!(server.is_err());
+
+        // A responder answers a wrong proof with a refusal
+        let (server, replies) = tokio::join!(
+            responder(&listener, &secret),
+            raw_initiator(&endpoint, Message::new_hello(&local, new_nonce()), Some("00".repeat(32))),
+        );
+        assert!(matches!(server, Err(ConnectionError::AuthFailed(_))));
+        assert_eq!(replies[1].msg_type, MessageType::Auth);
+        assert!(replies[1].error.is_some());
+
+        // ...and a hello without a challenge before anything else
+        let hello = Message { nonce: None, ..Message::new_hello(&local, new_nonce()) };
+        let (server, replies) = tokio::join!(responder(&listener, &secret), raw_initiator(&endpoint, hello, None));
+        assert!(matches!(server, Err(ConnectionError::AuthFailed(_))));
+        assert!(replies[0].error.is_some());
+        assert!(replies[0].proof.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_streaming_and_cancellation() {
+        let (server, client, _) = pair(loopback()).await;
+
+        // Polled as a plain `Stream`, as stream combinators would
+        let mut stream = client.call_streaming("count", json!({ "n": 2 })).await.unwrap();
+        let mut items = Vec::new();
+        while let Some(item) =
+            std::future::poll_fn(|cx| futures_core::Stream::poll_next(Pin::new(&mut stream), cx)).await
+        {
+            items.push(item.unwrap());
+        }
+        assert_eq!(
+            items,
+            [
+                StreamItem::Chunk(json!(1)),
+                StreamItem::Progress(json!({ "done": 1, "total": 2 })),
+                StreamItem::Chunk(json!(2)),
+                StreamItem::Progress(json!({ "done": 2, "total": 2 })),
+                StreamItem::Result(json!(2)),
+            ]
+        );
+        // A plain call gets only the result
+        assert_eq!(client.call("count", json!({ "n": 3 })).await.unwrap(), json!(3));
+
+        // Cancelling reaches the handler, whose response ends the stream
+        let mut stream = client.call_streaming("wait", json!({})).await.unwrap();
+        stream.cancel().await.unwrap();
+        assert!(matches!(stream.next().await, Some(Err(ConnectionError::Remote(e))) if e.contains("cancelled")));
+        assert!(stream.next().await.is_none());
+
+        // So do dropping a stream and timing out a call
+        drop(client.call_streaming("wait", json!({})).await.unwrap());
+        let result = client.call_with_timeout("wait", json!({}), Duration::from_millis(50)).await;
+        assert!(matches!(result, Err(ConnectionError::Timeout)));
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !server.shared.running.lock().await.is_empty() {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .unwrap();
     }
 }
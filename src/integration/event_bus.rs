@@ -1,4 +1,3 @@
-use async_trait::async_trait;
 use serde_json::Value;
 use std::collections::HashMap;
 use std::sync::Arc;
@@ -40,6 +39,14 @@ impl EventBus {
         }
     }
     
+    /// Names of the events with at least one listener, sorted.
+    pub async fn events(&self) -> Vec<String> {
+        let listeners = self.listeners.read().await;
+        let mut events: Vec<String> = listeners.keys().cloned().collect();
+        events.sort();
+        events
+    }
+    
     /// Remove all listeners for an event.
     pub async fn remove_listeners(&self, event_name: &str) {
         let mut listeners = self.listeners.write().await;
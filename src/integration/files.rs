@@ -0,0 +1,123 @@
+use crate::integration::connection::{ConnectionError, ConnectionManager, StreamItem};
+use crate::integration::relay::{relayed_guest, GUEST_CALL_FUNCTION};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use thiserror::Error;
+
+/// Guest function streaming a file: a `progress` with `{"size": N}`, then
+/// `stream` chunks of `{"data": "<base64>"}`.
+pub const FS_READ: &str = "fs.read";
+
+/// Guest function writing `{"data": "<base64>"}` to `path`, replacing the file
+/// unless `append` is set.
+pub const FS_WRITE: &str = "fs.write";
+
+/// Guest function listing everything below a directory as
+/// `{"entries": [{"path": "<relative>", "dir": bool}]}`.
+pub const FS_LIST: &str = "fs.list";
+
+/// Guest function creating a directory and its parents.
+pub const FS_MKDIR: &str = "fs.mkdir";
+
+#[derive(Error, Debug)]
+pub enum FilesError {
+    #[error("{0}")]
+    Connection(#[from] ConnectionError),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Invalid reply from the guest's {function}: {reason}")]
+    InvalidReply { function: &'static str, reason: String },
+}
+
+/// One entry of an `fs.list` reply.
+#[derive(Debug, Clone, Deserialize)]
+pub struct GuestEntry {
+    pub path: String,
+    #[serde(default)]
+    pub dir: bool,
+}
+
+#[derive(Deserialize)]
+struct Listing {
+    entries: Vec<GuestEntry>,
+}
+
+/// File access to the guest through the integration host's relay.
+pub struct GuestFiles {
+    relay: ConnectionManager,
+}
+
+impl GuestFiles {
+    /// Use `relay` when its guest offers every file function; `None` otherwise.
+    pub async fn new(relay: ConnectionManager) -> Result<Option<Self>, ConnectionError> {
+        let guest = relayed_guest(&relay).await?;
+        let offered = [FS_READ, FS_WRITE, FS_LIST, FS_MKDIR]
+            .iter()
+            .all(|f| guest.functions.iter().any(|g| g == f));
+        Ok(offered.then_some(Self { relay }))
+    }
+
+    /// Stream `path` to `on_chunk`, reporting the size once the guest sends it.
+    pub async fn read(
+        &self,
+        path: &str,
+        mut on_size: impl FnMut(u64),
+        mut on_chunk: impl FnMut(&[u8]) -> std::io::Result<()>,
+    ) -> Result<u64, FilesError> {
+        let mut call = self.relay.call_streaming(GUEST_CALL_FUNCTION, guest_call(FS_READ, json!({ "path": path }))).await?;
+        let mut bytes = 0;
+        while let Some(item) = call.next().await {
+            match item? {
+                StreamItem::Progress(progress) => {
+                    if let Some(size) = progress.get("size").and_then(Value::as_u64) {
+                        on_size(size);
+                    }
+                }
+                StreamItem::Chunk(chunk) => {
+                    let data = chunk
+                        .get("data")
+                        .and_then(Value::as_str)
+                        .and_then(|data| STANDARD.decode(data).ok())
+                        .ok_or_else(|| invalid(FS_READ, "chunk without base64 data"))?;
+                    on_chunk(&data)?;
+                    bytes += data.len() as u64;
+                }
+                StreamItem::Result(_) => break,
+            }
+        }
+        Ok(bytes)
+    }
+
+    /// Write one chunk of a file, replacing it unless `append`.
+    pub async fn write(&self, path: &str, data: &[u8], append: bool) -> Result<(), FilesError> {
+        let args = json!({ "path": path, "data": STANDARD.encode(data), "append": append });
+        self.relay.call(GUEST_CALL_FUNCTION, guest_call(FS_WRITE, args)).await?;
+        Ok(())
+    }
+
+    /// Everything below `dir`, with paths relative to it.
+    pub async fn list(&self, dir: &str) -> Result<Vec<GuestEntry>, FilesError> {
+        let listing = self.relay.call(GUEST_CALL_FUNCTION, guest_call(FS_LIST, json!({ "path": dir }))).await?;
+        let listing: Listing = serde_json::from_value(listing).map_err(|e| invalid(FS_LIST, &e.to_string()))?;
+        Ok(listing.entries)
+    }
+
+    pub async fn mkdir(&self, dir: &str) -> Result<(), FilesError> {
+        self.relay.call(GUEST_CALL_FUNCTION, guest_call(FS_MKDIR, json!({ "path": dir }))).await?;
+        Ok(())
+    }
+
+    pub async fn close(&self) {
+        self.relay.close().await;
+    }
+}
+
+fn guest_call(function: &str, args: Value) -> Value {
+    json!({ "function": function, "args": args })
+}
+
+fn invalid(function: &'static str, reason: &str) -> FilesError {
+    FilesError::InvalidReply { function, reason: reason.to_string() }
+}
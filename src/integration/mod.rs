@@ -1,9 +1,19 @@
 pub mod protocol;
 pub mod registry;
 pub mod connection;
+pub mod transport;
+pub mod auth;
+pub mod channel;
 pub mod event_bus;
+pub mod relay;
+pub mod files;
 
 pub use protocol::*;
 pub use registry::*;
 pub use connection::*;
+pub use transport::*;
+pub use auth::*;
+pub use channel::*;
 pub use event_bus::*;
+pub use relay::*;
+pub use files::*;
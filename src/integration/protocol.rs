@@ -1,5 +1,46 @@
 use serde::{Deserialize, Serialize};
-use uuid::Uuid;
+use crate::util::random::random_uuid;
+
+/// Newest protocol version this build speaks.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// Oldest protocol version this build still speaks.
+pub const MIN_PROTOCOL_VERSION: u32 = 1;
+
+/// First version with `stream`, `progress` and `cancel` messages.
+pub const STREAMING_VERSION: u32 = 2;
+
+/// What one side of a connection offers, exchanged in `hello` and `welcome`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Capabilities {
+    /// Newest protocol version in `hello`; the agreed version in `welcome`.
+    pub protocol: u32,
+    pub min_protocol: u32,
+    /// Implementation name and version, e.g. `portaqemu/0.3.0`.
+    pub implementation: String,
+    #[serde(default)]
+    pub functions: Vec<String>,
+    #[serde(default)]
+    pub events: Vec<String>,
+}
+
+impl Capabilities {
+    pub fn new(functions: Vec<String>, events: Vec<String>) -> Self {
+        Self {
+            protocol: PROTOCOL_VERSION,
+            min_protocol: MIN_PROTOCOL_VERSION,
+            implementation: concat!("portaqemu/", env!("CARGO_PKG_VERSION")).to_string(),
+            functions,
+            events,
+        }
+    }
+
+    /// Highest version both sides speak, if any.
+    pub fn negotiate(&self, peer: &Capabilities) -> Option<u32> {
+        let version = self.protocol.min(peer.protocol);
+        (version >= self.min_protocol.max(peer.min_protocol)).then_some(version)
+    }
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Message {
@@ -18,6 +59,12 @@ pub struct Message {
     pub event: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub data: Option<serde_json::Value>,
+    /// Challenge sent in `hello` and `welcome`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// Answer to the peer's challenge, in `welcome` and `auth`.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof: Option<String>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
@@ -26,6 +73,18 @@ pub enum MessageType {
     Call,
     Response,
     Event,
+    /// Opens a connection, carrying the initiator's `Capabilities` in `data`.
+    Hello,
+    /// Answers `hello` with the responder's `Capabilities`, or refuses it with `error`.
+    Welcome,
+    /// The initiator's `proof`, then the responder's verdict (`error` if refused).
+    Auth,
+    /// A chunk of a call's output in `data`, sent before its response.
+    Stream,
+    /// Progress of a call in `data`, sent before its response.
+    Progress,
+    /// Asks the callee to stop the call `id`; its response still follows.
+    Cancel,
 }
 
 impl Message {
@@ -39,6 +98,8 @@ impl Message {
             error: None,
             event: None,
             data: None,
+            nonce: None,
+            proof: None,
         }
     }
     
@@ -52,6 +113,8 @@ impl Message {
             error: None,
             event: None,
             data: None,
+            nonce: None,
+            proof: None,
         }
     }
     
@@ -65,12 +128,14 @@ impl Message {
             error: Some(error),
             event: None,
             data: None,
+            nonce: None,
+            proof: None,
         }
     }
     
     pub fn new_event(event: String, data: serde_json::Value) -> Self {
         Self {
-            id: Uuid::new_v4().to_string(),
+            id: random_uuid().to_string(),
             msg_type: MessageType::Event,
             function: None,
             args: None,
@@ -78,6 +143,86 @@ impl Message {
             error: None,
             event: Some(event),
             data: Some(data),
+            nonce: None,
+            proof: None,
+        }
+    }
+    
+    pub fn new_hello(capabilities: &Capabilities, nonce: String) -> Self {
+        Self {
+            id: random_uuid().to_string(),
+            msg_type: MessageType::Hello,
+            function: None,
+            args: None,
+            result: None,
+            error: None,
+            event: None,
+            data: serde_json::to_value(capabilities).ok(),
+            nonce: Some(nonce),
+            proof: None,
+        }
+    }
+    
+    /// Reply to the hello `id`; an `error` refuses the connection.
+    pub fn new_welcome(id: String, capabilities: &Capabilities, error: Option<String>) -> Self {
+        Self {
+            id,
+            msg_type: MessageType::Welcome,
+            function: None,
+            args: None,
+            result: None,
+            error,
+            event: None,
+            data: serde_json::to_value(capabilities).ok(),
+            nonce: None,
+            proof: None,
+        }
+    }
+    
+    /// Authentication step of the handshake started by hello `id`.
+    pub fn new_auth(id: String, proof: Option<String>, error: Option<String>) -> Self {
+        Self {
+            id,
+            msg_type: MessageType::Auth,
+            function: None,
+            args: None,
+            result: None,
+            error,
+            event: None,
+            data: None,
+            nonce: None,
+            proof,
+        }
+    }
+    
+    /// A `stream` or `progress` update for call `id`.
+    pub fn new_update(id: String, msg_type: MessageType, data: serde_json::Value) -> Self {
+        Self {
+            id,
+            msg_type,
+            function: None,
+            args: None,
+            result: None,
+            error: None,
+            event: None,
+            data: Some(data),
+            nonce: None,
+            proof: None,
+        }
+    }
+    
+    pub fn new_cancel(id: String) -> Self {
+        Self {
+            id,
+            msg_type: MessageType::Cancel,
+            function: None,
+            args: None,
+            result: None,
+            error: None,
+            event: None,
+            data: None,
+            nonce: None,
+            proof: None,
         }
     }
 }
@@ -1,56 +1,236 @@
+use crate::util::cancel::CancellationToken;
 use async_trait::async_trait;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 use serde_json::Value;
 use std::collections::HashMap;
+use std::future::Future;
+use std::pin::Pin;
 use std::sync::Arc;
 use tokio::sync::RwLock;
 use thiserror::Error;
 
+/// Built-in function listing every registered function with its metadata.
+pub const LIST_FUNCTION: &str = "rpc.list";
+
 #[derive(Error, Debug)]
 pub enum RegistryError {
     #[error("Function '{0}' not found")]
     NotFound(String),
+    #[error("Invalid arguments for '{function}': {reason}")]
+    InvalidArgs { function: String, reason: String },
     #[error("Function execution error: {0}")]
     Execution(String),
+    #[error("Call cancelled")]
+    Cancelled,
+}
+
+pub type HandlerFuture = Pin<Box<dyn Future<Output = Result<Value, RegistryError>> + Send>>;
+
+pub type FunctionHandler = Arc<dyn Fn(Value, CallContext) -> HandlerFuture + Send + Sync>;
+
+/// Partial output a handler reports before its result.
+#[derive(Debug, Clone, PartialEq)]
+pub enum CallUpdate {
+    /// A chunk of streamed output.
+    Stream(Value),
+    /// How far along the call is.
+    Progress(Value),
+}
+
+/// Where a running call's updates go (the caller, for calls over a connection).
+#[async_trait]
+pub trait UpdateSink: Send + Sync {
+    async fn send(&self, update: CallUpdate);
+}
+
+/// Per-call context handed to handlers: cancellation, plus streamed output and
+/// progress for the caller. Updates are dropped when nobody is listening.
+#[derive(Clone, Default)]
+pub struct CallContext {
+    cancel: CancellationToken,
+    sink: Option<Arc<dyn UpdateSink>>,
 }
 
-pub type FunctionHandler = Arc<dyn Fn(Value) -> Result<Value, RegistryError> + Send + Sync>;
+impl CallContext {
+    pub fn new(cancel: CancellationToken, sink: Option<Arc<dyn UpdateSink>>) -> Self {
+        Self { cancel, sink }
+    }
+
+    /// Cancelled when the caller gives up on the call.
+    pub fn cancellation(&self) -> &CancellationToken {
+        &self.cancel
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancel.is_cancelled()
+    }
+
+    /// `Err(RegistryError::Cancelled)` once cancelled, for use with `?` between steps.
+    pub fn check_cancelled(&self) -> Result<(), RegistryError> {
+        if self.is_cancelled() { Err(RegistryError::Cancelled) } else { Ok(()) }
+    }
+
+    /// Send a chunk of output ahead of the result.
+    pub async fn stream(&self, chunk: Value) {
+        if let Some(sink) = &self.sink {
+            sink.send(CallUpdate::Stream(chunk)).await;
+        }
+    }
+
+    /// Report progress ahead of the result.
+    pub async fn progress(&self, data: Value) {
+        if let Some(sink) = &self.sink {
+            sink.send(CallUpdate::Progress(data)).await;
+        }
+    }
+}
+
+/// Arguments of a typed function that takes none (`{}` or `null`).
+#[derive(Debug, Clone, Copy, Default, Deserialize)]
+pub struct NoArgs {}
+
+/// Name and metadata of a registered function, as reported by `rpc.list`.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct FunctionInfo {
+    pub name: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// Shape of the expected arguments (a JSON Schema, by convention).
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub args: Option<Value>,
+}
+
+impl FunctionInfo {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self { name: name.into(), ..Default::default() }
+    }
+
+    pub fn description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    pub fn args(mut self, schema: Value) -> Self {
+        self.args = Some(schema);
+        self
+    }
+}
+
+impl From<String> for FunctionInfo {
+    fn from(name: String) -> Self {
+        Self::new(name)
+    }
+}
+
+impl From<&str> for FunctionInfo {
+    fn from(name: &str) -> Self {
+        Self::new(name)
+    }
+}
+
+struct Entry {
+    info: FunctionInfo,
+    handler: FunctionHandler,
+}
+
+type Functions = Arc<RwLock<HashMap<String, Entry>>>;
 
 /// Function registry for RPC calls.
 pub struct FunctionRegistry {
-    functions: Arc<RwLock<HashMap<String, FunctionHandler>>>,
+    functions: Functions,
 }
 
 impl FunctionRegistry {
     pub fn new() -> Self {
-        Self {
-            functions: Arc::new(RwLock::new(HashMap::new())),
-        }
+        let functions: Functions = Arc::new(RwLock::new(HashMap::new()));
+        let listed = functions.clone();
+        let handler: FunctionHandler = Arc::new(move |_, _| {
+            let functions = listed.clone();
+            Box::pin(async move { Ok(serde_json::to_value(list(&functions).await).unwrap_or_default()) })
+        });
+        let info = FunctionInfo::new(LIST_FUNCTION).description("List registered functions and their arguments");
+        functions
+            .try_write()
+            .expect("new registry is not shared yet")
+            .insert(LIST_FUNCTION.to_string(), Entry { info, handler });
+        Self { functions }
+    }
+
+    /// Register an async handler taking and returning raw JSON.
+    /// `function` is a name or a `FunctionInfo` with metadata.
+    pub async fn register<F, Fut>(&self, function: impl Into<FunctionInfo>, handler: F)
+    where
+        F: Fn(Value, CallContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<Value, RegistryError>> + Send + 'static,
+    {
+        let info = function.into();
+        let handler: FunctionHandler = Arc::new(move |args, ctx| Box::pin(handler(args, ctx)));
+        self.functions.write().await.insert(info.name.clone(), Entry { info, handler });
     }
-    
-    /// Register a function handler.
-    pub async fn register<F>(&self, name: String, handler: F)
+
+    /// Register an async handler with typed arguments and result. Arguments that
+    /// don't deserialize into `A` fail with `RegistryError::InvalidArgs`.
+    pub async fn register_typed<A, R, F, Fut>(&self, function: impl Into<FunctionInfo>, handler: F)
     where
-        F: Fn(Value) -> Result<Value, RegistryError> + Send + Sync + 'static,
+        A: DeserializeOwned + Send + 'static,
+        R: Serialize + 'static,
+        F: Fn(A, CallContext) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<R, RegistryError>> + Send + 'static,
     {
-        let mut functions = self.functions.write().await;
-        functions.insert(name, Arc::new(handler));
+        let info = function.into();
+        let name = info.name.clone();
+        let handler = Arc::new(handler);
+        self.register(info, move |args: Value, ctx| {
+            // Callers commonly omit arguments entirely
+            let args = if args.is_null() { Value::Object(Default::default()) } else { args };
+            let parsed = serde_json::from_value::<A>(args).map_err(|e| RegistryError::InvalidArgs {
+                function: name.clone(),
+                reason: e.to_string(),
+            });
+            let handler = handler.clone();
+            async move {
+                let result = handler(parsed?, ctx).await?;
+                serde_json::to_value(result).map_err(|e| RegistryError::Execution(e.to_string()))
+            }
+        })
+        .await;
     }
-    
-    /// Call a registered function.
+
+    /// Call a registered function, with no way to cancel it or see its updates.
     pub async fn call(&self, name: &str, args: Value) -> Result<Value, RegistryError> {
-        let functions = self.functions.read().await;
-        let handler = functions
-            .get(name)
-            .ok_or_else(|| RegistryError::NotFound(name.to_string()))?;
-        
-        handler(args)
+        self.call_with(name, args, CallContext::default()).await
+    }
+
+    /// Call a registered function with `ctx` for cancellation and updates.
+    pub async fn call_with(&self, name: &str, args: Value, ctx: CallContext) -> Result<Value, RegistryError> {
+        // Release the lock before running the handler, which may take a while
+        let handler = {
+            let functions = self.functions.read().await;
+            functions
+                .get(name)
+                .map(|entry| entry.handler.clone())
+                .ok_or_else(|| RegistryError::NotFound(name.to_string()))?
+        };
+        handler(args, ctx).await
     }
-    
+
     /// Check if a function is registered.
     pub async fn has_function(&self, name: &str) -> bool {
         let functions = self.functions.read().await;
         functions.contains_key(name)
     }
+
+    /// Metadata of all registered functions, sorted by name.
+    pub async fn list(&self) -> Vec<FunctionInfo> {
+        list(&self.functions).await
+    }
+}
+
+async fn list(functions: &Functions) -> Vec<FunctionInfo> {
+    let mut infos: Vec<FunctionInfo> = functions.read().await.values().map(|entry| entry.info.clone()).collect();
+    infos.sort_by(|a, b| a.name.cmp(&b.name));
+    infos
 }
 
 impl Default for FunctionRegistry {
@@ -58,3 +238,44 @@ impl Default for FunctionRegistry {
         Self::new()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[derive(Deserialize)]
+    struct AddArgs {
+        a: i64,
+        b: i64,
+    }
+
+    #[tokio::test]
+    async fn test_typed_handlers_and_list() {
+        let registry = FunctionRegistry::new();
+        registry
+            .register_typed(
+                FunctionInfo::new("add").description("Add two numbers").args(json!({ "a": "integer", "b": "integer" })),
+                |args: AddArgs, _| async move {
+                    tokio::task::yield_now().await;
+                    Ok(args.a + args.b)
+                },
+            )
+            .await;
+        registry.register_typed("noop", |_: NoArgs, _| async { Ok(()) }).await;
+
+        assert_eq!(registry.call("add", json!({ "a": 2, "b": 3 })).await.unwrap(), json!(5));
+        assert_eq!(registry.call("noop", Value::Null).await.unwrap(), Value::Null);
+        assert!(matches!(
+            registry.call("add", json!({ "a": "two" })).await,
+            Err(RegistryError::InvalidArgs { function, .. }) if function == "add"
+        ));
+        assert!(matches!(registry.call("sub", json!({})).await, Err(RegistryError::NotFound(_))));
+
+        let listed: Vec<FunctionInfo> = serde_json::from_value(registry.call(LIST_FUNCTION, json!({})).await.unwrap()).unwrap();
+        let names: Vec<&str> = listed.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(names, ["add", "noop", LIST_FUNCTION]);
+        assert_eq!(listed[0].description, "Add two numbers");
+        assert!(listed[0].args.is_some());
+    }
+}
@@ -0,0 +1,220 @@
+use crate::integration::auth::Secret;
+use crate::integration::connection::{ConnectionError, ConnectionManager, Role, StreamItem};
+use crate::integration::event_bus::EventBus;
+use crate::integration::protocol::Capabilities;
+use crate::integration::registry::{CallContext, FunctionInfo, FunctionRegistry, NoArgs, RegistryError};
+use crate::integration::transport::{Endpoint, Listener};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::Path;
+use std::sync::Arc;
+use tokio::sync::RwLock;
+
+/// Relay function reporting the connected guest's capabilities.
+pub const GUEST_INFO_FUNCTION: &str = "guest.info";
+
+/// Relay function calling a guest function, passing its stream and progress through.
+pub const GUEST_CALL_FUNCTION: &str = "guest.call";
+
+/// The guest connection of the current integration session, if any.
+#[derive(Clone, Default)]
+pub struct GuestLink(Arc<RwLock<Option<Arc<ConnectionManager>>>>);
+
+impl GuestLink {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// The connected guest, unless its session has ended.
+    pub async fn get(&self) -> Option<Arc<ConnectionManager>> {
+        self.0.read().await.clone().filter(|connection| !connection.is_closed())
+    }
+
+    pub(crate) async fn set(&self, connection: Arc<ConnectionManager>) {
+        *self.0.write().await = Some(connection);
+    }
+
+    /// Forget `connection`, unless a newer session already replaced it.
+    pub(crate) async fn clear(&self, connection: &Arc<ConnectionManager>) {
+        let mut current = self.0.write().await;
+        if current.as_ref().is_some_and(|c| Arc::ptr_eq(c, connection)) {
+            *current = None;
+        }
+    }
+
+    async fn require(&self) -> Result<Arc<ConnectionManager>, RegistryError> {
+        self.get()
+            .await
+            .ok_or_else(|| RegistryError::Execution("No integration guest is connected".to_string()))
+    }
+}
+
+/// Arguments of `guest.call`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GuestCallArgs {
+    pub function: String,
+    #[serde(default)]
+    pub args: Value,
+}
+
+/// Functions offered to other `portaqemu` commands on the host. Only the
+/// integration host holds the chardev, so they reach the guest through it.
+pub async fn relay_registry(guest: GuestLink) -> FunctionRegistry {
+    let registry = FunctionRegistry::new();
+    registry
+        .register_typed(
+            FunctionInfo::new(GUEST_INFO_FUNCTION).description("Capabilities the connected guest announced"),
+            {
+                let guest = guest.clone();
+                move |_: NoArgs, _| {
+                    let guest = guest.clone();
+                    async move { Ok(guest.require().await?.peer_capabilities().clone()) }
+                }
+            },
+        )
+        .await;
+    registry
+        .register_typed(
+            FunctionInfo::new(GUEST_CALL_FUNCTION)
+                .description("Call a guest function, relaying its stream and progress messages")
+                .args(json!({ "function": "string", "args": "object" })),
+            move |args: GuestCallArgs, ctx| {
+                let guest = guest.clone();
+                async move {
+                    let guest = guest.require().await?;
+                    relay_call(&guest, args, &ctx).await
+                }
+            },
+        )
+        .await;
+    registry
+}
+
+async fn relay_call(guest: &ConnectionManager, args: GuestCallArgs, ctx: &CallContext) -> Result<Value, RegistryError> {
+    let remote = |e: ConnectionError| RegistryError::Execution(e.to_string());
+    let mut call = guest.call_streaming(&args.function, args.args).await.map_err(remote)?;
+    loop {
+        let item = tokio::select! {
+            item = call.next() => item,
+            // Dropping the stream cancels the call in the guest
+            _ = ctx.cancellation().cancelled() => return Err(RegistryError::Cancelled),
+        };
+        match item {
+            Some(Ok(StreamItem::Chunk(chunk))) => ctx.stream(chunk).await,
+            Some(Ok(StreamItem::Progress(progress))) => ctx.progress(progress).await,
+            Some(Ok(StreamItem::Result(result))) => return Ok(result),
+            Some(Err(e)) => return Err(remote(e)),
+            None => return Err(remote(ConnectionError::Closed)),
+        }
+    }
+}
+
+/// Where the relay listens: a socket in the owner-only run directory, or a
+/// loopback port where Unix sockets are unavailable (clients still need the secret).
+pub fn relay_endpoint(socket: &Path) -> Endpoint {
+    if cfg!(unix) {
+        Endpoint::Unix(socket.to_path_buf())
+    } else {
+        Endpoint::Tcp(SocketAddr::from((Ipv4Addr::LOCALHOST, 0)))
+    }
+}
+
+/// Accept relay clients on `listener`, each authenticating with `secret` like a guest would.
+pub async fn serve_relay(listener: Listener, secret: Secret, guest: GuestLink) {
+    let registry = Arc::new(relay_registry(guest).await);
+    loop {
+        let stream = match listener.accept().await {
+            Ok(stream) => stream,
+            Err(e) => {
+                tracing::warn!("Integration relay stopped accepting: {}", e);
+                return;
+            }
+        };
+        let (secret, registry) = (secret.clone(), registry.clone());
+        tokio::spawn(async move {
+            match ConnectionManager::new(stream, Role::Responder, &secret, registry, Arc::new(EventBus::new())).await {
+                Ok(connection) => connection.closed().await,
+                Err(e) => tracing::warn!("Integration relay handshake failed: {}", e),
+            }
+        });
+    }
+}
+
+/// Connect to the integration host's relay at `endpoint`.
+pub async fn connect_relay(endpoint: &Endpoint, secret: &Secret) -> Result<ConnectionManager, ConnectionError> {
+    ConnectionManager::connect(endpoint, secret, Arc::new(FunctionRegistry::new()), Arc::new(EventBus::new())).await
+}
+
+/// Capabilities of the guest behind the relay; fails when no guest is connected.
+pub async fn relayed_guest(relay: &ConnectionManager) -> Result<Capabilities, ConnectionError> {
+    Ok(serde_json::from_value(relay.call(GUEST_INFO_FUNCTION, json!({})).await?)?)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::integration::files::{GuestFiles, FS_LIST, FS_MKDIR, FS_READ, FS_WRITE};
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+
+    /// A guest whose `fs.read` streams two chunks; the other file functions just need to exist.
+    async fn fake_guest() -> FunctionRegistry {
+        let registry = FunctionRegistry::new();
+        registry
+            .register(FS_READ, |_, ctx: CallContext| async move {
+                ctx.progress(json!({ "size": 6 })).await;
+                for chunk in ["abc", "def"] {
+                    ctx.stream(json!({ "data": STANDARD.encode(chunk) })).await;
+                }
+                Ok(json!({}))
+            })
+            .await;
+        for name in [FS_WRITE, FS_LIST, FS_MKDIR] {
+            registry.register(name, |_, _| async { Ok(json!({})) }).await;
+        }
+        registry
+    }
+
+    #[tokio::test]
+    async fn test_relay_streams_guest_calls() {
+        let dir = tempfile::tempdir().unwrap();
+        let secret = Secret::generate().unwrap();
+        let guest = GuestLink::new();
+        let listener = Listener::bind(&relay_endpoint(&dir.path().join("relay.sock"))).await.unwrap();
+        let endpoint = listener.local_endpoint().unwrap();
+        tokio::spawn(serve_relay(listener, secret.clone(), guest.clone()));
+
+        // Nothing to relay to until a guest connects
+        let relay = connect_relay(&endpoint, &secret).await.unwrap();
+        assert!(relayed_guest(&relay).await.is_err());
+
+        let (host_end, guest_end) = tokio::io::duplex(64 * 1024);
+        let (host, guest_side) = tokio::join!(
+            ConnectionManager::new(host_end, Role::Responder, &secret, Arc::new(FunctionRegistry::new()), Arc::new(EventBus::new())),
+            async {
+                let registry = Arc::new(fake_guest().await);
+                ConnectionManager::new(guest_end, Role::Initiator, &secret, registry, Arc::new(EventBus::new())).await
+            },
+        );
+        let (host, _guest_side) = (Arc::new(host.unwrap()), guest_side.unwrap());
+        guest.set(host.clone()).await;
+
+        let files = GuestFiles::new(relay).await.unwrap().expect("guest offers the file functions");
+        let (mut size, mut data) = (None, Vec::new());
+        let bytes = files
+            .read("/etc/hostname", |s| size = Some(s), |chunk| {
+                data.extend_from_slice(chunk);
+                Ok(())
+            })
+            .await
+            .unwrap();
+        assert_eq!((size, bytes, data.as_slice()), (Some(6), 6, b"abcdef".as_slice()));
+
+        // A relay client with the wrong secret is refused
+        assert!(connect_relay(&endpoint, &Secret::generate().unwrap()).await.is_err());
+
+        guest.clear(&host).await;
+        assert!(guest.get().await.is_none());
+    }
+}
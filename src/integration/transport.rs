@@ -0,0 +1,172 @@
+use serde::Serialize;
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use thiserror::Error;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+
+/// Largest accepted frame; a peer sending more without a newline is dropped.
+pub const MAX_FRAME_BYTES: u64 = 16 * 1024 * 1024;
+
+#[derive(Error, Debug)]
+pub enum TransportError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Frame exceeds {MAX_FRAME_BYTES} bytes")]
+    FrameTooLarge,
+    #[error("Invalid endpoint '{0}' (expected HOST:PORT or unix:PATH)")]
+    InvalidEndpoint(String),
+    #[error("Unix sockets are not supported on this platform")]
+    UnixUnsupported,
+}
+
+/// Byte stream carrying integration messages.
+pub trait Stream: AsyncRead + AsyncWrite + Send + Unpin + 'static {}
+impl<T: AsyncRead + AsyncWrite + Send + Unpin + 'static> Stream for T {}
+
+/// Address of an integration peer: `HOST:PORT` or `unix:PATH`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Endpoint {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl std::str::FromStr for Endpoint {
+    type Err = TransportError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("unix:") {
+            Some(path) => Ok(Endpoint::Unix(PathBuf::from(path))),
+            None => s.parse().map(Endpoint::Tcp).map_err(|_| TransportError::InvalidEndpoint(s.to_string())),
+        }
+    }
+}
+
+impl fmt::Display for Endpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Endpoint::Tcp(addr) => write!(f, "{}", addr),
+            Endpoint::Unix(path) => write!(f, "unix:{}", path.display()),
+        }
+    }
+}
+
+impl Endpoint {
+    pub async fn connect(&self) -> Result<Box<dyn Stream>, TransportError> {
+        match self {
+            Endpoint::Tcp(addr) => {
+                let stream = TcpStream::connect(addr).await?;
+                stream.set_nodelay(true)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Endpoint::Unix(path) => Ok(Box::new(tokio::net::UnixStream::connect(path).await?)),
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err(TransportError::UnixUnsupported),
+        }
+    }
+}
+
+/// Accepts integration connections on an `Endpoint`.
+pub enum Listener {
+    Tcp(TcpListener),
+    #[cfg(unix)]
+    Unix(tokio::net::UnixListener, PathBuf),
+}
+
+impl Listener {
+    pub async fn bind(endpoint: &Endpoint) -> Result<Self, TransportError> {
+        match endpoint {
+            Endpoint::Tcp(addr) => Ok(Listener::Tcp(TcpListener::bind(addr).await?)),
+            #[cfg(unix)]
+            Endpoint::Unix(path) => {
+                // A socket left by a previous run would make bind fail
+                let _ = std::fs::remove_file(path);
+                Ok(Listener::Unix(tokio::net::UnixListener::bind(path)?, path.clone()))
+            }
+            #[cfg(not(unix))]
+            Endpoint::Unix(_) => Err(TransportError::UnixUnsupported),
+        }
+    }
+
+    /// The bound endpoint, with the actual port when binding to port 0.
+    pub fn local_endpoint(&self) -> Result<Endpoint, TransportError> {
+        match self {
+            Listener::Tcp(listener) => Ok(Endpoint::Tcp(listener.local_addr()?)),
+            #[cfg(unix)]
+            Listener::Unix(_, path) => Ok(Endpoint::Unix(path.clone())),
+        }
+    }
+
+    pub async fn accept(&self) -> Result<Box<dyn Stream>, TransportError> {
+        match self {
+            Listener::Tcp(listener) => {
+                let (stream, _) = listener.accept().await?;
+                stream.set_nodelay(true)?;
+                Ok(Box::new(stream))
+            }
+            #[cfg(unix)]
+            Listener::Unix(listener, _) => Ok(Box::new(listener.accept().await?.0)),
+        }
+    }
+}
+
+#[cfg(unix)]
+impl Drop for Listener {
+    fn drop(&mut self) {
+        if let Listener::Unix(_, path) = self {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+}
+
+/// Read one newline-delimited frame. Returns `None` at end of stream.
+pub async fn read_frame<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<Option<Vec<u8>>, TransportError> {
+    let mut frame = Vec::new();
+    let n = (&mut *reader).take(MAX_FRAME_BYTES + 1).read_until(b'\n', &mut frame).await?;
+    if n == 0 {
+        return Ok(None);
+    }
+    if frame.last() != Some(&b'\n') {
+        if n as u64 > MAX_FRAME_BYTES {
+            return Err(TransportError::FrameTooLarge);
+        }
+        // The peer closed mid-frame
+        return Ok(None);
+    }
+    frame.pop();
+    Ok(Some(frame))
+}
+
+/// Discard the rest of the current frame, through its newline, without buffering it.
+/// Returns `false` if the stream ended first.
+pub async fn skip_frame<R: AsyncBufRead + Unpin>(reader: &mut R) -> Result<bool, TransportError> {
+    loop {
+        let buf = reader.fill_buf().await?;
+        if buf.is_empty() {
+            return Ok(false);
+        }
+        match buf.iter().position(|&b| b == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(true);
+            }
+            None => {
+                let len = buf.len();
+                reader.consume(len);
+            }
+        }
+    }
+}
+
+/// Write `value` as one JSON line.
+pub async fn write_frame<W: AsyncWrite + Unpin, T: Serialize>(writer: &mut W, value: &T) -> Result<(), TransportError> {
+    let mut frame = serde_json::to_vec(value)?;
+    frame.push(b'\n');
+    writer.write_all(&frame).await?;
+    writer.flush().await?;
+    Ok(())
+}
@@ -9,5 +9,9 @@ pub mod doctor;
 pub mod output;
 pub mod util;
 pub mod integration;
+pub mod provision;
+pub mod ssh;
+pub mod autoforward;
+pub mod console;
 
 pub use cli::run;
@@ -1,6 +1,5 @@
 use portaqemu::cli;
 use std::process;
-use tracing_subscriber;
 
 fn main() {
     // Initialize logging
@@ -0,0 +1,284 @@
+use crate::config::schema::ResolvedConfig;
+use std::fmt::Write;
+
+/// Component attributes shared by every amd64 unattend component.
+const COMPONENT_ATTRS: &str = r#"processorArchitecture="amd64" publicKeyToken="31bf3856ad364e35" language="neutral" versionScope="nonSxS""#;
+
+/// Drive letters Setup may assign to the virtio-win ISO.
+const DRIVER_LETTERS: &[char] = &['D', 'E', 'F', 'G'];
+
+/// Drivers injected during Setup: storage (virtio-blk), network (virtio-net)
+/// and virtio-serial for the guest agent channel.
+const DRIVER_DIRS: &[&str] = &["viostor", "NetKVM", "vioserial"];
+
+/// Guest agent installer within the virtio-win ISO.
+const GUEST_AGENT_MSI: &str = r"guest-agent\qemu-ga-x86_64.msi";
+
+/// Guest OS directories within the virtio-win ISO.
+const DRIVER_OS_DIRS: &[&str] = &["w11", "w10"];
+
+/// Windows computer names are limited to 15 characters.
+const MAX_COMPUTER_NAME_LEN: usize = 15;
+
+/// Inputs for an autounattend.xml.
+pub struct UnattendParams<'a> {
+    pub computer_name: &'a str,
+    pub user: &'a str,
+    pub password: &'a str,
+    pub public_key: &'a str,
+    pub locale: &'a str,
+    pub keyboard: &'a str,
+    pub timezone: &'a str,
+    pub edition: &'a str,
+    pub product_key: Option<&'a str>,
+}
+
+impl<'a> UnattendParams<'a> {
+    pub fn from_config(config: &'a ResolvedConfig, password: &'a str, public_key: &'a str) -> Self {
+        let computer_name = config
+            .provision
+            .as_ref()
+            .map(|p| p.hostname.as_str())
+            .unwrap_or(&config.vm.name);
+        Self {
+            computer_name: truncate(computer_name, MAX_COMPUTER_NAME_LEN),
+            user: &config.vscode.ssh_user,
+            password,
+            public_key,
+            locale: &config.install.locale,
+            keyboard: &config.install.keyboard,
+            timezone: &config.install.timezone,
+            edition: &config.install.edition,
+            product_key: config.install.product_key.as_deref(),
+        }
+    }
+}
+
+fn truncate(s: &str, max: usize) -> &str {
+    match s.char_indices().nth(max) {
+        Some((idx, _)) => &s[..idx],
+        None => s,
+    }
+}
+
+/// Escape text for inclusion in XML element content.
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+/// Quote a string for a PowerShell single-quoted literal.
+fn ps_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+/// Commands run at first logon: OpenSSH Server with key-based access, then the
+/// guest agent from the virtio-win ISO when it is still attached.
+fn first_logon_commands(params: &UnattendParams) -> Vec<String> {
+    let keys_file = r"C:\ProgramData\ssh\administrators_authorized_keys";
+    vec![
+        "Add-WindowsCapability -Online -Name OpenSSH.Server~~~~0.0.1.0".to_string(),
+        "Set-Service -Name sshd -StartupType Automatic; Start-Service sshd".to_string(),
+        "if (-not (Get-NetFirewallRule -Name sshd -ErrorAction SilentlyContinue)) { New-NetFirewallRule -Name sshd -DisplayName 'OpenSSH Server' -Protocol TCP -LocalPort 22 -Action Allow }".to_string(),
+        format!(
+            "Set-Content -Path {} -Value {} -Encoding ascii; icacls {} /inheritance:r /grant 'Administrators:F' /grant 'SYSTEM:F'",
+            ps_quote(keys_file),
+            ps_quote(params.public_key.trim()),
+            ps_quote(keys_file)
+        ),
+        "Restart-Service sshd".to_string(),
+        format!(
+            "foreach ($d in {}) {{ $m = $d + ':\\{}'; if (Test-Path $m) {{ Start-Process msiexec -Wait -ArgumentList '/i', $m, '/qn'; break }} }}",
+            DRIVER_LETTERS.iter().map(|l| format!("'{}'", l)).collect::<Vec<_>>().join(","),
+            GUEST_AGENT_MSI
+        ),
+    ]
+}
+
+/// Generate autounattend.xml for a fully unattended Windows install.
+pub fn generate_autounattend(params: &UnattendParams) -> String {
+    let mut xml = String::new();
+    let e = xml_escape;
+
+    let _ = writeln!(xml, r#"<?xml version="1.0" encoding="utf-8"?>"#);
+    let _ = writeln!(
+        xml,
+        r#"<unattend xmlns="urn:schemas-microsoft-com:unattend" xmlns:wcm="http://schemas.microsoft.com/WMIConfig/2002/State">"#
+    );
+
+    // windowsPE: locale, drivers, hardware check bypass, disk layout, image selection
+    let _ = writeln!(xml, r#"  <settings pass="windowsPE">"#);
+    let _ = writeln!(xml, r#"    <component name="Microsoft-Windows-International-Core-WinPE" {}>"#, COMPONENT_ATTRS);
+    let _ = writeln!(xml, "      <SetupUILanguage><UILanguage>{}</UILanguage></SetupUILanguage>", e(params.locale));
+    write_locales(&mut xml, params);
+    let _ = writeln!(xml, "    </component>");
+
+    let _ = writeln!(xml, r#"    <component name="Microsoft-Windows-PnpCustomizationsWinPE" {}>"#, COMPONENT_ATTRS);
+    let _ = writeln!(xml, "      <DriverPaths>");
+    let mut key = 1;
+    for letter in DRIVER_LETTERS {
+        for dir in DRIVER_DIRS {
+            for os in DRIVER_OS_DIRS {
+                let _ = writeln!(
+                    xml,
+                    r#"        <PathAndCredentials wcm:action="add" wcm:keyValue="{}"><Path>{}:\{}\{}\amd64</Path></PathAndCredentials>"#,
+                    key, letter, dir, os
+                );
+                key += 1;
+            }
+        }
+    }
+    let _ = writeln!(xml, "      </DriverPaths>");
+    let _ = writeln!(xml, "    </component>");
+
+    let _ = writeln!(xml, r#"    <component name="Microsoft-Windows-Setup" {}>"#, COMPONENT_ATTRS);
+    let _ = writeln!(xml, "      <RunSynchronous>");
+    for (order, check) in ["BypassTPMCheck", "BypassSecureBootCheck", "BypassRAMCheck"].iter().enumerate() {
+        let _ = writeln!(
+            xml,
+            r#"        <RunSynchronousCommand wcm:action="add"><Order>{}</Order><Path>reg add HKLM\SYSTEM\Setup\LabConfig /v {} /t REG_DWORD /d 1 /f</Path></RunSynchronousCommand>"#,
+            order + 1,
+            check
+        );
+    }
+    let _ = writeln!(xml, "      </RunSynchronous>");
+    let _ = writeln!(xml, "      <DiskConfiguration>");
+    let _ = writeln!(xml, r#"        <Disk wcm:action="add">"#);
+    let _ = writeln!(xml, "          <DiskID>0</DiskID>");
+    let _ = writeln!(xml, "          <WillWipeDisk>true</WillWipeDisk>");
+    let _ = writeln!(
+        xml,
+        r#"          <CreatePartitions><CreatePartition wcm:action="add"><Order>1</Order><Type>Primary</Type><Extend>true</Extend></CreatePartition></CreatePartitions>"#
+    );
+    let _ = writeln!(
+        xml,
+        r#"          <ModifyPartitions><ModifyPartition wcm:action="add"><Order>1</Order><PartitionID>1</PartitionID><Format>NTFS</Format><Label>Windows</Label><Letter>C</Letter><Active>true</Active></ModifyPartition></ModifyPartitions>"#
+    );
+    let _ = writeln!(xml, "        </Disk>");
+    let _ = writeln!(xml, "      </DiskConfiguration>");
+    let _ = writeln!(xml, "      <ImageInstall>");
+    let _ = writeln!(xml, "        <OSImage>");
+    let _ = writeln!(
+        xml,
+        r#"          <InstallFrom><MetaData wcm:action="add"><Key>/IMAGE/NAME</Key><Value>{}</Value></MetaData></InstallFrom>"#,
+        e(params.edition)
+    );
+    let _ = writeln!(xml, "          <InstallTo><DiskID>0</DiskID><PartitionID>1</PartitionID></InstallTo>");
+    let _ = writeln!(xml, "        </OSImage>");
+    let _ = writeln!(xml, "      </ImageInstall>");
+    let _ = writeln!(xml, "      <UserData>");
+    let _ = writeln!(xml, "        <AcceptEula>true</AcceptEula>");
+    if let Some(product_key) = params.product_key {
+        let _ = writeln!(
+            xml,
+            "        <ProductKey><Key>{}</Key><WillShowUI>OnError</WillShowUI></ProductKey>",
+            e(product_key)
+        );
+    }
+    let _ = writeln!(xml, "      </UserData>");
+    let _ = writeln!(xml, "    </component>");
+    let _ = writeln!(xml, "  </settings>");
+
+    // specialize: computer name and time zone
+    let _ = writeln!(xml, r#"  <settings pass="specialize">"#);
+    let _ = writeln!(xml, r#"    <component name="Microsoft-Windows-Shell-Setup" {}>"#, COMPONENT_ATTRS);
+    let _ = writeln!(xml, "      <ComputerName>{}</ComputerName>", e(params.computer_name));
+    let _ = writeln!(xml, "      <TimeZone>{}</TimeZone>", e(params.timezone));
+    let _ = writeln!(xml, "    </component>");
+    let _ = writeln!(xml, "  </settings>");
+
+    // oobeSystem: skip OOBE, create the account, enable OpenSSH at first logon
+    let _ = writeln!(xml, r#"  <settings pass="oobeSystem">"#);
+    let _ = writeln!(xml, r#"    <component name="Microsoft-Windows-International-Core" {}>"#, COMPONENT_ATTRS);
+    write_locales(&mut xml, params);
+    let _ = writeln!(xml, "    </component>");
+    let _ = writeln!(xml, r#"    <component name="Microsoft-Windows-Shell-Setup" {}>"#, COMPONENT_ATTRS);
+    let _ = writeln!(xml, "      <OOBE>");
+    for setting in [
+        "HideEULAPage",
+        "HideLocalAccountScreen",
+        "HideOEMRegistrationScreen",
+        "HideOnlineAccountScreens",
+        "HideWirelessSetupInOOBE",
+    ] {
+        let _ = writeln!(xml, "        <{0}>true</{0}>", setting);
+    }
+    let _ = writeln!(xml, "        <ProtectYourPC>3</ProtectYourPC>");
+    let _ = writeln!(xml, "      </OOBE>");
+    let _ = writeln!(xml, "      <UserAccounts>");
+    let _ = writeln!(xml, "        <LocalAccounts>");
+    let _ = writeln!(xml, r#"          <LocalAccount wcm:action="add">"#);
+    let _ = writeln!(xml, "            <Name>{}</Name>", e(params.user));
+    let _ = writeln!(xml, "            <Group>Administrators</Group>");
+    let _ = writeln!(
+        xml,
+        "            <Password><Value>{}</Value><PlainText>true</PlainText></Password>",
+        e(params.password)
+    );
+    let _ = writeln!(xml, "          </LocalAccount>");
+    let _ = writeln!(xml, "        </LocalAccounts>");
+    let _ = writeln!(xml, "      </UserAccounts>");
+    let _ = writeln!(xml, "      <AutoLogon>");
+    let _ = writeln!(xml, "        <Enabled>true</Enabled>");
+    let _ = writeln!(xml, "        <LogonCount>1</LogonCount>");
+    let _ = writeln!(xml, "        <Username>{}</Username>", e(params.user));
+    let _ = writeln!(
+        xml,
+        "        <Password><Value>{}</Value><PlainText>true</PlainText></Password>",
+        e(params.password)
+    );
+    let _ = writeln!(xml, "      </AutoLogon>");
+    let _ = writeln!(xml, "      <FirstLogonCommands>");
+    for (order, command) in first_logon_commands(params).iter().enumerate() {
+        let _ = writeln!(
+            xml,
+            r#"        <SynchronousCommand wcm:action="add"><Order>{}</Order><CommandLine>{}</CommandLine></SynchronousCommand>"#,
+            order + 1,
+            e(&format!("powershell -NoProfile -ExecutionPolicy Bypass -Command \"{}\"", command))
+        );
+    }
+    let _ = writeln!(xml, "      </FirstLogonCommands>");
+    let _ = writeln!(xml, "    </component>");
+    let _ = writeln!(xml, "  </settings>");
+    let _ = writeln!(xml, "</unattend>");
+
+    xml
+}
+
+fn write_locales(xml: &mut String, params: &UnattendParams) {
+    let _ = writeln!(xml, "      <InputLocale>{}</InputLocale>", xml_escape(params.keyboard));
+    let _ = writeln!(xml, "      <SystemLocale>{}</SystemLocale>", xml_escape(params.locale));
+    let _ = writeln!(xml, "      <UILanguage>{}</UILanguage>", xml_escape(params.locale));
+    let _ = writeln!(xml, "      <UserLocale>{}</UserLocale>", xml_escape(params.locale));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_escapes_user_password_and_product_key() {
+        let params = UnattendParams {
+            computer_name: "devvm",
+            user: "dev<&>",
+            password: "p\"w'<d>&",
+            public_key: "ssh-ed25519 AAAA dev",
+            locale: "en-US",
+            keyboard: "en-US",
+            timezone: "UTC",
+            edition: "Windows 11 Pro",
+            product_key: Some("AB<CD>-&"),
+        };
+        let xml = generate_autounattend(&params);
+        assert!(xml.contains("<Name>dev&lt;&amp;&gt;</Name>"));
+        assert!(xml.contains("<Username>dev&lt;&amp;&gt;</Username>"));
+        assert_eq!(xml.matches("<Value>p&quot;w&apos;&lt;d&gt;&amp;</Value>").count(), 2);
+        assert!(xml.contains("AB&lt;CD&gt;-&amp;"));
+        for raw in ["dev<&>", "p\"w'<d>&", "AB<CD>-&"] {
+            assert!(!xml.contains(raw), "{} left unescaped", raw);
+        }
+    }
+}
@@ -0,0 +1,158 @@
+use crate::config::paths::public_key_path;
+use crate::config::schema::{ResolvedConfig, ResolvedProvisionConfig};
+use crate::integration::auth::{AuthError, Secret, GUEST_SECRET_PATH};
+use crate::provision::iso9660::{build_iso, IsoFile};
+use crate::ssh::keys::{restrict_permissions, write_private, KeyError};
+use crate::util::fs_atomic;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use thiserror::Error;
+
+/// Volume label cloud-init looks for when searching for a NoCloud seed.
+const SEED_LABEL: &str = "cidata";
+
+/// Distribution package (and systemd unit) providing the guest agent.
+const GUEST_AGENT_PACKAGE: &str = "qemu-guest-agent";
+
+#[derive(Error, Debug)]
+pub enum SeedError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Atomic write error: {0}")]
+    AtomicWrite(#[from] crate::util::fs_atomic::AtomicWriteError),
+    #[error("No [provision] section in config")]
+    NotConfigured,
+    #[error("Public key not found: {0}")]
+    PublicKeyMissing(String),
+    #[error("{0}")]
+    Secret(#[from] AuthError),
+    #[error("{0}")]
+    Write(#[from] KeyError),
+}
+
+/// Contents of a NoCloud seed.
+#[derive(Debug, Clone)]
+pub struct SeedFiles {
+    pub user_data: String,
+    pub meta_data: String,
+}
+
+impl SeedFiles {
+    /// Hash of all seed inputs, used to decide whether to regenerate.
+    pub fn inputs_hash(&self) -> String {
+        let mut hasher = Sha256::new();
+        hasher.update(self.user_data.as_bytes());
+        hasher.update(b"\0");
+        hasher.update(self.meta_data.as_bytes());
+        format!("{:x}", hasher.finalize())
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SeedOutcome {
+    pub path: PathBuf,
+    pub regenerated: bool,
+}
+
+/// Generate `user-data` and `meta-data` from config.
+pub fn generate_seed_files(
+    config: &ResolvedConfig,
+    provision: &ResolvedProvisionConfig,
+) -> Result<SeedFiles, SeedError> {
+    let pub_path = public_key_path(&config.vscode.identity_file);
+    let public_key = fs::read_to_string(&pub_path)
+        .map_err(|_| SeedError::PublicKeyMissing(pub_path.to_string_lossy().to_string()))?;
+
+    // JSON strings are valid YAML scalars, which avoids hand-rolled quoting
+    let quote = |s: &str| serde_json::Value::from(s).to_string();
+
+    let mut user_data = String::from("#cloud-config\n");
+    user_data.push_str(&format!("hostname: {}\n", quote(&provision.hostname)));
+    user_data.push_str("users:\n");
+    user_data.push_str(&format!("  - name: {}\n", quote(&config.vscode.ssh_user)));
+    user_data.push_str("    sudo: \"ALL=(ALL) NOPASSWD:ALL\"\n");
+    user_data.push_str("    shell: /bin/bash\n");
+    user_data.push_str("    ssh_authorized_keys:\n");
+    user_data.push_str(&format!("      - {}\n", quote(public_key.trim())));
+    user_data.push_str("ssh_pwauth: false\n");
+    let mut packages: Vec<&str> = provision.packages.iter().map(String::as_str).collect();
+    if config.guest_agent.enabled && !packages.contains(&GUEST_AGENT_PACKAGE) {
+        packages.push(GUEST_AGENT_PACKAGE);
+    }
+    if !packages.is_empty() {
+        user_data.push_str("packages:\n");
+        for package in &packages {
+            user_data.push_str(&format!("  - {}\n", quote(package)));
+        }
+    }
+    if config.integration.enabled {
+        // Also available over fw_cfg, but that needs the qemu_fw_cfg module and root
+        let secret = Secret::load(&config.integration.secret_file)?;
+        user_data.push_str("write_files:\n");
+        user_data.push_str(&format!("  - path: {}\n", quote(GUEST_SECRET_PATH)));
+        user_data.push_str("    permissions: \"0600\"\n");
+        user_data.push_str(&format!("    content: {}\n", quote(secret.expose())));
+    }
+    if config.guest_agent.enabled {
+        // The unit is normally started by udev when the channel appears, which
+        // happened before the package was installed
+        user_data.push_str("runcmd:\n");
+        user_data.push_str(&format!("  - [systemctl, start, {}]\n", GUEST_AGENT_PACKAGE));
+    }
+
+    // A new instance-id makes cloud-init re-run per-instance modules
+    let mut hasher = Sha256::new();
+    hasher.update(user_data.as_bytes());
+    let instance_hash = format!("{:x}", hasher.finalize());
+    let meta_data = format!(
+        "instance-id: {}\nlocal-hostname: {}\n",
+        quote(&format!("{}-{}", config.vm.name, &instance_hash[..12])),
+        quote(&provision.hostname)
+    );
+
+    Ok(SeedFiles { user_data, meta_data })
+}
+
+/// Build the seed ISO, skipping the write when its inputs are unchanged.
+pub fn ensure_seed(config: &ResolvedConfig, force: bool) -> Result<SeedOutcome, SeedError> {
+    let provision = config.provision.as_ref().ok_or(SeedError::NotConfigured)?;
+    let files = generate_seed_files(config, provision)?;
+    let hash = files.inputs_hash();
+
+    let hash_path = inputs_hash_path(&provision.seed);
+    let current = fs::read_to_string(&hash_path).ok();
+    if !force && provision.seed.exists() && current.as_deref().map(str::trim) == Some(hash.as_str()) {
+        // Seeds written before they carried the secret may still be readable by others
+        if config.integration.enabled {
+            restrict_permissions(&provision.seed)?;
+        }
+        return Ok(SeedOutcome {
+            path: provision.seed.clone(),
+            regenerated: false,
+        });
+    }
+
+    let iso = build_iso(
+        SEED_LABEL,
+        &[
+            IsoFile { name: "user-data", data: files.user_data.as_bytes() },
+            IsoFile { name: "meta-data", data: files.meta_data.as_bytes() },
+        ],
+    );
+    // The seed carries the integration secret, so only the current user may read it.
+    // The secret is part of the inputs hash, so rotating it rebuilds the seed.
+    write_private(&provision.seed, &iso)?;
+    fs_atomic::atomic_write_str(&hash_path, &hash)?;
+
+    Ok(SeedOutcome {
+        path: provision.seed.clone(),
+        regenerated: true,
+    })
+}
+
+fn inputs_hash_path(seed: &Path) -> PathBuf {
+    let mut path = seed.as_os_str().to_os_string();
+    path.push(".inputs");
+    PathBuf::from(path)
+}
@@ -0,0 +1,238 @@
+// Minimal ISO9660 writer with Joliet names, enough for small flat images
+// such as a cloud-init `cidata` seed. Timestamps are fixed so identical
+// inputs produce identical images.
+
+const SECTOR: usize = 2048;
+/// Sectors 0-15 are the system area.
+const FIRST_DESCRIPTOR: usize = 16;
+
+/// A file placed in the image root directory.
+pub struct IsoFile<'a> {
+    pub name: &'a str,
+    pub data: &'a [u8],
+}
+
+/// Build an ISO9660 image with a Joliet supplementary descriptor.
+/// All files are placed in the root directory.
+pub fn build_iso(volume_id: &str, files: &[IsoFile]) -> Vec<u8> {
+    let mut files: Vec<&IsoFile> = files.iter().collect();
+    files.sort_by(|a, b| a.name.cmp(b.name));
+
+    // Fixed layout: PVD, SVD, terminator, 4 path tables, 2 root dirs, then file data
+    let pvd_sector = FIRST_DESCRIPTOR;
+    let svd_sector = pvd_sector + 1;
+    let term_sector = svd_sector + 1;
+    let l_path_primary = term_sector + 1;
+    let m_path_primary = l_path_primary + 1;
+    let l_path_joliet = m_path_primary + 1;
+    let m_path_joliet = l_path_joliet + 1;
+    let root_primary = m_path_joliet + 1;
+    let root_joliet = root_primary + 1;
+
+    let mut extents = Vec::new();
+    let mut next = root_joliet + 1;
+    for file in &files {
+        extents.push(next);
+        next += file.data.len().div_ceil(SECTOR).max(1);
+    }
+    let total_sectors = next;
+
+    let mut image = vec![0u8; total_sectors * SECTOR];
+
+    // Root directories
+    let primary_names: Vec<Vec<u8>> = files.iter().map(|f| primary_name(f.name)).collect();
+    let joliet_names: Vec<Vec<u8>> = files.iter().map(|f| joliet_name(f.name)).collect();
+    for (sector, names) in [(root_primary, &primary_names), (root_joliet, &joliet_names)] {
+        let mut dir = Vec::new();
+        dir.extend(dir_record(&[0x00], sector, SECTOR, true));
+        dir.extend(dir_record(&[0x01], sector, SECTOR, true));
+        for ((name, extent), file) in names.iter().zip(&extents).zip(&files) {
+            dir.extend(dir_record(name, *extent, file.data.len(), false));
+        }
+        assert!(dir.len() <= SECTOR, "root directory exceeds one sector");
+        write_at(&mut image, sector * SECTOR, &dir);
+    }
+
+    // Path tables
+    write_at(&mut image, l_path_primary * SECTOR, &path_table(root_primary, false));
+    write_at(&mut image, m_path_primary * SECTOR, &path_table(root_primary, true));
+    write_at(&mut image, l_path_joliet * SECTOR, &path_table(root_joliet, false));
+    write_at(&mut image, m_path_joliet * SECTOR, &path_table(root_joliet, true));
+
+    // Volume descriptors
+    let pvd = volume_descriptor(
+        false,
+        volume_id,
+        total_sectors,
+        (l_path_primary, m_path_primary),
+        root_primary,
+    );
+    write_at(&mut image, pvd_sector * SECTOR, &pvd);
+    let svd = volume_descriptor(
+        true,
+        volume_id,
+        total_sectors,
+        (l_path_joliet, m_path_joliet),
+        root_joliet,
+    );
+    write_at(&mut image, svd_sector * SECTOR, &svd);
+
+    let mut term = vec![0u8; 7];
+    term[0] = 255;
+    term[1..6].copy_from_slice(b"CD001");
+    term[6] = 1;
+    write_at(&mut image, term_sector * SECTOR, &term);
+
+    // File data
+    for (file, extent) in files.iter().zip(&extents) {
+        write_at(&mut image, extent * SECTOR, file.data);
+    }
+
+    image
+}
+
+fn write_at(image: &mut [u8], offset: usize, data: &[u8]) {
+    image[offset..offset + data.len()].copy_from_slice(data);
+}
+
+fn both_u16(value: u16) -> [u8; 4] {
+    let le = value.to_le_bytes();
+    let be = value.to_be_bytes();
+    [le[0], le[1], be[0], be[1]]
+}
+
+fn both_u32(value: u32) -> [u8; 8] {
+    let mut out = [0u8; 8];
+    out[..4].copy_from_slice(&value.to_le_bytes());
+    out[4..].copy_from_slice(&value.to_be_bytes());
+    out
+}
+
+/// ISO9660 level 1 identifier: uppercase with a `;1` version suffix.
+fn primary_name(name: &str) -> Vec<u8> {
+    let mut id: String = name
+        .chars()
+        .map(|c| match c.to_ascii_uppercase() {
+            c @ ('A'..='Z' | '0'..='9' | '_' | '.' | '-') => c,
+            _ => '_',
+        })
+        .collect();
+    id.push_str(";1");
+    id.into_bytes()
+}
+
+/// Joliet identifier: UCS-2 big-endian, original case.
+fn joliet_name(name: &str) -> Vec<u8> {
+    format!("{};1", name)
+        .encode_utf16()
+        .flat_map(|u| u.to_be_bytes())
+        .collect()
+}
+
+fn dir_record(name: &[u8], extent: usize, size: usize, is_dir: bool) -> Vec<u8> {
+    let len = 33 + name.len() + (name.len() + 1) % 2;
+    let mut rec = vec![0u8; len];
+    rec[0] = len as u8;
+    rec[2..10].copy_from_slice(&both_u32(extent as u32));
+    rec[10..18].copy_from_slice(&both_u32(size as u32));
+    // Recording date: 1970-01-01 00:00:00 UTC
+    rec[18..25].copy_from_slice(&[70, 1, 1, 0, 0, 0, 0]);
+    rec[25] = if is_dir { 0x02 } else { 0x00 };
+    rec[28..32].copy_from_slice(&both_u16(1));
+    rec[32] = name.len() as u8;
+    rec[33..33 + name.len()].copy_from_slice(name);
+    rec
+}
+
+fn path_table(root_sector: usize, big_endian: bool) -> Vec<u8> {
+    let mut rec = vec![0u8; 10];
+    rec[0] = 1;
+    if big_endian {
+        rec[2..6].copy_from_slice(&(root_sector as u32).to_be_bytes());
+        rec[6..8].copy_from_slice(&1u16.to_be_bytes());
+    } else {
+        rec[2..6].copy_from_slice(&(root_sector as u32).to_le_bytes());
+        rec[6..8].copy_from_slice(&1u16.to_le_bytes());
+    }
+    rec
+}
+
+/// Write a padded identifier field (ASCII for the primary, UCS-2 for Joliet).
+fn put_str(buf: &mut [u8], value: &str, joliet: bool) {
+    if joliet {
+        for pair in buf.chunks_mut(2) {
+            pair.copy_from_slice(&[0x00, 0x20][..pair.len()]);
+        }
+        for (i, unit) in value.encode_utf16().take(buf.len() / 2).enumerate() {
+            buf[i * 2..i * 2 + 2].copy_from_slice(&unit.to_be_bytes());
+        }
+    } else {
+        buf.fill(b' ');
+        let bytes = value.as_bytes();
+        let n = bytes.len().min(buf.len());
+        buf[..n].copy_from_slice(&bytes[..n]);
+    }
+}
+
+fn volume_descriptor(
+    joliet: bool,
+    volume_id: &str,
+    total_sectors: usize,
+    path_tables: (usize, usize),
+    root_sector: usize,
+) -> Vec<u8> {
+    let mut vd = vec![0u8; SECTOR];
+    vd[0] = if joliet { 2 } else { 1 };
+    vd[1..6].copy_from_slice(b"CD001");
+    vd[6] = 1;
+    put_str(&mut vd[8..40], "", joliet);
+    put_str(&mut vd[40..72], volume_id, joliet);
+    vd[80..88].copy_from_slice(&both_u32(total_sectors as u32));
+    if joliet {
+        // UCS-2 level 3 escape sequence
+        vd[88..91].copy_from_slice(b"%/E");
+    }
+    vd[120..124].copy_from_slice(&both_u16(1));
+    vd[124..128].copy_from_slice(&both_u16(1));
+    vd[128..132].copy_from_slice(&both_u16(SECTOR as u16));
+    vd[132..140].copy_from_slice(&both_u32(10));
+    vd[140..144].copy_from_slice(&(path_tables.0 as u32).to_le_bytes());
+    vd[148..152].copy_from_slice(&(path_tables.1 as u32).to_be_bytes());
+    vd[156..190].copy_from_slice(&dir_record(&[0x00], root_sector, SECTOR, true));
+    for range in [190..318, 318..446, 446..574, 574..702, 702..739, 739..776, 776..813] {
+        put_str(&mut vd[range], "", joliet);
+    }
+    // Creation/modification/expiration/effective dates: "not specified"
+    for start in [813, 830, 847, 864] {
+        vd[start..start + 16].fill(b'0');
+        vd[start + 16] = 0;
+    }
+    vd[881] = 1;
+    vd
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_build_iso_layout() {
+        let iso = build_iso(
+            "cidata",
+            &[
+                IsoFile { name: "user-data", data: b"#cloud-config\n" },
+                IsoFile { name: "meta-data", data: b"instance-id: x\n" },
+            ],
+        );
+        assert_eq!(iso.len() % SECTOR, 0);
+        assert_eq!(&iso[16 * SECTOR + 1..16 * SECTOR + 6], b"CD001");
+        assert_eq!(&iso[16 * SECTOR + 40..16 * SECTOR + 46], b"cidata");
+        assert_eq!(iso[17 * SECTOR], 2);
+        assert_eq!(iso[18 * SECTOR], 255);
+
+        // Files are sorted by name: meta-data first, then user-data
+        let data_start = 25 * SECTOR;
+        assert_eq!(&iso[data_start..data_start + 15], b"instance-id: x\n");
+        assert_eq!(&iso[data_start + SECTOR..data_start + SECTOR + 14], b"#cloud-config\n");
+    }
+}
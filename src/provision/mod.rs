@@ -0,0 +1,5 @@
+pub mod iso9660;
+pub mod cloud_init;
+pub mod autounattend;
+
+pub use cloud_init::*;
@@ -1,6 +1,5 @@
-use crate::config::schema::{AccelPreferred, ResolvedConfig};
-use std::process::Command;
-use std::str;
+use crate::config::schema::AccelPreferred;
+use crate::qemu::caps::QemuCaps;
 use thiserror::Error;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
@@ -27,33 +26,12 @@ pub enum AccelError {
     Io(#[from] std::io::Error),
 }
 
-/// Detect available accelerators by running `qemu-system-x86_64 -accel help`.
-pub fn detect_available_accels(qemu_path: &std::path::Path) -> AccelAvailability {
-    let output = Command::new(qemu_path)
-        .arg("-accel")
-        .arg("help")
-        .output();
-    
-    let mut whpx_available = false;
-    let mut tcg_available = false;
-    
-    if let Ok(output) = output {
-        let stdout = str::from_utf8(&output.stdout).unwrap_or("");
-        let stderr = str::from_utf8(&output.stderr).unwrap_or("");
-        let combined = format!("{} {}", stdout, stderr);
-        
-        whpx_available = combined.contains("whpx");
-        tcg_available = combined.contains("tcg");
-    }
-    
-    // TCG is always available as fallback
-    if !tcg_available {
-        tcg_available = true;
-    }
-    
+/// Derive accelerator availability from detected QEMU capabilities.
+pub fn accels_from_caps(caps: &QemuCaps) -> AccelAvailability {
     AccelAvailability {
-        whpx_available,
-        tcg_available,
+        whpx_available: caps.has_accel("whpx"),
+        // TCG is always available as fallback
+        tcg_available: true,
     }
 }
 
@@ -1,18 +1,59 @@
 use crate::config::schema::ResolvedConfig;
 use crate::qemu::accel::AccelChoice;
+use crate::qemu::caps::{QemuCaps, QemuVersion};
+use crate::qemu::monitor::monitor_args;
+use crate::qemu::qga::QGA_CHANNEL;
+use crate::integration::auth::SECRET_FW_CFG_NAME;
+use crate::integration::channel::INTEGRATION_CHANNEL;
+use crate::qemu::share::{share_transport, ShareTransport};
+use crate::util::net::{check_ports_available, NetError, Protocol, ReservedPort};
 use std::ffi::OsString;
+use std::net::Ipv4Addr;
 use std::path::Path;
+use thiserror::Error;
+
+/// Id of the user-mode netdev, used by monitor commands.
+pub const USER_NETDEV_ID: &str = "n0";
+
+/// `server=on,wait=off` replaced the bare `server,nowait` flags in QEMU 6.0.
+const BOOL_OPTS_VERSION: QemuVersion = QemuVersion { major: 6, minor: 0, micro: 0 };
+
+#[derive(Error, Debug)]
+pub enum ArgvError {
+    #[error("QEMU {version} does not support machine type '{machine}'")]
+    UnsupportedMachine { version: String, machine: String },
+    #[error("QEMU {version} has none of the devices: {devices}")]
+    UnsupportedDevice { version: String, devices: String },
+}
+
+/// Pick the first device supported by this QEMU build.
+fn pick_device(caps: &QemuCaps, candidates: &[&'static str]) -> Result<&'static str, ArgvError> {
+    candidates
+        .iter()
+        .copied()
+        .find(|d| caps.has_device(d))
+        .ok_or_else(|| ArgvError::UnsupportedDevice {
+            version: caps.version.to_string(),
+            devices: candidates.join(", "),
+        })
+}
 
 /// Build QEMU command line arguments from config.
+/// Devices are chosen from what the detected QEMU build supports.
+#[allow(clippy::vec_init_then_push)]
 pub fn build_argv(
     cfg: &ResolvedConfig,
-    qemu_path: &Path,
+    caps: &QemuCaps,
     accel: AccelChoice,
-) -> Vec<OsString> {
+) -> Result<Vec<OsString>, ArgvError> {
     let mut argv = Vec::new();
     
-    // QEMU executable (will be prepended by spawn)
-    // argv.push(qemu_path.as_os_str().to_os_string());
+    if !caps.has_machine("q35") {
+        return Err(ArgvError::UnsupportedMachine {
+            version: caps.version.to_string(),
+            machine: "q35".to_string(),
+        });
+    }
     
     // Name
     argv.push("-name".into());
@@ -48,21 +89,56 @@ pub fn build_argv(
     argv.push("-rtc".into());
     argv.push("base=localtime".into());
     
-    // USB devices
-    argv.push("-device".into());
-    argv.push("qemu-xhci".into());
-    
-    argv.push("-device".into());
-    argv.push("usb-tablet".into());
+    // USB devices (tablet needs a USB controller)
+    if let Ok(controller) = pick_device(caps, &["qemu-xhci", "nec-usb-xhci"]) {
+        argv.push("-device".into());
+        argv.push(controller.into());
+        
+        argv.push("-device".into());
+        argv.push("usb-tablet".into());
+    }
     
     // Disk
     let disk_format = detect_disk_format(&cfg.vm.disk);
-    argv.push("-drive".into());
-    argv.push(format!(
-        "file={},if=virtio,format={}",
-        cfg.vm.disk.to_string_lossy(),
-        disk_format
-    ).into());
+    if caps.supports_blockdev() {
+        argv.push("-blockdev".into());
+        argv.push(format!(
+            "driver={},node-name=disk0,file.driver=file,file.filename={}",
+            disk_format,
+            cfg.vm.disk.to_string_lossy()
+        ).into());
+        
+        argv.push("-device".into());
+        argv.push("virtio-blk-pci,drive=disk0".into());
+    } else {
+        argv.push("-drive".into());
+        argv.push(format!(
+            "file={},if=virtio,format={}",
+            cfg.vm.disk.to_string_lossy(),
+            disk_format
+        ).into());
+    }
+    
+    // Provisioning seed (cloud-init NoCloud) as a CD-ROM
+    if let Some(provision) = &cfg.provision {
+        argv.extend(cdrom_args(caps, &provision.seed, "seed", false));
+    }
+    
+    // Shared folders (virtio-9p; SMB shares are part of the netdev below)
+    if share_transport(cfg.vm.guest_os) == ShareTransport::Virtio9p {
+        for (i, share) in cfg.shares.iter().enumerate() {
+            argv.push("-fsdev".into());
+            argv.push(format!(
+                "local,id=fs{},path={},security_model=mapped-xattr{}",
+                i,
+                escape_opt(&share.host_path.to_string_lossy()),
+                if share.read_only { ",readonly=on" } else { "" }
+            ).into());
+            
+            argv.push("-device".into());
+            argv.push(format!("virtio-9p-pci,fsdev=fs{},mount_tag={}", i, share.tag).into());
+        }
+    }
     
     // Networking
     let mut hostfwd_rules = Vec::new();
@@ -76,21 +152,206 @@ pub fn build_argv(
     // Additional forwards
     for forward in &cfg.network.forwards {
         hostfwd_rules.push(format!(
-            "hostfwd=tcp:127.0.0.1:{}-:{}",
-            forward.host, forward.guest
+            "hostfwd={}",
+            hostfwd_rule(forward.proto, forward.bind, forward.host, forward.guest_addr, forward.guest)
         ));
     }
     
     argv.push("-netdev".into());
-    argv.push(format!("user,id=n0,{}", hostfwd_rules.join(",")).into());
+    argv.push(format!("{},{}", user_netdev_options(cfg), hostfwd_rules.join(",")).into());
     
+    let nic = pick_device(caps, &["virtio-net-pci", "e1000"])?;
     argv.push("-device".into());
-    argv.push("virtio-net-pci,netdev=n0".into());
+    argv.push(format!("{},netdev={}", nic, USER_NETDEV_ID).into());
+    
+    // Serial console on a private socket, relayed and logged by the console hub.
+    // Chardevs give root access to the guest, so none of them listen on TCP.
+    if cfg.serial.enabled {
+        argv.push("-chardev".into());
+        argv.push(chardev_socket("serial0", &cfg.serial.socket, caps).into());
+        argv.push("-serial".into());
+        argv.push("chardev:serial0".into());
+    }
+    
+    // virtio-serial ports backed by private chardevs: the guest agent (`qemu::qga`)
+    // and the integration channel. Neither needs guest networking.
+    let mut serial_ports = Vec::new();
+    if cfg.guest_agent.enabled {
+        serial_ports.push(("qga0", &cfg.guest_agent.socket, QGA_CHANNEL));
+    }
+    if cfg.integration.enabled {
+        serial_ports.push(("integration0", &cfg.integration.socket, INTEGRATION_CHANNEL));
+    }
+    if !serial_ports.is_empty() {
+        argv.push("-device".into());
+        argv.push(format!("{},id=vser0", pick_device(caps, &["virtio-serial-pci"])?).into());
+    }
+    for (id, socket, name) in serial_ports {
+        argv.push("-chardev".into());
+        argv.push(chardev_socket(id, socket, caps).into());
+        argv.push("-device".into());
+        argv.push(format!("virtserialport,bus=vser0.0,chardev={},name={}", id, name).into());
+    }
     
-    argv
+    // Integration secret for the guest, passed by file so it stays off the command line
+    if cfg.integration.enabled {
+        argv.push("-fw_cfg".into());
+        argv.push(format!(
+            "name={},file={}",
+            SECRET_FW_CFG_NAME,
+            escape_opt(&cfg.integration.secret_file.to_string_lossy())
+        ).into());
+    }
+    
+    Ok(argv)
+}
+
+/// The command line QEMU is started with: `build_argv`, then `extra` (such as
+/// install media), then the QMP monitor listening on `monitor`.
+pub fn launch_argv(
+    cfg: &ResolvedConfig,
+    caps: &QemuCaps,
+    accel: AccelChoice,
+    extra: &[OsString],
+    monitor: &Path,
+) -> Result<Vec<OsString>, ArgvError> {
+    let mut argv = build_argv(cfg, caps, accel)?;
+    argv.extend(extra.iter().cloned());
+    argv.extend(monitor_args(caps, monitor));
+    Ok(argv)
+}
+
+/// Replace `"auto"` host ports (SSH, forwards, TCP console) with free ones. The
+/// picks stay bound until the returned reservations are dropped, just before QEMU starts.
+pub fn assign_host_ports(cfg: &mut ResolvedConfig) -> Result<Vec<ReservedPort>, NetError> {
+    let mut reserved = cfg.network.assign_auto_ports()?;
+    reserved.extend(cfg.serial.assign_auto_port()?);
+    Ok(reserved)
+}
+
+/// Check the host ports QEMU and the console hub will bind, other than the ones
+/// `reserved` holds for them.
+pub fn check_host_ports(cfg: &ResolvedConfig, reserved: &[ReservedPort]) -> Result<(), NetError> {
+    let mut bindings = cfg.network.host_bindings();
+    bindings.extend(cfg.serial.host_bindings());
+    bindings.retain(|binding| !reserved.iter().any(|port| port.holds(binding)));
+    check_ports_available(&bindings)?;
+    Ok(())
+}
+
+/// Flags for a listening socket that doesn't block startup waiting for a client.
+pub fn socket_server_flags(caps: &QemuCaps) -> &'static str {
+    if caps.version >= BOOL_OPTS_VERSION { "server=on,wait=off" } else { "server,nowait" }
+}
+
+/// A listening Unix socket chardev at `path`.
+fn chardev_socket(id: &str, path: &Path, caps: &QemuCaps) -> String {
+    format!(
+        "socket,id={},path={},{}",
+        id,
+        escape_opt(&path.to_string_lossy()),
+        socket_server_flags(caps)
+    )
+}
+
+/// A hostfwd rule: `proto:bind:host-[guest_addr]:guest`.
+/// The same syntax is accepted by the monitor's `hostfwd_add`.
+pub fn hostfwd_rule(
+    proto: Protocol,
+    bind: Ipv4Addr,
+    host: u16,
+    guest_addr: Option<Ipv4Addr>,
+    guest: u16,
+) -> String {
+    format!(
+        "{}:{}:{}-{}:{}",
+        proto,
+        bind,
+        host,
+        guest_addr.map(|a| a.to_string()).unwrap_or_default(),
+        guest
+    )
+}
+
+/// Arguments attaching a read-only image as a CD-ROM drive.
+/// With `boot`, the CD-ROM is booted before the disk.
+pub fn cdrom_args(caps: &QemuCaps, image: &Path, id: &str, boot: bool) -> Vec<OsString> {
+    if caps.supports_blockdev() {
+        vec![
+            "-blockdev".into(),
+            format!(
+                "driver=raw,node-name={},read-only=on,file.driver=file,file.filename={}",
+                id,
+                escape_opt(&image.to_string_lossy())
+            ).into(),
+            "-device".into(),
+            format!("ide-cd,drive={}{}", id, if boot { ",bootindex=0" } else { "" }).into(),
+        ]
+    } else {
+        let mut args: Vec<OsString> = vec![
+            "-drive".into(),
+            format!(
+                "file={},media=cdrom,format=raw,readonly=on,id={}",
+                escape_opt(&image.to_string_lossy()),
+                id
+            ).into(),
+        ];
+        if boot {
+            args.push("-boot".into());
+            args.push("once=d".into());
+        }
+        args
+    }
+}
+
+/// Build the `-netdev user` options other than hostfwd rules.
+fn user_netdev_options(cfg: &ResolvedConfig) -> String {
+    let network = &cfg.network;
+    let mut opts = vec!["user".to_string(), format!("id={}", USER_NETDEV_ID)];
+    
+    if network.restrict {
+        opts.push("restrict=on".to_string());
+    }
+    if let Some(net) = &network.net {
+        opts.push(format!("net={}", net));
+    }
+    if let Some(host) = network.host {
+        opts.push(format!("host={}", host));
+    }
+    if let Some(dns) = network.dns {
+        opts.push(format!("dns={}", dns));
+    }
+    if !network.ipv6 {
+        opts.push("ipv6=off".to_string());
+    }
+    for domain in &network.dnssearch {
+        opts.push(format!("dnssearch={}", domain));
+    }
+    if let Some(domainname) = &network.domainname {
+        opts.push(format!("domainname={}", domainname));
+    }
+    for fwd in &network.guestfwd {
+        opts.push(format!(
+            "guestfwd=tcp:{}:{}-tcp:{}:{}",
+            fwd.guest_addr, fwd.guest_port, fwd.host_addr, fwd.host_port
+        ));
+    }
+    
+    if share_transport(cfg.vm.guest_os) == ShareTransport::Smb {
+        if let Some(share) = cfg.shares.first() {
+            opts.push(format!("smb={}", escape_opt(&share.host_path.to_string_lossy())));
+        }
+    }
+    
+    opts.join(",")
+}
+
+/// Escape a value for a QEMU comma-separated option list.
+pub fn escape_opt(value: &str) -> String {
+    value.replace(',', ",,")
 }
 
-fn detect_disk_format(disk_path: &Path) -> &'static str {
+pub fn detect_disk_format(disk_path: &Path) -> &'static str {
     let ext = disk_path.extension()
         .and_then(|e| e.to_str())
         .unwrap_or("")
@@ -0,0 +1,263 @@
+use crate::util::fs_atomic;
+use crate::util::hashing::hash_file;
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use std::time::UNIX_EPOCH;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CapsError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Could not parse QEMU version from: {0}")]
+    Version(String),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct QemuVersion {
+    pub major: u32,
+    pub minor: u32,
+    pub micro: u32,
+}
+
+impl QemuVersion {
+    pub const fn new(major: u32, minor: u32, micro: u32) -> Self {
+        Self { major, minor, micro }
+    }
+}
+
+impl fmt::Display for QemuVersion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.micro)
+    }
+}
+
+/// First release with a stable `-blockdev` option.
+const BLOCKDEV_MIN_VERSION: QemuVersion = QemuVersion::new(2, 9, 0);
+
+/// First release with AF_UNIX socket chardevs on Windows.
+const WINDOWS_UNIX_SOCKET_VERSION: QemuVersion = QemuVersion::new(7, 1, 0);
+
+/// Capabilities of a QEMU binary, as reported by its `help` outputs.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct QemuCaps {
+    pub version: QemuVersion,
+    pub version_string: String,
+    pub machines: BTreeSet<String>,
+    pub devices: BTreeSet<String>,
+    pub accels: BTreeSet<String>,
+}
+
+impl QemuCaps {
+    pub fn has_machine(&self, name: &str) -> bool {
+        self.machines.contains(name)
+    }
+
+    pub fn has_device(&self, name: &str) -> bool {
+        self.devices.contains(name)
+    }
+
+    pub fn has_accel(&self, name: &str) -> bool {
+        self.accels.contains(name)
+    }
+
+    pub fn supports_blockdev(&self) -> bool {
+        self.version >= BLOCKDEV_MIN_VERSION
+    }
+
+    /// Whether QMP and chardevs can listen on Unix sockets (always, outside Windows).
+    pub fn supports_unix_sockets(&self) -> bool {
+        cfg!(not(windows)) || self.version >= WINDOWS_UNIX_SOCKET_VERSION
+    }
+}
+
+/// On-disk cache entry, keyed by the binary's path. Size and mtime decide
+/// whether the binary may have changed; only then is it hashed again.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    size: u64,
+    mtime: u64,
+    sha256: String,
+    caps: QemuCaps,
+}
+
+/// Detect capabilities of a QEMU binary, using the cache file when the binary
+/// is unchanged.
+pub fn detect_caps(qemu_path: &Path, cache_path: &Path) -> Result<QemuCaps, CapsError> {
+    let metadata = fs::metadata(qemu_path)?;
+    let size = metadata.len();
+    let mtime = metadata
+        .modified()?
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or(0);
+    let key = qemu_path.to_string_lossy().to_string();
+
+    let mut cache: BTreeMap<String, CacheEntry> = fs::read_to_string(cache_path)
+        .ok()
+        .and_then(|contents| serde_json::from_str(&contents).ok())
+        .unwrap_or_default();
+
+    if let Some(entry) = cache.get(&key) {
+        if entry.size == size && entry.mtime == mtime {
+            return Ok(entry.caps.clone());
+        }
+    }
+
+    // Changed or new path: the same binary may be cached under another path
+    let sha256 = hash_file(qemu_path)?;
+    let caps = match cache.values().find(|entry| entry.sha256 == sha256) {
+        Some(entry) => entry.caps.clone(),
+        None => probe_caps(qemu_path)?,
+    };
+
+    cache.insert(key, CacheEntry { size, mtime, sha256, caps: caps.clone() });
+    if let Ok(json) = serde_json::to_string_pretty(&cache) {
+        if let Err(e) = fs_atomic::atomic_write_str(cache_path, &json) {
+            tracing::warn!("Failed to write QEMU capability cache: {}", e);
+        }
+    }
+
+    Ok(caps)
+}
+
+/// Run the QEMU binary to collect its capabilities (uncached).
+pub fn probe_caps(qemu_path: &Path) -> Result<QemuCaps, CapsError> {
+    let version_output = run_help(qemu_path, &["-version"])?;
+    let version = parse_version(&version_output)
+        .ok_or_else(|| CapsError::Version(version_output.trim().to_string()))?;
+    let version_string = version_output.lines().next().unwrap_or("").trim().to_string();
+
+    Ok(QemuCaps {
+        version,
+        version_string,
+        machines: parse_machines(&run_help(qemu_path, &["-machine", "help"])?),
+        devices: parse_devices(&run_help(qemu_path, &["-device", "help"])?),
+        accels: parse_accels(&run_help(qemu_path, &["-accel", "help"])?),
+    })
+}
+
+fn run_help(qemu_path: &Path, args: &[&str]) -> Result<String, CapsError> {
+    let output = Command::new(qemu_path).args(args).output()?;
+    let mut combined = String::from_utf8_lossy(&output.stdout).into_owned();
+    combined.push_str(&String::from_utf8_lossy(&output.stderr));
+    Ok(combined)
+}
+
+/// Parse `QEMU emulator version 8.2.0 (v8.2.0-...)`.
+pub fn parse_version(output: &str) -> Option<QemuVersion> {
+    let rest = &output[output.find("version ")? + "version ".len()..];
+    let token = rest.split_whitespace().next()?;
+    let mut parts = token
+        .split(|c: char| !c.is_ascii_digit())
+        .filter(|p| !p.is_empty())
+        .map(|p| p.parse::<u32>());
+
+    let major = parts.next()?.ok()?;
+    let minor = parts.next().and_then(|p| p.ok()).unwrap_or(0);
+    let micro = parts.next().and_then(|p| p.ok()).unwrap_or(0);
+    Some(QemuVersion::new(major, minor, micro))
+}
+
+/// Parse `-machine help`: one machine per line, aliases in parentheses.
+pub fn parse_machines(output: &str) -> BTreeSet<String> {
+    let mut machines = BTreeSet::new();
+    for line in output.lines().skip_while(|l| !l.starts_with("Supported machines")).skip(1) {
+        if let Some(name) = line.split_whitespace().next() {
+            machines.insert(name.to_string());
+        }
+        if let Some(start) = line.find("(alias of ") {
+            let alias = &line[start + "(alias of ".len()..];
+            if let Some(end) = alias.find(')') {
+                machines.insert(alias[..end].to_string());
+            }
+        }
+    }
+    machines
+}
+
+/// Parse `-device help`: `name "virtio-net-pci", bus PCI, alias "virtio-net"`.
+pub fn parse_devices(output: &str) -> BTreeSet<String> {
+    let mut devices = BTreeSet::new();
+    for line in output.lines() {
+        for key in ["name \"", "alias \""] {
+            if let Some(start) = line.find(key) {
+                let value = &line[start + key.len()..];
+                if let Some(end) = value.find('"') {
+                    devices.insert(value[..end].to_string());
+                }
+            }
+        }
+    }
+    devices
+}
+
+/// Parse `-accel help`: a header followed by one accelerator per line.
+pub fn parse_accels(output: &str) -> BTreeSet<String> {
+    output
+        .lines()
+        .map(str::trim)
+        .filter(|l| !l.is_empty() && !l.contains(' ') && !l.ends_with(':'))
+        .map(str::to_string)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_version() {
+        let out = "QEMU emulator version 8.2.0 (v8.2.0-12041-gabcdef)\nCopyright (c) 2003-2023";
+        assert_eq!(parse_version(out), Some(QemuVersion::new(8, 2, 0)));
+        assert_eq!(parse_version("QEMU emulator version 2.5"), Some(QemuVersion::new(2, 5, 0)));
+    }
+
+    #[test]
+    fn test_parse_help_outputs() {
+        let machines = parse_machines(
+            "Supported machines are:\nq35                  Standard PC (Q35 + ICH9, 2009) (alias of pc-q35-8.2)\npc-q35-8.2           Standard PC (Q35 + ICH9, 2009)\n",
+        );
+        assert!(machines.contains("q35") && machines.contains("pc-q35-8.2"));
+
+        let devices = parse_devices("Network devices:\nname \"virtio-net-pci\", bus PCI, alias \"virtio-net\"\nname \"e1000\", bus PCI\n");
+        assert!(devices.contains("virtio-net-pci") && devices.contains("virtio-net") && devices.contains("e1000"));
+
+        let accels = parse_accels("Accelerators supported in QEMU binary:\ntcg\nwhpx\n");
+        assert_eq!(accels.into_iter().collect::<Vec<_>>(), vec!["tcg", "whpx"]);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_cache_reprobes_only_changed_binaries() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempfile::tempdir().unwrap();
+        let (qemu, log, cache) = (dir.path().join("qemu"), dir.path().join("runs"), dir.path().join("caps.json"));
+        let write_qemu = |version: &str| {
+            let script = format!(
+                "#!/bin/sh\necho run >> {}\necho 'QEMU emulator version {}'\n",
+                log.display(),
+                version
+            );
+            fs::write(&qemu, script).unwrap();
+            fs::set_permissions(&qemu, fs::Permissions::from_mode(0o755)).unwrap();
+        };
+        let runs = || fs::read_to_string(&log).map(|l| l.lines().count()).unwrap_or(0);
+
+        write_qemu("8.2.0");
+        assert_eq!(detect_caps(&qemu, &cache).unwrap().version, QemuVersion::new(8, 2, 0));
+        let probed = runs();
+        assert_eq!(detect_caps(&qemu, &cache).unwrap().version, QemuVersion::new(8, 2, 0));
+        assert_eq!(runs(), probed);
+
+        // A replaced binary (here a different size) is probed again
+        write_qemu("9.0.10");
+        assert_eq!(detect_caps(&qemu, &cache).unwrap().version, QemuVersion::new(9, 0, 10));
+        assert_eq!(runs(), 2 * probed);
+    }
+}
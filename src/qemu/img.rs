@@ -0,0 +1,48 @@
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use thiserror::Error;
+
+/// File name of the QEMU disk image utility.
+#[cfg(windows)]
+pub const QEMU_IMG_EXE: &str = "qemu-img.exe";
+#[cfg(not(windows))]
+pub const QEMU_IMG_EXE: &str = "qemu-img";
+
+#[derive(Error, Debug)]
+pub enum ImgError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("qemu-img not found next to {0} or in PATH")]
+    NotFound(String),
+    #[error("qemu-img failed: {0}")]
+    Failed(String),
+}
+
+/// Locate qemu-img, preferring the one shipped next to the QEMU binary.
+pub fn locate_qemu_img(qemu_path: &Path) -> Result<PathBuf, ImgError> {
+    let sibling = qemu_path.with_file_name(QEMU_IMG_EXE);
+    if sibling.is_file() {
+        return Ok(sibling);
+    }
+    which::which(QEMU_IMG_EXE).map_err(|_| ImgError::NotFound(qemu_path.to_string_lossy().to_string()))
+}
+
+/// Create a new disk image of the given size.
+pub fn create_disk(qemu_img: &Path, disk: &Path, format: &str, size_gb: u32) -> Result<(), ImgError> {
+    if let Some(parent) = disk.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    
+    let output = Command::new(qemu_img)
+        .arg("create")
+        .arg("-f")
+        .arg(format)
+        .arg(disk)
+        .arg(format!("{}G", size_gb))
+        .output()?;
+    
+    if !output.status.success() {
+        return Err(ImgError::Failed(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
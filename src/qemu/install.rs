@@ -0,0 +1,150 @@
+use crate::qemu::caps::probe_caps;
+use crate::qemu::locate::{installs_dir, QEMU_EXE};
+use crate::util::hashing::hash_file;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum InstallError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Archive not found: {0}")]
+    ArchiveNotFound(String),
+    #[error("No checksum given for {path}; its SHA-256 is {actual} (pass --sha256 or add a .sha256 file)")]
+    MissingChecksum { path: String, actual: String },
+    #[error("SHA-256 mismatch: expected {expected}, got {actual}")]
+    ChecksumMismatch { expected: String, actual: String },
+    #[error("Failed to extract archive: {0}")]
+    Extract(String),
+    #[error("{0} not found in archive")]
+    BinaryNotFound(&'static str),
+    #[error("Capability detection failed: {0}")]
+    Caps(#[from] crate::qemu::caps::CapsError),
+    #[error("QEMU {0} is already installed")]
+    AlreadyInstalled(String),
+}
+
+#[derive(Debug, Clone)]
+pub struct InstalledQemu {
+    pub version: String,
+    pub path: PathBuf,
+    pub sha256: String,
+}
+
+/// Install a QEMU distribution zip into `bin/qemu/<version>/`.
+/// The archive is verified against `expected_sha256`, or a `<zip>.sha256` file next to it.
+pub fn install_qemu_zip(
+    root: &Path,
+    zip_path: &Path,
+    expected_sha256: Option<&str>,
+) -> Result<InstalledQemu, InstallError> {
+    if !zip_path.is_file() {
+        return Err(InstallError::ArchiveNotFound(zip_path.to_string_lossy().to_string()));
+    }
+
+    // Verify checksum
+    let actual = hash_file(zip_path)?;
+    let expected = match expected_sha256 {
+        Some(expected) => expected.to_string(),
+        None => read_sidecar_checksum(zip_path).ok_or_else(|| InstallError::MissingChecksum {
+            path: zip_path.to_string_lossy().to_string(),
+            actual: actual.clone(),
+        })?,
+    };
+    if !expected.trim().eq_ignore_ascii_case(&actual) {
+        return Err(InstallError::ChecksumMismatch {
+            expected: expected.trim().to_string(),
+            actual,
+        });
+    }
+
+    // Extract into a staging directory
+    let installs = installs_dir(root);
+    let staging = installs.join(format!(".staging-{}", std::process::id()));
+    if staging.exists() {
+        fs::remove_dir_all(&staging)?;
+    }
+    fs::create_dir_all(&staging)?;
+
+    let result = extract_and_place(&installs, &staging, zip_path, &actual);
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn extract_and_place(
+    installs: &Path,
+    staging: &Path,
+    zip_path: &Path,
+    sha256: &str,
+) -> Result<InstalledQemu, InstallError> {
+    extract_zip(zip_path, staging)?;
+
+    let binary = find_binary(staging, 3).ok_or(InstallError::BinaryNotFound(QEMU_EXE))?;
+    let dist_root = binary.parent().unwrap_or(staging).to_path_buf();
+
+    let version = probe_caps(&binary)?.version.to_string();
+    let target = installs.join(&version);
+    if target.exists() {
+        return Err(InstallError::AlreadyInstalled(version));
+    }
+
+    fs::rename(&dist_root, &target)?;
+
+    Ok(InstalledQemu {
+        path: target.join(QEMU_EXE),
+        version,
+        sha256: sha256.to_string(),
+    })
+}
+
+/// Read the first hex token of `<zip>.sha256` (sha256sum format).
+fn read_sidecar_checksum(zip_path: &Path) -> Option<String> {
+    let mut sidecar = zip_path.as_os_str().to_os_string();
+    sidecar.push(".sha256");
+    let contents = fs::read_to_string(PathBuf::from(sidecar)).ok()?;
+    contents.split_whitespace().next().map(str::to_string)
+}
+
+/// Extract a zip archive using the system archiver (bsdtar ships with Windows 10+).
+fn extract_zip(zip_path: &Path, dest: &Path) -> Result<(), InstallError> {
+    #[cfg(windows)]
+    let output = Command::new("tar")
+        .arg("-xf")
+        .arg(zip_path)
+        .arg("-C")
+        .arg(dest)
+        .output()?;
+
+    #[cfg(not(windows))]
+    let output = Command::new("unzip")
+        .arg("-q")
+        .arg(zip_path)
+        .arg("-d")
+        .arg(dest)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(InstallError::Extract(
+            String::from_utf8_lossy(&output.stderr).trim().to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Find the QEMU binary within the extracted tree (distributions often nest one level).
+fn find_binary(dir: &Path, depth: usize) -> Option<PathBuf> {
+    let candidate = dir.join(QEMU_EXE);
+    if candidate.is_file() {
+        return Some(candidate);
+    }
+    if depth == 0 {
+        return None;
+    }
+    fs::read_dir(dir)
+        .ok()?
+        .flatten()
+        .filter(|entry| entry.path().is_dir())
+        .find_map(|entry| find_binary(&entry.path(), depth - 1))
+}
@@ -1,44 +1,145 @@
-use std::env;
+use crate::config::schema::ResolvedQemuConfig;
+use std::fmt;
+use std::fs;
 use std::path::{Path, PathBuf};
 use thiserror::Error;
 
+/// File name of the QEMU system emulator.
+#[cfg(windows)]
+pub const QEMU_EXE: &str = "qemu-system-x86_64.exe";
+#[cfg(not(windows))]
+pub const QEMU_EXE: &str = "qemu-system-x86_64";
+
 #[derive(Error, Debug)]
 pub enum LocateError {
     #[error("QEMU executable not found")]
     NotFound,
+    #[error("Configured QEMU binary not found: {0}")]
+    ConfiguredPathMissing(String),
+    #[error("QEMU version {0} is not installed (run: portaqemu qemu install <zip>)")]
+    VersionNotInstalled(String),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
 }
 
+/// Where a discovered QEMU binary came from.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QemuSource {
+    Config,
+    Root,
+    Path,
+}
+
+impl fmt::Display for QemuSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            QemuSource::Config => "config",
+            QemuSource::Root => "root",
+            QemuSource::Path => "PATH",
+        })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct DiscoveredQemu {
+    pub path: PathBuf,
+    pub source: QemuSource,
+    /// Version directory name for managed installs under `bin/qemu/<version>/`.
+    pub installed_version: Option<String>,
+}
+
+/// Directory holding managed side-by-side QEMU installs.
+pub fn installs_dir(root: &Path) -> PathBuf {
+    root.join("bin").join("qemu")
+}
+
 /// Locate qemu-system-x86_64 executable.
-/// Checks: config override -> <root>/bin/qemu/ -> <root>/bin/ -> PATH
-pub fn locate_qemu(root: &Path) -> Result<PathBuf, LocateError> {
-    // 1. Check <root>/bin/qemu/qemu-system-x86_64.exe
-    let qemu_path = root.join("bin").join("qemu").join("qemu-system-x86_64.exe");
-    if qemu_path.exists() {
-        return Ok(qemu_path);
+/// Checks: qemu.path -> qemu.version -> <root>/bin/qemu/ -> <root>/bin/ -> newest managed install -> PATH
+pub fn locate_qemu(root: &Path, qemu_cfg: &ResolvedQemuConfig) -> Result<PathBuf, LocateError> {
+    // 1. Explicit path from config
+    if let Some(path) = &qemu_cfg.path {
+        if path.is_file() {
+            return Ok(path.clone());
+        }
+        return Err(LocateError::ConfiguredPathMissing(path.to_string_lossy().to_string()));
     }
-    
-    // 2. Check <root>/bin/qemu-system-x86_64.exe
-    let qemu_path = root.join("bin").join("qemu-system-x86_64.exe");
-    if qemu_path.exists() {
-        return Ok(qemu_path);
+
+    // 2. Pinned managed install
+    if let Some(version) = &qemu_cfg.version {
+        let qemu_path = installs_dir(root).join(version).join(QEMU_EXE);
+        if qemu_path.exists() {
+            return Ok(qemu_path);
+        }
+        return Err(LocateError::VersionNotInstalled(version.clone()));
     }
-    
-    // 3. Check PATH
-    #[cfg(windows)]
-    {
-        if let Ok(path) = which::which("qemu-system-x86_64.exe") {
-            return Ok(path);
+
+    // 3. Root locations, then managed installs, then PATH
+    discover_qemu(root, qemu_cfg)
+        .into_iter()
+        .find(|d| d.source != QemuSource::Config)
+        .map(|d| d.path)
+        .ok_or(LocateError::NotFound)
+}
+
+/// List every QEMU binary that can be found, in lookup order.
+pub fn discover_qemu(root: &Path, qemu_cfg: &ResolvedQemuConfig) -> Vec<DiscoveredQemu> {
+    let mut found = Vec::new();
+
+    if let Some(path) = &qemu_cfg.path {
+        if path.is_file() {
+            found.push(DiscoveredQemu {
+                path: path.clone(),
+                source: QemuSource::Config,
+                installed_version: None,
+            });
         }
     }
-    
-    #[cfg(not(windows))]
-    {
-        if let Ok(path) = which::which("qemu-system-x86_64") {
-            return Ok(path);
+
+    for qemu_path in [installs_dir(root).join(QEMU_EXE), root.join("bin").join(QEMU_EXE)] {
+        if qemu_path.exists() {
+            found.push(DiscoveredQemu {
+                path: qemu_path,
+                source: QemuSource::Root,
+                installed_version: None,
+            });
         }
     }
-    
-    Err(LocateError::NotFound)
+
+    for version in installed_versions(root) {
+        found.push(DiscoveredQemu {
+            path: installs_dir(root).join(&version).join(QEMU_EXE),
+            source: QemuSource::Root,
+            installed_version: Some(version),
+        });
+    }
+
+    if let Ok(path) = which::which(QEMU_EXE) {
+        found.push(DiscoveredQemu {
+            path,
+            source: QemuSource::Path,
+            installed_version: None,
+        });
+    }
+
+    found
+}
+
+/// Managed install versions under `bin/qemu/`, newest first.
+pub fn installed_versions(root: &Path) -> Vec<String> {
+    let mut versions: Vec<String> = fs::read_dir(installs_dir(root))
+        .into_iter()
+        .flatten()
+        .flatten()
+        .filter(|entry| entry.path().join(QEMU_EXE).is_file())
+        .filter_map(|entry| entry.file_name().into_string().ok())
+        .filter(|name| !name.starts_with('.'))
+        .collect();
+
+    versions.sort_by_key(|v| {
+        v.split('.')
+            .map(|part| part.parse::<u32>().unwrap_or(0))
+            .collect::<Vec<_>>()
+    });
+    versions.reverse();
+    versions
 }
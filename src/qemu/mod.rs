@@ -3,8 +3,15 @@ pub mod accel;
 pub mod argv;
 pub mod spawn;
 pub mod probe;
+pub mod caps;
+pub mod install;
+pub mod share;
+pub mod img;
+pub mod monitor;
+pub mod qga;
 
 pub use locate::*;
 pub use accel::*;
 pub use argv::*;
 pub use spawn::*;
+pub use caps::*;
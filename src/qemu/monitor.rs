@@ -0,0 +1,116 @@
+use crate::qemu::argv::{escape_opt, socket_server_flags};
+use crate::qemu::caps::QemuCaps;
+use crate::util::local_socket::{self, LocalStream};
+use serde_json::{json, Value};
+use std::ffi::OsString;
+use std::io::{BufRead, BufReader, Write};
+use std::path::Path;
+use std::time::Duration;
+use thiserror::Error;
+
+/// Timeout for talking with the monitor.
+const MONITOR_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Error, Debug)]
+pub enum MonitorError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("QMP connection closed")]
+    Closed,
+    #[error("QMP error: {0}")]
+    Command(String),
+}
+
+/// Arguments exposing QMP on the Unix socket `path`. QMP can run host commands
+/// (`migrate "exec:..."`), so it must never listen where other users can reach it.
+pub fn monitor_args(caps: &QemuCaps, path: &Path) -> Vec<OsString> {
+    vec![
+        "-qmp".into(),
+        format!("unix:{},{}", escape_opt(&path.to_string_lossy()), socket_server_flags(caps)).into(),
+    ]
+}
+
+/// Synchronous QMP client.
+pub struct QmpClient {
+    reader: BufReader<LocalStream>,
+    writer: LocalStream,
+}
+
+impl QmpClient {
+    /// Connect, read the greeting and enter command mode.
+    pub fn connect(path: &Path) -> Result<Self, MonitorError> {
+        let stream = local_socket::connect(path)?;
+        stream.set_read_timeout(Some(MONITOR_TIMEOUT))?;
+        let mut client = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+
+        client.read_message()?;
+        client.execute("qmp_capabilities", None)?;
+        Ok(client)
+    }
+
+    fn read_message(&mut self) -> Result<Value, MonitorError> {
+        let mut line = String::new();
+        if self.reader.read_line(&mut line)? == 0 {
+            return Err(MonitorError::Closed);
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+
+    /// Run a QMP command and return its `return` value. Asynchronous events are skipped.
+    pub fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value, MonitorError> {
+        let mut request = json!({ "execute": command });
+        if let Some(arguments) = arguments {
+            request["arguments"] = arguments;
+        }
+        writeln!(self.writer, "{}", request)?;
+
+        loop {
+            let message = self.read_message()?;
+            if let Some(value) = message.get("return") {
+                return Ok(value.clone());
+            }
+            if let Some(error) = message.get("error") {
+                let desc = error.get("desc").and_then(Value::as_str).unwrap_or("unknown error");
+                return Err(MonitorError::Command(desc.to_string()));
+            }
+        }
+    }
+
+    /// Run a human monitor (HMP) command. HMP reports failures as output text.
+    pub fn human_command(&mut self, command_line: &str) -> Result<String, MonitorError> {
+        let output = self.execute(
+            "human-monitor-command",
+            Some(json!({ "command-line": command_line })),
+        )?;
+        Ok(output.as_str().unwrap_or_default().to_string())
+    }
+
+    /// Add a user-mode network forwarding rule (`hostfwd_add`).
+    pub fn hostfwd_add(&mut self, netdev: &str, rule: &str) -> Result<(), MonitorError> {
+        expect_silent(self.human_command(&format!("hostfwd_add {} {}", netdev, rule))?)
+    }
+
+    /// Remove a forwarding rule (`hostfwd_remove`); `rule` is `proto:addr:port`.
+    pub fn hostfwd_remove(&mut self, netdev: &str, rule: &str) -> Result<(), MonitorError> {
+        let output = self.human_command(&format!("hostfwd_remove {} {}", netdev, rule))?;
+        // Success is reported as "host forwarding rule for ... removed"
+        if output.contains("removed") {
+            return Ok(());
+        }
+        expect_silent(output)
+    }
+}
+
+fn expect_silent(output: String) -> Result<(), MonitorError> {
+    let output = output.trim();
+    if output.is_empty() {
+        Ok(())
+    } else {
+        Err(MonitorError::Command(output.to_string()))
+    }
+}
@@ -1,52 +1,103 @@
-use std::io::Read;
+use crate::qemu::spawn::RunningVm;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
 use std::path::Path;
-use std::process::{Command, Stdio};
 use std::time::{Duration, Instant};
+use thiserror::Error;
 
-/// Check if QEMU failed quickly due to acceleration issues.
-pub fn is_accel_failure(
-    qemu_path: &Path,
-    argv: &[std::ffi::OsString],
+/// Number of bytes read from the end of the QEMU log when classifying a failure.
+const LOG_TAIL_BYTES: u64 = 4096;
+
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum StartupError {
+    #[error("Acceleration failed to initialize (exit code {code:?}): {log_tail}")]
+    AccelFailure { code: Option<i32>, log_tail: String },
+    #[error("QEMU exited during startup (exit code {code:?}): {log_tail}")]
+    EarlyExit { code: Option<i32>, log_tail: String },
+    #[error("Failed to wait for QEMU process: {0}")]
+    Wait(String),
+}
+
+impl StartupError {
+    /// Whether the failure was caused by the accelerator and a TCG retry makes sense.
+    pub fn is_accel_failure(&self) -> bool {
+        matches!(self, StartupError::AccelFailure { .. })
+    }
+}
+
+/// Watch a freshly spawned QEMU process for the grace period.
+/// Returns `Ok(())` if it is still running afterwards, otherwise classifies the
+/// exit using the tail of its log file.
+pub fn watch_startup(
+    vm: &mut RunningVm,
+    log_file: &Path,
     grace_period: Duration,
-) -> bool {
-    let mut cmd = Command::new(qemu_path);
-    cmd.args(argv);
-    cmd.stderr(Stdio::piped());
-    
+) -> Result<(), StartupError> {
+    let child = match vm.child.as_mut() {
+        Some(child) => child,
+        None => return Ok(()),
+    };
+
     let start = Instant::now();
-    if let Ok(mut child) = cmd.spawn() {
-        // Wait for process to exit or grace period
-        let mut stderr = String::new();
-        if let Some(mut stderr_handle) = child.stderr.take() {
-            let _ = stderr_handle.read_to_string(&mut stderr);
-        }
-        
-        // Wait with timeout
-        let mut waited = false;
-        while start.elapsed() < grace_period {
-            if let Ok(Some(status)) = child.try_wait() {
-                waited = true;
-                if !status.success() {
-                    // Check stderr for accel-related errors
-                    let stderr_lower = stderr.to_lowercase();
-                    return stderr_lower.contains("whpx")
-                        || stderr_lower.contains("failed to initialize whpx")
-                        || (stderr_lower.contains("acceleration") && stderr_lower.contains("not available"))
-                        || stderr_lower.contains("no accelerator found");
-                }
-                break;
+    while start.elapsed() < grace_period {
+        match child.try_wait() {
+            Ok(Some(status)) => {
+                let log_tail = read_log_tail(log_file);
+                return Err(classify_exit(status.code(), log_tail));
             }
-            std::thread::sleep(Duration::from_millis(100));
-        }
-        
-        if waited {
-            // Process exited within grace period with error
-            return true;
+            Ok(None) => std::thread::sleep(Duration::from_millis(100)),
+            Err(e) => return Err(StartupError::Wait(e.to_string())),
         }
-        
-        // Kill if still running
-        let _ = child.kill();
     }
-    
-    false
+
+    Ok(())
+}
+
+/// Classify an early QEMU exit from its exit code and log output.
+pub fn classify_exit(code: Option<i32>, log_tail: String) -> StartupError {
+    let lower = log_tail.to_lowercase();
+    let accel_related = lower.contains("whpx")
+        || lower.contains("no accelerator found")
+        || (lower.contains("acceleration") && lower.contains("not available"))
+        || lower.contains("invalid accelerator");
+
+    if accel_related {
+        StartupError::AccelFailure { code, log_tail }
+    } else {
+        StartupError::EarlyExit { code, log_tail }
+    }
+}
+
+/// Read the last few KB of a log file (best-effort).
+pub fn read_log_tail(log_file: &Path) -> String {
+    let mut file = match File::open(log_file) {
+        Ok(file) => file,
+        Err(_) => return String::new(),
+    };
+
+    let len = file.metadata().map(|m| m.len()).unwrap_or(0);
+    if len > LOG_TAIL_BYTES {
+        let _ = file.seek(SeekFrom::Start(len - LOG_TAIL_BYTES));
+    }
+
+    let mut buf = Vec::new();
+    let _ = file.read_to_end(&mut buf);
+    String::from_utf8_lossy(&buf).trim().to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_classify_whpx_failure() {
+        let err = classify_exit(Some(1), "qemu: WHPX: No accelerator found, hr=80070057".to_string());
+        assert!(err.is_accel_failure());
+    }
+
+    #[test]
+    fn test_classify_other_failure() {
+        let err = classify_exit(Some(1), "qemu: could not open disk image".to_string());
+        assert!(!err.is_accel_failure());
+    }
 }
@@ -0,0 +1,457 @@
+use crate::util::local_socket::{self, LocalStream};
+use crate::util::random::random_bytes;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// virtio-serial port name the agent opens in the guest.
+pub const QGA_CHANNEL: &str = "org.qemu.guest_agent.0";
+
+/// Timeout for each reply. The chardev accepts connections
+/// even when no agent runs in the guest, so a silent agent shows up as a read timeout.
+const QGA_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Bytes per `guest-file-read`/`guest-file-write` call.
+const FILE_CHUNK: usize = 48 * 1024;
+
+/// Delay between `guest-exec-status` polls.
+const EXEC_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Marks the reply to `guest-sync-delimited` and resets the agent's parser.
+const SYNC_DELIMITER: u8 = 0xFF;
+
+#[derive(Error, Debug)]
+pub enum QgaError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("JSON error: {0}")]
+    Json(#[from] serde_json::Error),
+    #[error("Invalid base64 from the guest agent: {0}")]
+    Base64(#[from] base64::DecodeError),
+    #[error("Guest agent connection closed")]
+    Closed,
+    #[error("Guest agent is not responding (is qemu-guest-agent running in the guest?)")]
+    NotResponding,
+    #[error("Guest agent error: {0}")]
+    Command(String),
+    #[error("Guest agent timed out after {0}s")]
+    Timeout(u64),
+}
+
+/// A guest network interface (`guest-network-get-interfaces`).
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GuestInterface {
+    pub name: String,
+    #[serde(default)]
+    pub hardware_address: Option<String>,
+    #[serde(default)]
+    pub ip_addresses: Vec<GuestIpAddress>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub struct GuestIpAddress {
+    /// `ipv4` or `ipv6`.
+    pub ip_address_type: String,
+    pub ip_address: String,
+    pub prefix: u8,
+}
+
+impl GuestInterface {
+    /// Whether this is the loopback interface.
+    pub fn is_loopback(&self) -> bool {
+        self.name == "lo" || self.name.starts_with("Loopback")
+    }
+}
+
+/// Identity of the running guest, as reported by the agent.
+#[derive(Debug, Clone, Serialize)]
+pub struct GuestInfo {
+    pub host_name: String,
+    pub interfaces: Vec<GuestInterface>,
+}
+
+impl GuestInfo {
+    /// All interface addresses, IPv4 first.
+    pub fn addresses(&self) -> Vec<&str> {
+        let mut addresses: Vec<&GuestIpAddress> = self.interfaces.iter().flat_map(|i| &i.ip_addresses).collect();
+        addresses.sort_by_key(|a| a.ip_address_type != "ipv4");
+        addresses.iter().map(|a| a.ip_address.as_str()).collect()
+    }
+}
+
+/// Result of a finished `guest-exec`.
+#[derive(Debug, Clone, Default)]
+pub struct ExecOutput {
+    pub exit_code: Option<i32>,
+    /// Signal that ended the process (Linux guests).
+    pub signal: Option<i32>,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+    /// The agent caps captured output; set when some was dropped.
+    pub truncated: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct ExecStatus {
+    exited: bool,
+    exitcode: Option<i32>,
+    signal: Option<i32>,
+    out_data: Option<String>,
+    err_data: Option<String>,
+    #[serde(default)]
+    out_truncated: bool,
+    #[serde(default)]
+    err_truncated: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct FileRead {
+    buf_b64: String,
+    eof: bool,
+}
+
+/// Synchronous qemu-guest-agent client. Works without SSH or any guest network.
+pub struct QgaClient {
+    reader: BufReader<LocalStream>,
+    writer: LocalStream,
+}
+
+impl QgaClient {
+    /// Connect to the agent chardev at `socket` and synchronize with the agent.
+    pub fn connect(socket: &Path) -> Result<Self, QgaError> {
+        Self::connect_timeout(socket, QGA_TIMEOUT)
+    }
+
+    /// Like `connect`, with a custom timeout for each reply.
+    pub fn connect_timeout(socket: &Path, timeout: Duration) -> Result<Self, QgaError> {
+        let stream = local_socket::connect(socket)?;
+        stream.set_read_timeout(Some(timeout))?;
+        let mut client = Self {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+        };
+        client.sync()?;
+        Ok(client)
+    }
+
+    /// Discard replies left over from earlier clients (the agent keeps
+    /// answering after a client gives up), then confirm the agent is listening.
+    fn sync(&mut self) -> Result<(), QgaError> {
+        let id = u32::from_le_bytes(random_bytes::<4>()?) >> 1;
+        self.writer.write_all(&[SYNC_DELIMITER])?;
+        writeln!(self.writer, "{}", json!({ "execute": "guest-sync-delimited", "arguments": { "id": id } }))?;
+
+        loop {
+            let mut skipped = Vec::new();
+            if self.reader.read_until(SYNC_DELIMITER, &mut skipped).map_err(not_responding)? == 0 {
+                return Err(QgaError::Closed);
+            }
+            if skipped.last() != Some(&SYNC_DELIMITER) {
+                return Err(QgaError::Closed);
+            }
+            let message = self.read_message().map_err(|e| match e {
+                QgaError::Io(e) => not_responding(e),
+                e => e,
+            })?;
+            if message.get("return").and_then(Value::as_u64) == Some(id.into()) {
+                return Ok(());
+            }
+        }
+    }
+
+    fn read_message(&mut self) -> Result<Value, QgaError> {
+        loop {
+            let mut line = String::new();
+            if self.reader.read_line(&mut line)? == 0 {
+                return Err(QgaError::Closed);
+            }
+            if !line.trim().is_empty() {
+                return Ok(serde_json::from_str(&line)?);
+            }
+        }
+    }
+
+    /// Run an agent command and return its `return` value.
+    pub fn execute(&mut self, command: &str, arguments: Option<Value>) -> Result<Value, QgaError> {
+        let mut request = json!({ "execute": command });
+        if let Some(arguments) = arguments {
+            request["arguments"] = arguments;
+        }
+        writeln!(self.writer, "{}", request)?;
+
+        let message = self.read_message()?;
+        if let Some(value) = message.get("return") {
+            return Ok(value.clone());
+        }
+        let desc = message
+            .pointer("/error/desc")
+            .and_then(Value::as_str)
+            .unwrap_or("unknown error");
+        Err(QgaError::Command(desc.to_string()))
+    }
+
+    pub fn ping(&mut self) -> Result<(), QgaError> {
+        self.execute("guest-ping", None).map(|_| ())
+    }
+
+    pub fn host_name(&mut self) -> Result<String, QgaError> {
+        let value = self.execute("guest-get-host-name", None)?;
+        Ok(value.get("host-name").and_then(Value::as_str).unwrap_or_default().to_string())
+    }
+
+    pub fn network_interfaces(&mut self) -> Result<Vec<GuestInterface>, QgaError> {
+        Ok(serde_json::from_value(self.execute("guest-network-get-interfaces", None)?)?)
+    }
+
+    /// Host name and non-loopback interfaces.
+    pub fn guest_info(&mut self) -> Result<GuestInfo, QgaError> {
+        let mut interfaces = self.network_interfaces()?;
+        interfaces.retain(|i| !i.is_loopback());
+        Ok(GuestInfo { host_name: self.host_name()?, interfaces })
+    }
+
+    /// Run `path` with `args` in the guest (no shell) and collect its output.
+    pub fn exec(&mut self, path: &str, args: &[String], timeout: Duration) -> Result<ExecOutput, QgaError> {
+        let started = self.execute(
+            "guest-exec",
+            Some(json!({ "path": path, "arg": args, "capture-output": true })),
+        )?;
+        let pid = started
+            .get("pid")
+            .and_then(Value::as_i64)
+            .ok_or_else(|| QgaError::Command("guest-exec returned no pid".to_string()))?;
+
+        let start = Instant::now();
+        loop {
+            let status: ExecStatus =
+                serde_json::from_value(self.execute("guest-exec-status", Some(json!({ "pid": pid })))?)?;
+            if status.exited {
+                return Ok(ExecOutput {
+                    exit_code: status.exitcode,
+                    signal: status.signal,
+                    stdout: decode(status.out_data.as_deref())?,
+                    stderr: decode(status.err_data.as_deref())?,
+                    truncated: status.out_truncated || status.err_truncated,
+                });
+            }
+            if start.elapsed() >= timeout {
+                return Err(QgaError::Timeout(timeout.as_secs()));
+            }
+            std::thread::sleep(EXEC_POLL_INTERVAL);
+        }
+    }
+
+    /// Copy a guest file into `out` one chunk at a time, calling `progress` with
+    /// the bytes copied so far. Returns the bytes copied.
+    pub fn read_file(&mut self, path: &str, out: &mut (impl Write + ?Sized), mut progress: impl FnMut(u64)) -> Result<u64, QgaError> {
+        let handle = self.open_file(path, "rb")?;
+        let result = (|| {
+            let mut bytes = 0;
+            loop {
+                let chunk: FileRead = serde_json::from_value(self.execute(
+                    "guest-file-read",
+                    Some(json!({ "handle": handle, "count": FILE_CHUNK })),
+                )?)?;
+                let data = STANDARD.decode(chunk.buf_b64)?;
+                out.write_all(&data)?;
+                bytes += data.len() as u64;
+                progress(bytes);
+                if chunk.eof {
+                    return Ok(bytes);
+                }
+            }
+        })();
+        self.close_file(handle, result)
+    }
+
+    /// Create or replace a guest file with what `input` yields, one chunk at a
+    /// time, calling `progress` with the bytes copied so far. Returns the bytes copied.
+    pub fn write_file(&mut self, path: &str, input: &mut (impl Read + ?Sized), mut progress: impl FnMut(u64)) -> Result<u64, QgaError> {
+        let handle = self.open_file(path, "wb")?;
+        let result = (|| {
+            let mut bytes = 0;
+            let mut chunk = Vec::with_capacity(FILE_CHUNK);
+            loop {
+                chunk.clear();
+                if input.take(FILE_CHUNK as u64).read_to_end(&mut chunk)? == 0 {
+                    return Ok(bytes);
+                }
+                self.execute(
+                    "guest-file-write",
+                    Some(json!({ "handle": handle, "buf-b64": STANDARD.encode(&chunk) })),
+                )?;
+                bytes += chunk.len() as u64;
+                progress(bytes);
+            }
+        })();
+        self.close_file(handle, result)
+    }
+
+    fn open_file(&mut self, path: &str, mode: &str) -> Result<i64, QgaError> {
+        self.execute("guest-file-open", Some(json!({ "path": path, "mode": mode })))?
+            .as_i64()
+            .ok_or_else(|| QgaError::Command("guest-file-open returned no handle".to_string()))
+    }
+
+    /// Close `handle`, keeping the first error.
+    fn close_file<T>(&mut self, handle: i64, result: Result<T, QgaError>) -> Result<T, QgaError> {
+        let closed = self.execute("guest-file-close", Some(json!({ "handle": handle })));
+        let value = result?;
+        closed?;
+        Ok(value)
+    }
+
+    /// Ask the guest OS to power off. The agent only replies on failure.
+    pub fn shutdown(&mut self) -> Result<(), QgaError> {
+        writeln!(self.writer, "{}", json!({ "execute": "guest-shutdown", "arguments": { "mode": "powerdown" } }))?;
+        match self.read_message() {
+            Ok(message) => match message.pointer("/error/desc").and_then(Value::as_str) {
+                Some(desc) => Err(QgaError::Command(desc.to_string())),
+                None => Ok(()),
+            },
+            Err(QgaError::Closed) => Ok(()),
+            Err(QgaError::Io(e)) if is_timeout(&e) => Ok(()),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+/// Connect and ping until the agent answers.
+pub fn wait_for_agent(socket: &Path, timeout: Duration) -> Result<(), QgaError> {
+    let start = Instant::now();
+    loop {
+        match QgaClient::connect(socket).and_then(|mut client| client.ping()) {
+            Ok(()) => return Ok(()),
+            Err(QgaError::Io(_) | QgaError::Closed | QgaError::NotResponding) if start.elapsed() < timeout => {
+                std::thread::sleep(Duration::from_secs(1));
+            }
+            Err(QgaError::Io(_) | QgaError::Closed | QgaError::NotResponding) => {
+                return Err(QgaError::Timeout(timeout.as_secs()));
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn decode(data: Option<&str>) -> Result<Vec<u8>, QgaError> {
+    Ok(data.map(|d| STANDARD.decode(d)).transpose()?.unwrap_or_default())
+}
+
+fn is_timeout(e: &std::io::Error) -> bool {
+    matches!(e.kind(), ErrorKind::WouldBlock | ErrorKind::TimedOut)
+}
+
+fn not_responding(e: std::io::Error) -> QgaError {
+    if is_timeout(&e) { QgaError::NotResponding } else { QgaError::Io(e) }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::os::unix::net::UnixListener;
+
+    /// Minimal agent: a stale reply first, then sync, ping, exec and an in-memory filesystem.
+    fn fake_agent(listener: UnixListener) {
+        let (stream, _) = listener.accept().unwrap();
+        let mut writer = stream.try_clone().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut files: HashMap<i64, (String, Vec<u8>, usize)> = HashMap::new();
+        let mut stored: HashMap<String, Vec<u8>> = HashMap::new();
+        let mut polls = 0;
+        writer.write_all(b"{\"return\": {}}\n\xff{\"return\": 1}\n").unwrap();
+
+        let mut line = Vec::new();
+        while reader.read_until(b'\n', &mut line).unwrap() > 0 {
+            let text = String::from_utf8_lossy(&line).trim_start_matches('\u{fffd}').to_string();
+            line.clear();
+            let request: Value = serde_json::from_str(&text).unwrap();
+            let args = &request["arguments"];
+            let reply = match request["execute"].as_str().unwrap() {
+                "guest-sync-delimited" => {
+                    writer.write_all(&[SYNC_DELIMITER]).unwrap();
+                    json!({ "return": args["id"] })
+                }
+                "guest-ping" => json!({ "return": {} }),
+                "guest-exec" => json!({ "return": { "pid": 42 } }),
+                "guest-exec-status" => {
+                    polls += 1;
+                    if polls < 2 {
+                        json!({ "return": { "exited": false } })
+                    } else {
+                        json!({ "return": { "exited": true, "exitcode": 3, "out-data": STANDARD.encode("hi\n") } })
+                    }
+                }
+                "guest-file-open" => {
+                    let path = args["path"].as_str().unwrap().to_string();
+                    if args["mode"] == "rb" && !stored.contains_key(&path) {
+                        json!({ "error": { "class": "GenericError", "desc": "No such file" } })
+                    } else {
+                        files.insert(7, (path, Vec::new(), 0));
+                        json!({ "return": 7 })
+                    }
+                }
+                "guest-file-write" => {
+                    let data = STANDARD.decode(args["buf-b64"].as_str().unwrap()).unwrap();
+                    files.get_mut(&7).unwrap().1.extend(&data);
+                    json!({ "return": { "count": data.len(), "eof": false } })
+                }
+                "guest-file-read" => {
+                    let (path, _, offset) = files.get_mut(&7).unwrap();
+                    let data = &stored[path.as_str()];
+                    let end = (*offset + args["count"].as_u64().unwrap() as usize).min(data.len());
+                    let chunk = STANDARD.encode(&data[*offset..end]);
+                    *offset = end;
+                    json!({ "return": { "count": end, "buf-b64": chunk, "eof": end == data.len() } })
+                }
+                "guest-file-close" => {
+                    let (path, written, _) = files.remove(&7).unwrap();
+                    stored.entry(path).or_insert(written);
+                    json!({ "return": {} })
+                }
+                other => panic!("unexpected command {}", other),
+            };
+            writeln!(writer, "{}", reply).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_qga_client() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = dir.path().join("qga.sock");
+        let listener = UnixListener::bind(&socket).unwrap();
+        let agent = std::thread::spawn(move || fake_agent(listener));
+
+        let mut client = QgaClient::connect(&socket).unwrap();
+        client.ping().unwrap();
+
+        let output = client.exec("/bin/sh", &["-c".to_string(), "echo hi; exit 3".to_string()], QGA_TIMEOUT).unwrap();
+        assert_eq!(output.exit_code, Some(3));
+        assert_eq!(output.stdout, b"hi\n");
+
+        let data: Vec<u8> = (0..FILE_CHUNK * 2 + 10).map(|i| i as u8).collect();
+        let mut reported = Vec::new();
+        let written = client.write_file("/tmp/blob", &mut data.as_slice(), |n| reported.push(n)).unwrap();
+        assert_eq!(written, data.len() as u64);
+        assert_eq!(reported, [FILE_CHUNK as u64, 2 * FILE_CHUNK as u64, written]);
+        let mut read = Vec::new();
+        assert_eq!(client.read_file("/tmp/blob", &mut read, |_| {}).unwrap(), written);
+        assert_eq!(read, data);
+        assert!(matches!(
+            client.read_file("/missing", &mut Vec::new(), |_| {}),
+            Err(QgaError::Command(desc)) if desc == "No such file"
+        ));
+
+        drop(client);
+        agent.join().unwrap();
+    }
+}
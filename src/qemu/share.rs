@@ -0,0 +1,45 @@
+use crate::config::schema::{GuestOs, NetworkConfig, ResolvedShareConfig, DEFAULT_USER_NET};
+use crate::util::net::Ipv4Cidr;
+use std::net::Ipv4Addr;
+
+/// Longest mount tag accepted by virtio-9p.
+pub const MAX_MOUNT_TAG_LEN: usize = 31;
+
+/// How a share is exported to the guest.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShareTransport {
+    /// virtio-9p fsdev, mounted with `mount -t 9p` (Linux guests).
+    Virtio9p,
+    /// SLiRP built-in SMB server via `-netdev user,smb=` (Windows guests).
+    Smb,
+}
+
+pub fn share_transport(guest_os: GuestOs) -> ShareTransport {
+    match guest_os {
+        GuestOs::Linux => ShareTransport::Virtio9p,
+        GuestOs::Windows => ShareTransport::Smb,
+    }
+}
+
+/// Address of the SLiRP SMB server (the 4th address of the user network).
+pub fn smb_server_addr(network: &NetworkConfig) -> Ipv4Addr {
+    let net = network
+        .net
+        .as_deref()
+        .unwrap_or(DEFAULT_USER_NET)
+        .parse::<Ipv4Cidr>()
+        .unwrap_or_else(|_| DEFAULT_USER_NET.parse().expect("default net is valid"));
+    Ipv4Addr::from(u32::from(net.network()) + 4)
+}
+
+/// Command to run inside the guest to mount a share.
+pub fn guest_mount_command(share: &ResolvedShareConfig, guest_os: GuestOs, network: &NetworkConfig) -> String {
+    match share_transport(guest_os) {
+        ShareTransport::Virtio9p => format!(
+            "sudo mkdir -p /mnt/{tag} && sudo mount -t 9p -o trans=virtio,version=9p2000.L{ro} {tag} /mnt/{tag}",
+            tag = share.tag,
+            ro = if share.read_only { ",ro" } else { "" },
+        ),
+        ShareTransport::Smb => format!("net use * \\\\{}\\qemu", smb_server_addr(network)),
+    }
+}
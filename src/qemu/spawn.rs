@@ -1,6 +1,7 @@
 use std::fs::File;
 use std::path::Path;
-use std::process::Stdio;
+use std::process::{Command, Stdio};
+use crate::util::process::detach;
 use thiserror::Error;
 
 #[derive(Debug)]
@@ -36,9 +37,10 @@ pub fn spawn_qemu(
     cmd.args(argv);
     cmd.stdout(Stdio::from(log_file_handle.try_clone()?));
     cmd.stderr(Stdio::from(log_file_handle));
+    detach(&mut cmd);
     
     // Spawn
-    let mut child = cmd.spawn()?;
+    let child = cmd.spawn()?;
     
     // Get PID
     let pid = child.id();
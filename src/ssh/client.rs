@@ -0,0 +1,113 @@
+use crate::config::schema::{GuestOs, ResolvedConfig};
+use crate::ssh::keys::KeyError;
+use crate::ssh::known_hosts::host_key_alias;
+use std::ffi::OsString;
+use std::path::Path;
+use std::process::Command;
+
+/// Where Windows OpenSSH reads keys for members of the Administrators group.
+const WINDOWS_ADMIN_KEYS: &str = r"C:\ProgramData\ssh\administrators_authorized_keys";
+
+/// Options for connecting to the VM with `identity_file`.
+/// Host keys are checked against the VM's pinned known_hosts file.
+pub fn ssh_options(config: &ResolvedConfig, identity_file: &Path) -> Vec<OsString> {
+    let mut options: Vec<OsString> = vec!["-p".into(), config.network.ssh_host_port.to_string().into()];
+    options.extend(identity_options(config, identity_file));
+    options
+}
+
+/// Same as `ssh_options`, for scp (which spells the port flag `-P`).
+pub fn scp_options(config: &ResolvedConfig, identity_file: &Path) -> Vec<OsString> {
+    let mut options: Vec<OsString> = vec!["-P".into(), config.network.ssh_host_port.to_string().into()];
+    options.extend(identity_options(config, identity_file));
+    options
+}
+
+fn identity_options(config: &ResolvedConfig, identity_file: &Path) -> Vec<OsString> {
+    vec![
+        "-i".into(),
+        identity_file.into(),
+        "-o".into(),
+        "IdentitiesOnly=yes".into(),
+        "-o".into(),
+        "StrictHostKeyChecking=yes".into(),
+        "-o".into(),
+        format!("UserKnownHostsFile={}", config.vscode.known_hosts_file.to_string_lossy()).into(),
+        "-o".into(),
+        format!("HostKeyAlias={}", host_key_alias(&config.vm.name)).into(),
+    ]
+}
+
+/// `user@localhost` for the VM.
+pub fn ssh_destination(config: &ResolvedConfig) -> String {
+    format!("{}@localhost", config.vscode.ssh_user)
+}
+
+/// `ssh` invocation for the VM using `identity_file`; remote command arguments may follow.
+pub fn ssh_command(config: &ResolvedConfig, identity_file: &Path) -> Command {
+    let mut cmd = Command::new("ssh");
+    cmd.args(ssh_options(config, identity_file));
+    cmd.arg(ssh_destination(config));
+    cmd
+}
+
+/// Add `key_line` to the guest's authorized keys over SSH, authenticating with
+/// `identity_file` (or a password prompt). `replace` removes an old key line.
+pub fn authorize_key(
+    config: &ResolvedConfig,
+    identity_file: &Path,
+    key_line: &str,
+    replace: Option<&str>,
+) -> Result<(), KeyError> {
+    let script = match config.vm.guest_os {
+        GuestOs::Linux => linux_authorize_script(key_line, replace),
+        GuestOs::Windows => windows_authorize_script(key_line, replace),
+    };
+
+    let status = ssh_command(config, identity_file).arg(script).status()?;
+    if !status.success() {
+        return Err(KeyError::Push(match status.code() {
+            Some(code) => format!("ssh exited with code {}", code),
+            None => "ssh was terminated".to_string(),
+        }));
+    }
+    Ok(())
+}
+
+fn sh_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', r"'\''"))
+}
+
+fn ps_quote(s: &str) -> String {
+    format!("'{}'", s.replace('\'', "''"))
+}
+
+fn linux_authorize_script(key_line: &str, replace: Option<&str>) -> String {
+    let mut script = String::from(
+        "umask 077 && mkdir -p ~/.ssh && touch ~/.ssh/authorized_keys && ",
+    );
+    if let Some(old) = replace {
+        script.push_str(&format!(
+            "{{ grep -vxF {} ~/.ssh/authorized_keys > ~/.ssh/authorized_keys.tmp; mv ~/.ssh/authorized_keys.tmp ~/.ssh/authorized_keys; }} && ",
+            sh_quote(old.trim())
+        ));
+    }
+    script.push_str(&format!(
+        "{{ grep -qxF {0} ~/.ssh/authorized_keys || echo {0} >> ~/.ssh/authorized_keys; }}",
+        sh_quote(key_line.trim())
+    ));
+    script
+}
+
+fn windows_authorize_script(key_line: &str, replace: Option<&str>) -> String {
+    let filter = match replace {
+        Some(old) => format!(" | Where-Object {{ $_ -ne {} }}", ps_quote(old.trim())),
+        None => String::new(),
+    };
+    format!(
+        "powershell -NoProfile -Command \"$f = {file}; $k = @(Get-Content $f -ErrorAction SilentlyContinue{filter}); if ($k -notcontains {key}) {{ $k += {key} }}; Set-Content -Path $f -Value $k -Encoding ascii\"",
+        file = ps_quote(WINDOWS_ADMIN_KEYS),
+        filter = filter,
+        key = ps_quote(key_line.trim()),
+    )
+}
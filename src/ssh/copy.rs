@@ -0,0 +1,89 @@
+use crate::config::schema::ResolvedConfig;
+use crate::ssh::client::{scp_options, ssh_destination};
+use std::ffi::OsString;
+use std::path::PathBuf;
+use std::process::Command;
+use thiserror::Error;
+
+/// Prefix marking a guest path in `portaqemu cp` arguments.
+pub const GUEST_PREFIX: &str = "vm:";
+
+#[derive(Error, Debug)]
+pub enum CopyError {
+    #[error("One of source and destination must be a guest path (vm:<path>)")]
+    NoGuestPath,
+    #[error("Copying between two guest paths is not supported")]
+    BothGuestPaths,
+    #[error("Source not found: {0}")]
+    SourceNotFound(String),
+}
+
+/// One side of a copy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CopyEndpoint {
+    Host(PathBuf),
+    Guest(String),
+}
+
+impl CopyEndpoint {
+    pub fn parse(arg: &str) -> Self {
+        match arg.strip_prefix(GUEST_PREFIX) {
+            Some(path) => CopyEndpoint::Guest(path.to_string()),
+            None => CopyEndpoint::Host(PathBuf::from(arg)),
+        }
+    }
+
+    fn to_scp_arg(&self, config: &ResolvedConfig) -> OsString {
+        match self {
+            CopyEndpoint::Host(path) => path.into(),
+            // An empty guest path means the user's home directory
+            CopyEndpoint::Guest(path) => format!("{}:{}", ssh_destination(config), path).into(),
+        }
+    }
+}
+
+/// Build the scp invocation copying `src` to `dst`.
+/// scp shows its own progress meter unless `quiet`.
+pub fn copy_command(
+    config: &ResolvedConfig,
+    src: &CopyEndpoint,
+    dst: &CopyEndpoint,
+    recursive: bool,
+    quiet: bool,
+) -> Result<Command, CopyError> {
+    match (src, dst) {
+        (CopyEndpoint::Host(_), CopyEndpoint::Host(_)) => return Err(CopyError::NoGuestPath),
+        (CopyEndpoint::Guest(_), CopyEndpoint::Guest(_)) => return Err(CopyError::BothGuestPaths),
+        (CopyEndpoint::Host(path), _) if !path.exists() => {
+            return Err(CopyError::SourceNotFound(path.to_string_lossy().to_string()));
+        }
+        _ => {}
+    }
+
+    let mut cmd = Command::new("scp");
+    cmd.args(scp_options(config, &config.vscode.identity_file));
+    if recursive {
+        cmd.arg("-r");
+    }
+    if quiet {
+        cmd.arg("-q");
+    }
+    cmd.arg(src.to_scp_arg(config));
+    cmd.arg(dst.to_scp_arg(config));
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_endpoint() {
+        assert_eq!(CopyEndpoint::parse("vm:/tmp/"), CopyEndpoint::Guest("/tmp/".to_string()));
+        assert_eq!(CopyEndpoint::parse("vm:"), CopyEndpoint::Guest(String::new()));
+        assert_eq!(
+            CopyEndpoint::parse("C:/build.zip"),
+            CopyEndpoint::Host(PathBuf::from("C:/build.zip"))
+        );
+    }
+}
@@ -0,0 +1,144 @@
+use crate::config::schema::ResolvedConfig;
+use crate::ssh::keys::fingerprint_line;
+use crate::util::fs_atomic;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+use thiserror::Error;
+
+/// Host key types requested from the guest, in preference order.
+const KEY_TYPES: &str = "ed25519,ecdsa,rsa";
+/// Seconds ssh-keyscan waits for the guest.
+const SCAN_TIMEOUT_SECS: u32 = 10;
+
+#[derive(Error, Debug)]
+pub enum KnownHostsError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Atomic write error: {0}")]
+    AtomicWrite(#[from] fs_atomic::AtomicWriteError),
+    #[error("ssh-keyscan failed: {0}")]
+    Scan(String),
+    #[error("No host keys received from port {0}")]
+    NoKeys(u16),
+}
+
+/// A guest host key as stored in known_hosts.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct HostKey {
+    pub key_type: String,
+    pub key: String,
+}
+
+impl HostKey {
+    pub fn fingerprint(&self) -> String {
+        fingerprint_line(&format!("{} {}", self.key_type, self.key)).unwrap_or_default()
+    }
+}
+
+/// Host key entries are stored under an alias so they survive host port changes.
+pub fn host_key_alias(vm_name: &str) -> String {
+    format!("portaqemu-{}", vm_name)
+}
+
+/// Fetch the guest's host keys with ssh-keyscan.
+pub fn scan_host_keys(port: u16) -> Result<Vec<HostKey>, KnownHostsError> {
+    let output = Command::new("ssh-keyscan")
+        .arg("-p")
+        .arg(port.to_string())
+        .arg("-T")
+        .arg(SCAN_TIMEOUT_SECS.to_string())
+        .arg("-t")
+        .arg(KEY_TYPES)
+        .arg("127.0.0.1")
+        .output()
+        .map_err(|e| KnownHostsError::Scan(e.to_string()))?;
+
+    let keys = parse_known_hosts(&String::from_utf8_lossy(&output.stdout));
+    if keys.is_empty() {
+        return Err(KnownHostsError::NoKeys(port));
+    }
+    Ok(keys)
+}
+
+/// Parse `<host> <type> <key>` lines, ignoring comments.
+fn parse_known_hosts(contents: &str) -> Vec<HostKey> {
+    contents
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(|line| {
+            let mut fields = line.split_whitespace().skip(1);
+            Some(HostKey {
+                key_type: fields.next()?.to_string(),
+                key: fields.next()?.to_string(),
+            })
+        })
+        .collect()
+}
+
+/// Host keys pinned for the VM (empty if none yet).
+pub fn read_pinned(config: &ResolvedConfig) -> Result<Vec<HostKey>, KnownHostsError> {
+    let path = &config.vscode.known_hosts_file;
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+    Ok(parse_known_hosts(&fs::read_to_string(path)?))
+}
+
+fn write_pinned(path: &Path, vm_name: &str, keys: &[HostKey]) -> Result<(), KnownHostsError> {
+    let alias = host_key_alias(vm_name);
+    let mut contents = format!("# Host keys pinned by portaqemu for VM '{}'\n", vm_name);
+    for key in keys {
+        contents.push_str(&format!("{} {} {}\n", alias, key.key_type, key.key));
+    }
+    fs_atomic::atomic_write_str(path, &contents)?;
+    Ok(())
+}
+
+/// Result of pinning the guest host keys.
+pub struct PinOutcome {
+    pub keys: Vec<HostKey>,
+    /// False when keys were already pinned and left untouched.
+    pub newly_pinned: bool,
+}
+
+/// Pin the guest's host keys on first contact. Existing pins are never replaced;
+/// use `reset_pinned` for a re-imaged VM.
+pub fn pin_host_keys(config: &ResolvedConfig) -> Result<PinOutcome, KnownHostsError> {
+    let pinned = read_pinned(config)?;
+    if !pinned.is_empty() {
+        return Ok(PinOutcome { keys: pinned, newly_pinned: false });
+    }
+
+    let keys = scan_host_keys(config.network.ssh_host_port)?;
+    write_pinned(&config.vscode.known_hosts_file, &config.vm.name, &keys)?;
+    Ok(PinOutcome { keys, newly_pinned: true })
+}
+
+/// Forget the pinned host keys. Returns whether a file was removed.
+pub fn reset_pinned(config: &ResolvedConfig) -> Result<bool, KnownHostsError> {
+    let path = &config.vscode.known_hosts_file;
+    if !path.exists() {
+        return Ok(false);
+    }
+    fs::remove_file(path)?;
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_keyscan_output() {
+        let output = "# 127.0.0.1:2222 SSH-2.0-OpenSSH_9.6\n\
+                      [127.0.0.1]:2222 ssh-ed25519 AAAAC3NzaC1lZDI1NTE5AAAAIJwNQaD\n\
+                      \n\
+                      [127.0.0.1]:2222 ecdsa-sha2-nistp256 AAAAE2VjZHNh\n";
+        let keys = parse_known_hosts(output);
+        assert_eq!(keys.len(), 2);
+        assert_eq!(keys[0].key_type, "ssh-ed25519");
+        assert_eq!(keys[1].key, "AAAAE2VjZHNh");
+    }
+}
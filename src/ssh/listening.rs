@@ -0,0 +1,91 @@
+use crate::config::schema::{GuestOs, ResolvedConfig};
+use crate::ssh::client::ssh_command;
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum ListenError {
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Listing guest ports failed: {0}")]
+    Command(String),
+}
+
+/// Command listing listening TCP sockets in the guest.
+fn listening_command(guest_os: GuestOs) -> &'static str {
+    match guest_os {
+        GuestOs::Linux => "ss -ltnH",
+        GuestOs::Windows => "netstat -an -p tcp",
+    }
+}
+
+/// TCP ports the guest listens on, reachable from outside the guest.
+pub fn guest_listening_ports(config: &ResolvedConfig) -> Result<BTreeSet<u16>, ListenError> {
+    let output = ssh_command(config, &config.vscode.identity_file)
+        .arg("-T")
+        .arg("-o")
+        .arg("BatchMode=yes")
+        .arg(listening_command(config.vm.guest_os))
+        .output()?;
+    if !output.status.success() {
+        return Err(ListenError::Command(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(parse_listening(config.vm.guest_os, &String::from_utf8_lossy(&output.stdout)))
+}
+
+/// Parse `ss -ltnH` or `netstat -an` output. Loopback-only listeners are skipped,
+/// since host forwards connect to the guest's network address.
+pub fn parse_listening(guest_os: GuestOs, output: &str) -> BTreeSet<u16> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let local = match guest_os {
+                // State Recv-Q Send-Q Local:Port Peer:Port
+                GuestOs::Linux => fields.get(3)?,
+                // Proto Local Foreign State
+                GuestOs::Windows if fields.get(3) == Some(&"LISTENING") => fields.get(1)?,
+                GuestOs::Windows => return None,
+            };
+            let (addr, port) = local.rsplit_once(':')?;
+            if is_loopback(addr) {
+                return None;
+            }
+            port.parse().ok()
+        })
+        .collect()
+}
+
+fn is_loopback(addr: &str) -> bool {
+    // Strip IPv6 brackets and `%iface` scope suffixes
+    let addr = addr.trim_start_matches('[').trim_end_matches(']');
+    let addr = addr.split('%').next().unwrap_or(addr);
+    addr.parse::<IpAddr>().map(|ip| ip.is_loopback()).unwrap_or(false)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_ss_output() {
+        let output = "LISTEN 0 4096 127.0.0.53%lo:53 0.0.0.0:*\n\
+                      LISTEN 0 128 0.0.0.0:22 0.0.0.0:*\n\
+                      LISTEN 0 511 *:3000 *:*\n\
+                      LISTEN 0 128 [::1]:631 [::]:*\n\
+                      LISTEN 0 128 [::]:8080 [::]:*\n";
+        let ports: Vec<u16> = parse_listening(GuestOs::Linux, output).into_iter().collect();
+        assert_eq!(ports, vec![22, 3000, 8080]);
+    }
+
+    #[test]
+    fn test_parse_netstat_output() {
+        let output = "\r\nActive Connections\r\n\r\n  Proto  Local Address          Foreign Address        State\r\n\
+                      \x20 TCP    0.0.0.0:135            0.0.0.0:0              LISTENING\r\n\
+                      \x20 TCP    127.0.0.1:5939         0.0.0.0:0              LISTENING\r\n\
+                      \x20 TCP    10.0.2.15:50000        20.1.1.1:443           ESTABLISHED\r\n";
+        let ports: Vec<u16> = parse_listening(GuestOs::Windows, output).into_iter().collect();
+        assert_eq!(ports, vec![135]);
+    }
+}
@@ -0,0 +1,13 @@
+pub mod keys;
+pub mod client;
+pub mod known_hosts;
+pub mod copy;
+pub mod listening;
+pub mod ready;
+
+pub use keys::*;
+pub use client::*;
+pub use known_hosts::*;
+pub use copy::*;
+pub use listening::*;
+pub use ready::*;
@@ -0,0 +1,152 @@
+use crate::config::schema::{ReadinessConfig, ResolvedConfig};
+use crate::ssh::client::ssh_command;
+use crate::ssh::known_hosts::pin_host_keys;
+use std::io::{BufRead, BufReader};
+use std::net::{Ipv4Addr, SocketAddr, TcpStream};
+use std::time::{Duration, Instant};
+use thiserror::Error;
+
+/// Timeout for connecting and for reading the banner in one probe.
+const PROBE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Lines a server may send before its version string (RFC 4253, 4.2).
+const MAX_PRE_BANNER_LINES: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum ReadyError {
+    #[error("SSH port {0} is closed")]
+    PortClosed(u16),
+    #[error("No SSH banner on port {0} (sshd not running yet?)")]
+    NoBanner(u16),
+    #[error("SSH authentication failed: {0}")]
+    AuthFailed(String),
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Guest not ready after {secs}s: {last}")]
+    Timeout { secs: u64, last: Box<ReadyError> },
+}
+
+impl ReadyError {
+    /// Whether the guest may still be booting (as opposed to a local failure).
+    pub fn is_retryable(&self) -> bool {
+        matches!(
+            self,
+            ReadyError::PortClosed(_) | ReadyError::NoBanner(_) | ReadyError::AuthFailed(_)
+        )
+    }
+}
+
+/// Progress of `wait_for_ssh`, reported after each failed probe.
+#[derive(Debug)]
+pub struct ProbeProgress<'a> {
+    pub attempt: u32,
+    pub elapsed: Duration,
+    pub error: &'a ReadyError,
+}
+
+/// Connect to the SSH port and read the server's version string.
+/// A bare TCP connect is not enough: user-mode networking accepts connections
+/// on forwarded ports before anything listens in the guest.
+pub fn probe_banner(port: u16, timeout: Duration) -> Result<String, ReadyError> {
+    let addr = SocketAddr::from((Ipv4Addr::LOCALHOST, port));
+    let stream = TcpStream::connect_timeout(&addr, timeout).map_err(|_| ReadyError::PortClosed(port))?;
+    stream.set_read_timeout(Some(timeout))?;
+
+    let mut reader = BufReader::new(stream);
+    for _ in 0..MAX_PRE_BANNER_LINES {
+        let mut line = String::new();
+        match reader.read_line(&mut line) {
+            Ok(0) | Err(_) => break,
+            Ok(_) => {}
+        }
+        let line = line.trim_end();
+        if line.starts_with("SSH-2.0-") || line.starts_with("SSH-1.99-") {
+            return Ok(line.to_string());
+        }
+    }
+    Err(ReadyError::NoBanner(port))
+}
+
+/// Log in with the VM identity and run a no-op command, pinning host keys first.
+pub fn probe_auth(config: &ResolvedConfig) -> Result<(), ReadyError> {
+    pin_host_keys(config).map_err(|e| ReadyError::AuthFailed(e.to_string()))?;
+    let output = ssh_command(config, &config.vscode.identity_file)
+        .arg("-T")
+        .arg("-o")
+        .arg("BatchMode=yes")
+        .arg("-o")
+        .arg(format!("ConnectTimeout={}", PROBE_TIMEOUT.as_secs()))
+        // Valid in sh, cmd and PowerShell
+        .arg("exit 0")
+        .output()?;
+    if !output.status.success() {
+        return Err(ReadyError::AuthFailed(String::from_utf8_lossy(&output.stderr).trim().to_string()));
+    }
+    Ok(())
+}
+
+/// Probe once: banner, then the authenticated command when `auth` is set.
+pub fn probe_ssh(config: &ResolvedConfig, auth: bool) -> Result<String, ReadyError> {
+    let banner = probe_banner(config.network.ssh_host_port, PROBE_TIMEOUT)?;
+    if auth {
+        probe_auth(config)?;
+    }
+    Ok(banner)
+}
+
+/// Probe until the guest's sshd is ready, backing off exponentially between attempts.
+/// Returns the server banner.
+pub fn wait_for_ssh(
+    config: &ResolvedConfig,
+    readiness: &ReadinessConfig,
+    mut on_progress: impl FnMut(&ProbeProgress),
+) -> Result<String, ReadyError> {
+    let start = Instant::now();
+    let timeout = Duration::from_secs(readiness.timeout_secs);
+    let max_backoff = Duration::from_millis(readiness.max_backoff_ms);
+    let mut backoff = Duration::from_millis(readiness.initial_backoff_ms).min(max_backoff);
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        let error = match probe_ssh(config, readiness.auth) {
+            Ok(banner) => return Ok(banner),
+            Err(e) if e.is_retryable() => e,
+            Err(e) => return Err(e),
+        };
+
+        let elapsed = start.elapsed();
+        on_progress(&ProbeProgress { attempt, elapsed, error: &error });
+        if elapsed + backoff >= timeout {
+            return Err(ReadyError::Timeout { secs: readiness.timeout_secs, last: Box::new(error) });
+        }
+        std::thread::sleep(backoff);
+        backoff = (backoff * 2).min(max_backoff);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use std::net::TcpListener;
+
+    #[test]
+    fn test_probe_banner() {
+        let listener = TcpListener::bind((Ipv4Addr::LOCALHOST, 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            // Accepted but closed without a banner, like an unforwarded SLiRP port
+            drop(listener.accept().unwrap());
+            let (mut stream, _) = listener.accept().unwrap();
+            stream.write_all(b"hello\r\nSSH-2.0-OpenSSH_9.6\r\n").unwrap();
+        });
+
+        let timeout = Duration::from_secs(2);
+        assert!(matches!(probe_banner(port, timeout), Err(ReadyError::NoBanner(p)) if p == port));
+        assert_eq!(probe_banner(port, timeout).unwrap(), "SSH-2.0-OpenSSH_9.6");
+        server.join().unwrap();
+
+        assert!(matches!(probe_banner(port, timeout), Err(ReadyError::PortClosed(_))));
+    }
+}
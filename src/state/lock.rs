@@ -47,7 +47,7 @@ impl Lock {
                         // On Unix, check if process exists
                         use std::process::Command;
                         let output = Command::new("kill")
-                            .args(&["-0", &pid.to_string()])
+                            .args(["-0", &pid.to_string()])
                             .output();
                         if output.is_ok() && output.unwrap().status.success() {
                             return Err(LockError::AlreadyLocked);
@@ -1,22 +1,120 @@
+use crate::config::schema::{PortForward, ResolvedConfig};
+use crate::util::net::{Protocol, AUTO_PORT};
 use serde::{Deserialize, Serialize};
+use std::net::Ipv4Addr;
+use std::path::PathBuf;
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct VmState {
     pub running: bool,
     pub qemu_pid: Option<u32>,
     pub started_at: Option<String>,
     pub qemu_args_hash: Option<String>,
     pub last_error: Option<String>,
+    #[serde(default)]
+    pub shares: Vec<ActiveShare>,
+    /// QMP socket of the running VM.
+    #[serde(default)]
+    pub monitor: Option<PathBuf>,
+    /// Host port forwarded to guest SSH (the chosen port when configured as `"auto"`).
+    #[serde(default)]
+    pub ssh_host_port: Option<u16>,
+    #[serde(default)]
+    pub forwards: Vec<ActiveForward>,
+    /// PID of the background `forward watch` process.
+    #[serde(default)]
+    pub autoforward_pid: Option<u32>,
+    /// Address viewers attach to the serial console on.
+    #[serde(default)]
+    pub console: Option<String>,
+    /// PID of the background console hub.
+    #[serde(default)]
+    pub console_pid: Option<u32>,
+    /// Socket of the guest agent's chardev.
+    #[serde(default)]
+    pub guest_agent_socket: Option<PathBuf>,
+    /// PID of the background integration host.
+    #[serde(default)]
+    pub integration_pid: Option<u32>,
+    /// Entries requested while the `"auto"` SSH port was unknown, written at the next `up`.
+    #[serde(default)]
+    pub pending_entries: PendingEntries,
+}
+
+/// SSH entries `vscode install` and `terminal install` left for `up` to write.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct PendingEntries {
+    pub ssh_config: bool,
+    pub terminal_fragment: bool,
+}
+
+impl VmState {
+    /// Fill `"auto"` ports in `config` with the ports chosen at startup.
+    pub fn apply_ports(&self, config: &mut ResolvedConfig) {
+        let network = &mut config.network;
+        if network.ssh_host_port == AUTO_PORT {
+            if let Some(port) = self.ssh_host_port {
+                network.ssh_host_port = port;
+            }
+        }
+
+        let mut claimed = Vec::new();
+        for forward in network.forwards.iter_mut().filter(|f| f.host == AUTO_PORT) {
+            let active = self.forwards.iter().enumerate().find(|(i, active)| {
+                !active.runtime
+                    && !active.auto
+                    && !claimed.contains(i)
+                    && active.proto == forward.proto
+                    && active.bind == forward.bind
+                    && active.guest_addr == forward.guest_addr
+                    && active.guest == forward.guest
+                    && active.name == forward.name
+            });
+            if let Some((i, active)) = active {
+                forward.host = active.host;
+                claimed.push(i);
+            }
+        }
+    }
+}
+
+/// A shared folder exported by the running VM.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ActiveShare {
+    pub tag: String,
+    pub host_path: String,
+    pub read_only: bool,
+    pub mount_command: String,
+}
+
+
+/// A host port forward active in the running VM.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ActiveForward {
+    pub proto: Protocol,
+    pub bind: Ipv4Addr,
+    pub host: u16,
+    pub guest_addr: Option<Ipv4Addr>,
+    pub guest: u16,
+    pub name: Option<String>,
+    /// Added with `portaqemu forward add` rather than from config.
+    pub runtime: bool,
+    /// Added automatically for a listening guest port.
+    #[serde(default)]
+    pub auto: bool,
 }
 
-impl Default for VmState {
-    fn default() -> Self {
+impl From<&PortForward> for ActiveForward {
+    fn from(forward: &PortForward) -> Self {
         Self {
-            running: false,
-            qemu_pid: None,
-            started_at: None,
-            qemu_args_hash: None,
-            last_error: None,
+            proto: forward.proto,
+            bind: forward.bind,
+            host: forward.host,
+            guest_addr: forward.guest_addr,
+            guest: forward.guest,
+            name: forward.name.clone(),
+            runtime: false,
+            auto: false,
         }
     }
 }
@@ -2,8 +2,9 @@ use crate::config::schema::{ResolvedConfig, TerminalMode};
 use crate::config::paths::get_fragment_file;
 use crate::terminal::guid::generate_profile_guid;
 use crate::util::fs_atomic;
+use crate::util::net::AUTO_PORT;
 use serde_json::json;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
@@ -14,15 +15,20 @@ pub enum FragmentError {
     Json(#[from] serde_json::Error),
     #[error("Atomic write error: {0}")]
     AtomicWrite(#[from] crate::util::fs_atomic::AtomicWriteError),
+    #[error("SSH port is \"auto\" and no port has been chosen yet (start the VM with: portaqemu up)")]
+    PortNotChosen,
 }
 
-/// Generate fragment JSON for a VM.
-pub fn generate_fragment(config: &ResolvedConfig) -> Result<String, FragmentError> {
+/// Generate fragment JSON for a VM managed from `root`.
+pub fn generate_fragment(config: &ResolvedConfig, root: &Path) -> Result<String, FragmentError> {
     let guid = generate_profile_guid(config);
     let profile_name = format!("PortaQEMU: {}", config.vm.name);
     
     let commandline = match config.terminal.mode {
         TerminalMode::Ssh => {
+            if config.network.ssh_host_port == AUTO_PORT {
+                return Err(FragmentError::PortNotChosen);
+            }
             format!(
                 "ssh -p {} {}@localhost",
                 config.network.ssh_host_port,
@@ -30,11 +36,11 @@ pub fn generate_fragment(config: &ResolvedConfig) -> Result<String, FragmentErro
             )
         }
         TerminalMode::UpAttach => {
-            // For up_attach mode, we need the portaqemu.exe path
-            // This would need to be passed in or computed
+            // Closing the tab only detaches; the VM keeps running
             format!(
-                "\"{}\" up --attach",
-                "portaqemu.exe" // TODO: resolve actual path
+                "\"{}\" --root \"{}\" console --start",
+                std::env::current_exe()?.to_string_lossy(),
+                root.to_string_lossy()
             )
         }
     };
@@ -60,9 +66,9 @@ pub fn generate_fragment(config: &ResolvedConfig) -> Result<String, FragmentErro
 }
 
 /// Install terminal fragment.
-pub fn install_fragment(config: &ResolvedConfig) -> Result<PathBuf, FragmentError> {
+pub fn install_fragment(config: &ResolvedConfig, root: &Path) -> Result<PathBuf, FragmentError> {
     let fragment_file = get_fragment_file(&config.vm.name);
-    let fragment_json = generate_fragment(config)?;
+    let fragment_json = generate_fragment(config, root)?;
     
     // Ensure parent directory exists
     if let Some(parent) = fragment_file.parent() {
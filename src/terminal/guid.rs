@@ -1,4 +1,4 @@
-use uuid::{Uuid, Version};
+use uuid::Uuid;
 use crate::config::schema::ResolvedConfig;
 
 /// PortaQEMU namespace UUID (v5 namespace)
@@ -1,5 +1,8 @@
 use sha2::{Digest, Sha256};
 use std::ffi::OsString;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
 
 /// Compute SHA256 hash of QEMU argv for state tracking.
 pub fn hash_argv(argv: &[OsString]) -> String {
@@ -12,3 +15,18 @@ pub fn hash_argv(argv: &[OsString]) -> String {
     }
     format!("{:x}", hasher.finalize())
 }
+
+/// Compute SHA256 hash of a file's contents.
+pub fn hash_file(path: &Path) -> std::io::Result<String> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = file.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
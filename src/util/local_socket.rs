@@ -0,0 +1,140 @@
+use std::fs;
+use std::io;
+use std::path::Path;
+use thiserror::Error;
+
+/// Longest socket path that fits `sun_path` everywhere (104 bytes on macOS, 108 elsewhere).
+const MAX_SOCKET_PATH: usize = 100;
+
+#[derive(Error, Debug)]
+pub enum LocalSocketError {
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+    #[error("Socket path is too long ({len} bytes, at most {MAX_SOCKET_PATH}): {path} (use a shorter --root)")]
+    PathTooLong { path: String, len: usize },
+    #[error("Could not restrict {path} to the current user: {message}")]
+    Permissions { path: String, message: String },
+}
+
+/// Blocking client end of a Unix domain socket (AF_UNIX also works on Windows 10 1803+).
+#[cfg(unix)]
+pub type LocalStream = std::os::unix::net::UnixStream;
+#[cfg(windows)]
+pub type LocalStream = uds_windows::UnixStream;
+
+/// Async client end of a Unix domain socket.
+#[cfg(unix)]
+pub type AsyncLocalStream = tokio::net::UnixStream;
+#[cfg(windows)]
+pub type AsyncLocalStream = tokio::io::DuplexStream;
+
+/// Create the directory holding QEMU's sockets, accessible only to the current user.
+/// Sockets inside it are unreachable for other users whatever their own mode.
+pub fn create_private_dir(dir: &Path) -> Result<(), LocalSocketError> {
+    fs::create_dir_all(dir)?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(dir, fs::Permissions::from_mode(0o700))?;
+    }
+
+    #[cfg(windows)]
+    {
+        let permissions_error = |message: String| LocalSocketError::Permissions {
+            path: dir.to_string_lossy().to_string(),
+            message,
+        };
+        let user = std::env::var("USERNAME").map_err(|_| permissions_error("USERNAME is not set".to_string()))?;
+        // Drop inherited ACEs and grant only the current user, inherited by the sockets
+        let output = std::process::Command::new("icacls")
+            .arg(dir)
+            .arg("/inheritance:r")
+            .arg("/grant:r")
+            .arg(format!("{}:(OI)(CI)F", user))
+            .output()?;
+        if !output.status.success() {
+            return Err(permissions_error(String::from_utf8_lossy(&output.stdout).trim().to_string()));
+        }
+    }
+
+    Ok(())
+}
+
+/// Fail early on a path QEMU would reject as too long for a socket.
+pub fn check_socket_path(path: &Path) -> Result<(), LocalSocketError> {
+    let len = path.as_os_str().len();
+    if len > MAX_SOCKET_PATH {
+        return Err(LocalSocketError::PathTooLong { path: path.to_string_lossy().to_string(), len });
+    }
+    Ok(())
+}
+
+pub fn connect(path: &Path) -> io::Result<LocalStream> {
+    LocalStream::connect(path)
+}
+
+#[cfg(unix)]
+pub async fn connect_async(path: &Path) -> io::Result<AsyncLocalStream> {
+    tokio::net::UnixStream::connect(path).await
+}
+
+/// Tokio cannot drive AF_UNIX sockets on Windows, so a pair of threads relays
+/// between a blocking socket and an in-memory pipe.
+#[cfg(windows)]
+pub async fn connect_async(path: &Path) -> io::Result<AsyncLocalStream> {
+    use std::io::{Read, Write};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    let stream = connect(path)?;
+    let mut reader = stream.try_clone()?;
+    let mut writer = stream;
+    let (local, relay) = tokio::io::duplex(64 * 1024);
+    let (mut relay_rx, mut relay_tx) = tokio::io::split(relay);
+    let handle = tokio::runtime::Handle::current();
+
+    std::thread::spawn({
+        let handle = handle.clone();
+        move || {
+            let mut buf = [0u8; 8192];
+            while let Ok(n @ 1..) = reader.read(&mut buf) {
+                if handle.block_on(relay_tx.write_all(&buf[..n])).is_err() {
+                    break;
+                }
+            }
+            let _ = handle.block_on(relay_tx.shutdown());
+        }
+    });
+    std::thread::spawn(move || {
+        let mut buf = [0u8; 8192];
+        while let Ok(n @ 1..) = handle.block_on(relay_rx.read(&mut buf)) {
+            if writer.write_all(&buf[..n]).is_err() {
+                break;
+            }
+        }
+        // Also unblocks the reading thread
+        let _ = writer.shutdown(std::net::Shutdown::Both);
+    });
+
+    Ok(local)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_private_dir_and_path_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let run = dir.path().join("run");
+        create_private_dir(&run).unwrap();
+        assert_eq!(fs::metadata(&run).unwrap().permissions().mode() & 0o777, 0o700);
+
+        assert!(check_socket_path(&run.join("qmp.sock")).is_ok());
+        assert!(matches!(
+            check_socket_path(&run.join("x".repeat(MAX_SOCKET_PATH))),
+            Err(LocalSocketError::PathTooLong { .. })
+        ));
+    }
+}
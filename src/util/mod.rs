@@ -3,3 +3,6 @@ pub mod net;
 pub mod process;
 pub mod time;
 pub mod hashing;
+pub mod random;
+pub mod cancel;
+pub mod local_socket;
@@ -1,11 +1,180 @@
-use std::net::{TcpListener, TcpStream};
+use serde::de::Error as _;
+use serde::{Deserialize, Deserializer, Serialize};
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, TcpListener, TcpStream, UdpSocket};
 use std::time::{Duration, Instant};
 use thiserror::Error;
 
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Protocol {
+    #[default]
+    Tcp,
+    Udp,
+}
+
+impl std::str::FromStr for Protocol {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tcp" => Ok(Protocol::Tcp),
+            "udp" => Ok(Protocol::Udp),
+            _ => Err(format!("invalid protocol '{}' (expected tcp or udp)", s)),
+        }
+    }
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Protocol::Tcp => "tcp",
+            Protocol::Udp => "udp",
+        })
+    }
+}
+
+/// A host-side address a forward will listen on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PortBinding {
+    pub proto: Protocol,
+    pub addr: IpAddr,
+    pub port: u16,
+}
+
+impl PortBinding {
+    pub fn tcp_loopback(port: u16) -> Self {
+        Self {
+            proto: Protocol::Tcp,
+            addr: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port,
+        }
+    }
+}
+
+/// Host port written as `"auto"` (or `0`) in config; a free port is picked at startup.
+pub const AUTO_PORT: u16 = 0;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum HostPortSpec {
+    Port(u16),
+    Keyword(String),
+}
+
+/// Deserialize a host port given as a number or `"auto"` (stored as [`AUTO_PORT`]).
+pub fn deserialize_host_port<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    match HostPortSpec::deserialize(deserializer)? {
+        HostPortSpec::Port(port) => Ok(port),
+        HostPortSpec::Keyword(s) if s == "auto" => Ok(AUTO_PORT),
+        HostPortSpec::Keyword(s) => Err(D::Error::custom(format!(
+            "invalid port '{}' (expected a number or \"auto\")",
+            s
+        ))),
+    }
+}
+
+/// An inclusive port range, written as `8080` or `3000-3999` (TOML: `[22, "3000-3999"]`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(try_from = "PortRangeSpec")]
+pub struct PortRange {
+    pub start: u16,
+    pub end: u16,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PortRangeSpec {
+    Port(u16),
+    Range(String),
+}
+
+impl TryFrom<PortRangeSpec> for PortRange {
+    type Error = String;
+    
+    fn try_from(spec: PortRangeSpec) -> Result<Self, Self::Error> {
+        match spec {
+            PortRangeSpec::Port(port) => Ok(PortRange { start: port, end: port }),
+            PortRangeSpec::Range(s) => s.parse(),
+        }
+    }
+}
+
+impl std::str::FromStr for PortRange {
+    type Err = String;
+    
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid port range '{}'", s);
+        let (start, end) = s.split_once('-').unwrap_or((s, s));
+        let start: u16 = start.trim().parse().map_err(|_| invalid())?;
+        let end: u16 = end.trim().parse().map_err(|_| invalid())?;
+        if start > end {
+            return Err(invalid());
+        }
+        Ok(PortRange { start, end })
+    }
+}
+
+impl PortRange {
+    pub fn contains(&self, port: u16) -> bool {
+        (self.start..=self.end).contains(&port)
+    }
+}
+
+/// An IPv4 network in CIDR notation, e.g. `10.0.2.0/24`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Ipv4Cidr {
+    pub addr: Ipv4Addr,
+    pub prefix: u8,
+}
+
+impl Ipv4Cidr {
+    fn mask(&self) -> u32 {
+        if self.prefix == 0 { 0 } else { u32::MAX << (32 - self.prefix) }
+    }
+
+    pub fn network(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) & self.mask())
+    }
+
+    pub fn broadcast(&self) -> Ipv4Addr {
+        Ipv4Addr::from(u32::from(self.addr) | !self.mask())
+    }
+
+    pub fn contains(&self, addr: Ipv4Addr) -> bool {
+        u32::from(addr) & self.mask() == u32::from(self.network())
+    }
+
+    /// Whether `addr` is a usable host address (not the network or broadcast address).
+    pub fn is_host(&self, addr: Ipv4Addr) -> bool {
+        self.contains(addr) && addr != self.network() && addr != self.broadcast()
+    }
+}
+
+impl std::str::FromStr for Ipv4Cidr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (addr, prefix) = s.split_once('/').ok_or_else(|| format!("missing prefix length in '{}'", s))?;
+        let addr: Ipv4Addr = addr.parse().map_err(|_| format!("invalid address in '{}'", s))?;
+        let prefix: u8 = prefix.parse().map_err(|_| format!("invalid prefix length in '{}'", s))?;
+        if prefix > 30 {
+            return Err(format!("prefix length {} leaves no room for guest addresses", prefix));
+        }
+        Ok(Self { addr, prefix })
+    }
+}
+
+impl fmt::Display for Ipv4Cidr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.addr, self.prefix)
+    }
+}
+
 #[derive(Error, Debug)]
 pub enum NetError {
-    #[error("Port {0} is already in use")]
-    PortInUse(u16),
+    #[error("{0} port {1} is already in use")]
+    PortInUse(Protocol, u16),
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
     #[error("Timeout waiting for port {0} to become available")]
@@ -14,13 +183,58 @@ pub enum NetError {
 
 /// Check if a port is available (not in use).
 pub fn is_port_available(port: u16) -> Result<bool, NetError> {
-    match TcpListener::bind(("127.0.0.1", port)) {
-        Ok(_) => Ok(true),
+    is_binding_available(&PortBinding::tcp_loopback(port))
+}
+
+/// Check if a TCP or UDP address/port can be bound.
+pub fn is_binding_available(binding: &PortBinding) -> Result<bool, NetError> {
+    let result = match binding.proto {
+        Protocol::Tcp => TcpListener::bind((binding.addr, binding.port)).map(|_| ()),
+        Protocol::Udp => UdpSocket::bind((binding.addr, binding.port)).map(|_| ()),
+    };
+    match result {
+        Ok(()) => Ok(true),
         Err(e) if e.kind() == std::io::ErrorKind::AddrInUse => Ok(false),
         Err(e) => Err(NetError::Io(e)),
     }
 }
 
+/// A free port, held open until dropped so it is not handed out twice.
+pub struct ReservedPort {
+    pub port: u16,
+    binding: PortBinding,
+    _socket: ReservedSocket,
+}
+
+impl ReservedPort {
+    /// Whether this reservation is what holds `binding` taken.
+    pub fn holds(&self, binding: &PortBinding) -> bool {
+        self.binding == *binding
+    }
+}
+
+// Never read; owning the socket is what keeps the port taken
+#[allow(dead_code)]
+enum ReservedSocket {
+    Tcp(TcpListener),
+    Udp(UdpSocket),
+}
+
+/// Let the OS pick a free port for `proto` on `addr`.
+pub fn reserve_port(proto: Protocol, addr: IpAddr) -> Result<ReservedPort, NetError> {
+    let (port, socket) = match proto {
+        Protocol::Tcp => {
+            let listener = TcpListener::bind((addr, 0))?;
+            (listener.local_addr()?.port(), ReservedSocket::Tcp(listener))
+        }
+        Protocol::Udp => {
+            let socket = UdpSocket::bind((addr, 0))?;
+            (socket.local_addr()?.port(), ReservedSocket::Udp(socket))
+        }
+    };
+    Ok(ReservedPort { port, binding: PortBinding { proto, addr, port }, _socket: socket })
+}
+
 /// Wait for a TCP port to become available (connectable).
 pub fn wait_for_port(
     host: &str,
@@ -43,17 +257,66 @@ pub fn wait_for_port(
     }
 }
 
-/// Check if multiple ports are available.
-pub fn check_ports_available(ports: &[u16]) -> Result<Vec<u16>, NetError> {
+/// Check if multiple TCP/UDP bindings are available.
+pub fn check_ports_available(bindings: &[PortBinding]) -> Result<Vec<PortBinding>, NetError> {
     let mut in_use = Vec::new();
-    for &port in ports {
-        if !is_port_available(port)? {
-            in_use.push(port);
+    for binding in bindings {
+        if !is_binding_available(binding)? {
+            in_use.push(*binding);
         }
     }
     if in_use.is_empty() {
         Ok(vec![])
     } else {
-        Err(NetError::PortInUse(in_use[0]))
+        Err(NetError::PortInUse(in_use[0].proto, in_use[0].port))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cidr_parse_and_contains() {
+        let net: Ipv4Cidr = "10.0.2.0/24".parse().unwrap();
+        assert!(net.is_host("10.0.2.2".parse().unwrap()));
+        assert!(!net.is_host("10.0.2.255".parse().unwrap()));
+        assert!(!net.contains("10.0.3.1".parse().unwrap()));
+        assert!("10.0.2.0".parse::<Ipv4Cidr>().is_err());
+        assert!("10.0.2.0/31".parse::<Ipv4Cidr>().is_err());
+    }
+
+    #[test]
+    fn test_port_range_deserialize() {
+        #[derive(Deserialize)]
+        struct Ranges {
+            ports: Vec<PortRange>,
+        }
+        let ranges: Ranges = toml::from_str(r#"ports = [22, "3000-3999"]"#).unwrap();
+        assert!(ranges.ports[0].contains(22));
+        assert!(ranges.ports[1].contains(3500));
+        assert!(!ranges.ports[1].contains(4000));
+        assert!("10-5".parse::<PortRange>().is_err());
+    }
+
+    #[test]
+    fn test_host_port_deserialize() {
+        #[derive(Deserialize)]
+        struct Port {
+            #[serde(deserialize_with = "deserialize_host_port")]
+            port: u16,
+        }
+        assert_eq!(toml::from_str::<Port>("port = 2222").unwrap().port, 2222);
+        assert_eq!(toml::from_str::<Port>(r#"port = "auto""#).unwrap().port, AUTO_PORT);
+        assert!(toml::from_str::<Port>(r#"port = "any""#).is_err());
+    }
+
+    #[test]
+    fn test_reserved_port_stays_taken() {
+        let reserved = reserve_port(Protocol::Tcp, Ipv4Addr::LOCALHOST.into()).unwrap();
+        let binding = PortBinding::tcp_loopback(reserved.port);
+        assert!(reserved.holds(&binding));
+        assert!(!is_binding_available(&binding).unwrap());
+        assert_ne!(reserve_port(Protocol::Tcp, Ipv4Addr::LOCALHOST.into()).unwrap().port, reserved.port);
     }
 }
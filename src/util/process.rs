@@ -35,7 +35,7 @@ pub fn is_process_running(pid: u32) -> bool {
     {
         // On Unix, send signal 0 to check if process exists
         let result = Command::new("kill")
-            .args(&["-0", &pid.to_string()])
+            .args(["-0", &pid.to_string()])
             .output();
         result.is_ok() && result.unwrap().status.success()
     }
@@ -67,9 +67,27 @@ pub fn kill_process(pid: u32) -> Result<(), ProcessError> {
     {
         Command::new("kill")
             .arg("-9")
-            .arg(&pid.to_string())
+            .arg(pid.to_string())
             .output()
             .map_err(ProcessError::Io)?;
         Ok(())
     }
 }
+
+/// Run a background child outside the caller's console and process group, so
+/// closing the terminal that started it (e.g. `portaqemu console --start`) leaves it running.
+pub fn detach(cmd: &mut Command) {
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        const CREATE_NEW_PROCESS_GROUP: u32 = 0x0000_0200;
+        cmd.creation_flags(DETACHED_PROCESS | CREATE_NEW_PROCESS_GROUP);
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        cmd.process_group(0);
+    }
+}
@@ -0,0 +1,55 @@
+use uuid::Uuid;
+
+/// Fill a buffer with bytes from the operating system's CSPRNG.
+pub fn fill_random(buf: &mut [u8]) -> std::io::Result<()> {
+    #[cfg(windows)]
+    {
+        use windows::Win32::Security::Cryptography::{BCryptGenRandom, BCRYPT_USE_SYSTEM_PREFERRED_RNG};
+
+        unsafe {
+            BCryptGenRandom(None, buf, BCRYPT_USE_SYSTEM_PREFERRED_RNG)
+                .ok()
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+        }
+    }
+
+    #[cfg(not(windows))]
+    {
+        use std::io::Read;
+        std::fs::File::open("/dev/urandom")?.read_exact(buf)
+    }
+}
+
+/// Generate `N` random bytes.
+pub fn random_bytes<const N: usize>() -> std::io::Result<[u8; N]> {
+    let mut buf = [0u8; N];
+    fill_random(&mut buf)?;
+    Ok(buf)
+}
+
+/// Generate a random (v4) UUID.
+pub fn random_uuid() -> Uuid {
+    let bytes = random_bytes::<16>().expect("OS random number generator unavailable");
+    uuid::Builder::from_random_bytes(bytes).into_uuid()
+}
+
+/// Generate a random alphanumeric string (e.g. for generated passwords).
+pub fn random_alphanumeric(len: usize) -> std::io::Result<String> {
+    const CHARSET: &[u8] = b"ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz23456789";
+    // Bytes past the last whole multiple of the charset size would favour its first
+    // characters, so they are drawn again
+    const LIMIT: usize = 256 - 256 % CHARSET.len();
+    let mut out = String::with_capacity(len);
+    let mut buf = [0u8; 64];
+    while out.len() < len {
+        fill_random(&mut buf)?;
+        let wanted = len - out.len();
+        out.extend(
+            buf.iter()
+                .filter(|&&b| (b as usize) < LIMIT)
+                .take(wanted)
+                .map(|&b| CHARSET[b as usize % CHARSET.len()] as char),
+        );
+    }
+    Ok(out)
+}
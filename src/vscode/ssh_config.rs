@@ -1,8 +1,9 @@
 use crate::config::schema::ResolvedConfig;
 use crate::config::paths::get_default_ssh_config;
+use crate::ssh::host_key_alias;
 use crate::util::fs_atomic;
+use crate::util::net::AUTO_PORT;
 use std::fs;
-use std::path::Path;
 use thiserror::Error;
 
 const BEGIN_MARKER: &str = "# PortaQEMU BEGIN";
@@ -12,14 +13,19 @@ const END_MARKER: &str = "# PortaQEMU END";
 pub enum SshConfigError {
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
+    #[error("Atomic write error: {0}")]
+    AtomicWrite(#[from] crate::util::fs_atomic::AtomicWriteError),
     #[error("Config block not found")]
     NotFound,
+    #[error("SSH port is \"auto\" and no port has been chosen yet (start the VM with: portaqemu up)")]
+    PortNotChosen,
 }
 
 /// Generate SSH config block for VS Code.
 pub fn generate_ssh_config_block(config: &ResolvedConfig) -> String {
     let host_name = format!("PortaQEMU-{}", config.vm.name);
     let identity_file = config.vscode.identity_file.to_string_lossy().replace('\\', "/");
+    let known_hosts_file = config.vscode.known_hosts_file.to_string_lossy().replace('\\', "/");
     
     format!(
         "Host {}\n\
@@ -27,22 +33,27 @@ pub fn generate_ssh_config_block(config: &ResolvedConfig) -> String {
          \tPort {}\n\
          \tUser {}\n\
          \tIdentityFile {}\n\
-         \tStrictHostKeyChecking no\n\
-         \tUserKnownHostsFile /dev/null\n",
+         \tStrictHostKeyChecking yes\n\
+         \tUserKnownHostsFile {}\n\
+         \tHostKeyAlias {}\n",
         host_name,
         config.network.ssh_host_port,
         config.vscode.ssh_user,
-        identity_file
+        identity_file,
+        known_hosts_file,
+        host_key_alias(&config.vm.name)
     )
 }
 
 /// Print SSH config block.
-pub fn print_ssh_config(config: &ResolvedConfig) -> String {
-    generate_ssh_config_block(config)
+pub fn print_ssh_config(config: &ResolvedConfig) -> Result<String, SshConfigError> {
+    check_port_chosen(config)?;
+    Ok(generate_ssh_config_block(config))
 }
 
 /// Install SSH config block into default SSH config.
 pub fn install_ssh_config(config: &ResolvedConfig) -> Result<(), SshConfigError> {
+    check_port_chosen(config)?;
     let config_path = get_default_ssh_config();
     
     // Read existing config
@@ -74,8 +85,26 @@ pub fn install_ssh_config(config: &ResolvedConfig) -> Result<(), SshConfigError>
     Ok(())
 }
 
+/// An `"auto"` SSH port is only known while the VM runs; `Port 0` would be useless.
+fn check_port_chosen(config: &ResolvedConfig) -> Result<(), SshConfigError> {
+    if config.network.ssh_host_port == AUTO_PORT {
+        return Err(SshConfigError::PortNotChosen);
+    }
+    Ok(())
+}
+
+/// Whether the default SSH config contains a PortaQEMU block.
+pub fn ssh_config_installed() -> Result<bool, SshConfigError> {
+    let config_path = get_default_ssh_config();
+    if !config_path.exists() {
+        return Ok(false);
+    }
+    let content = fs::read_to_string(&config_path)?;
+    Ok(content.lines().any(|line| line.trim() == BEGIN_MARKER))
+}
+
 /// Remove SSH config block from default SSH config.
-pub fn remove_ssh_config(config: &ResolvedConfig) -> Result<(), SshConfigError> {
+pub fn remove_ssh_config(_config: &ResolvedConfig) -> Result<(), SshConfigError> {
     let config_path = get_default_ssh_config();
     
     if !config_path.exists() {
@@ -91,7 +120,7 @@ pub fn remove_ssh_config(config: &ResolvedConfig) -> Result<(), SshConfigError>
 }
 
 fn remove_block_from_content(content: &str) -> String {
-    let mut lines = content.lines().collect::<Vec<_>>();
+    let lines = content.lines().collect::<Vec<_>>();
     let mut result = Vec::new();
     let mut in_block = false;
     